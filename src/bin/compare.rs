@@ -0,0 +1,111 @@
+//! The ``compare`` subcommand: reports latency changes between two results files.
+
+use std::io::{self, Write};
+use std::process;
+
+use getopts::Options;
+
+use httplatency::compare::{self, Change, Comparison, SignificanceTest, Thresholds};
+
+use super::{parse_opt, print_summary, write_json};
+
+/// Runs the compare subcommand given the full command line, returning the exit code
+///
+/// The exit code is 0 if nothing regressed, 1 if anything regressed or newly failed, and 2 if the
+/// comparison couldn't be made.
+pub fn main(args: &[String]) -> i32 {
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optopt("", "max-increase-ms", "only count a url as regressed if it got more than MS milliseconds slower", "MS");
+    opts.optopt("", "max-increase-pct", "only count a url as regressed if it got more than PCT percent slower", "PCT");
+    opts.optflag("", "require-significance", "only count a url as regressed if the change is statistically significant");
+    opts.optopt("", "alpha", "treat changes with a p-value below ALPHA as significant (default 0.05)", "ALPHA");
+    opts.optopt("", "bootstrap", "use N resamples for confidence intervals (default 1000)", "N");
+    opts.optopt("o", "output", "also write the comparison as JSON to NAME, or to stdout if NAME is '-'", "NAME");
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args[2..]) {
+        Ok(m) => { m }
+        Err(f) => { panic!(f.to_string()) }
+    };
+    if matches.opt_present("h") {
+        print_usage(&program, opts);
+        process::exit(0);
+    }
+    if matches.free.len() != 2 {
+        print_usage(&program, opts);
+        return 2;
+    }
+    let thresholds = Thresholds {
+        max_increase_ms: parse_opt(&matches, "max-increase-ms"),
+        max_increase_pct: parse_opt(&matches, "max-increase-pct"),
+        require_significance: matches.opt_present("require-significance"),
+    };
+    let mut test = SignificanceTest::default();
+    test.alpha = parse_opt(&matches, "alpha").unwrap_or(test.alpha);
+    test.iterations = parse_opt(&matches, "bootstrap").unwrap_or(test.iterations);
+    if !(test.alpha > 0.0 && test.alpha < 1.0) {
+        error!("--alpha must be between 0 and 1");
+        return 2;
+    }
+    if test.iterations == 0 {
+        error!("--bootstrap must be greater than zero");
+        return 2;
+    }
+
+    let (baseline, candidate) = match (compare::load_results(&matches.free[0]),
+                                       compare::load_results(&matches.free[1])) {
+        (Ok(b), Ok(c)) => (b, c),
+        (Err(err), _) | (_, Err(err)) => {
+            error!("{}", err);
+            return 2;
+        }
+    };
+    let comparison = compare::compare(&baseline, &candidate, &thresholds, &test);
+    if !comparison.same_input {
+        warn!("The two runs read different inputs; urls missing from one may not have been requested");
+    }
+    let output = matches.opt_str("o");
+    print_summary(&output, |out| print_comparison(out, &comparison));
+
+    if output.is_some() {
+        if let Err(err) = write_json(&comparison, output) {
+            error!("Error writing to file! {}", err);
+            return 2;
+        }
+    }
+    if comparison.has_regressions() { 1 } else { 0 }
+}
+
+/// Print the compare subcommand's instructions
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} compare BASELINE CANDIDATE [options]", program);
+    print!("{}", opts.usage(&brief));
+}
+
+/// Print a line per url showing how it changed, followed by totals, to ``out``
+fn print_comparison(out: &mut Write, comparison: &Comparison) -> io::Result<()> {
+    for c in comparison.urls.iter() {
+        let label = match c.change {
+            Change::Unchanged => "ok",
+            Change::Regressed => "REGRESSED",
+            Change::NewlyFailing => "FAILING",
+            Change::Recovered => "recovered",
+            Change::StillFailing => "still failing",
+        };
+        try!(match (c.baseline_ms, c.candidate_ms, c.delta_ms, c.delta_pct) {
+            (Some(b), Some(a), Some(d), Some(p)) =>
+                writeln!(out, "{:<13} {} {} {}ms -> {}ms ({:+}ms, {:+.1}%)", label, c.method, c.url, b, a, d, p),
+            _ => writeln!(out, "{:<13} {} {}", label, c.method, c.url),
+        });
+        if let Some(ref sig) = c.significance {
+            try!(writeln!(out, "{:<13} {} vs {} samples, p={:.4} ({}), median change {:.0}ms to {:.0}ms, p95 change {:.0}ms to {:.0}ms",
+                          "", sig.baseline_samples, sig.candidate_samples, sig.mann_whitney.p_value,
+                          if sig.significant { "significant" } else { "not significant" },
+                          sig.median_delta_ci.low, sig.median_delta_ci.high, sig.p95_delta_ci.low, sig.p95_delta_ci.high));
+        }
+    }
+    writeln!(out, "{} urls compared: {} regressed, {} newly failing, {} recovered",
+             comparison.urls.len(), comparison.regressions, comparison.newly_failing, comparison.recovered)
+}
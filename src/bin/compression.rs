@@ -0,0 +1,131 @@
+//! The ``compression`` subcommand: requests a url with each encoding to compare what they save.
+
+use std::io::{self, Write};
+use std::process;
+
+use getopts::Options;
+
+use httplatency;
+use httplatency::compression::{self, Encoding, Savings, ENCODINGS};
+use httplatency::envelope::Run;
+use httplatency::retry::RetryPolicy;
+use httplatency::target::{Measure, Target};
+
+use super::{parse_opt, print_summary, write_json};
+
+/// How many times each encoding is requested unless ``--samples`` is given
+const DEFAULT_SAMPLES: usize = 5;
+
+/// Runs the compression subcommand given the full command line, returning the exit code
+pub fn main(args: &[String]) -> i32 {
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optopt("e", "encodings", "compare the comma separated encodings LIST (default identity,gzip,deflate,br,zstd)", "LIST");
+    opts.optopt("n", "samples", &format!("request the url N times with each encoding and compare the medians (default {})", DEFAULT_SAMPLES), "N");
+    opts.optopt("t", "timeout", "give up on each request after MS milliseconds", "MS");
+    opts.optopt("o", "output", "set the output filename, or '-' for stdout. 'output.json' will be used if none is provided", "NAME");
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args[2..]) {
+        Ok(m) => { m }
+        Err(f) => { panic!(f.to_string()) }
+    };
+    if matches.opt_present("h") {
+        print_usage(&program, opts);
+        process::exit(0);
+    }
+    let url = if !matches.free.is_empty() {
+        matches.free[0].clone()
+    } else {
+        print_usage(&program, opts);
+        return 1;
+    };
+    let url = match httplatency::canonicalize_http_address(&url) {
+        Some(u) => u,
+        None => {
+            error!("Not a valid http(s) url: {}", url);
+            return 1;
+        }
+    };
+    let mut encodings = match matches.opt_str("encodings").map(|l| compression::parse_list(&l)) {
+        Some(Ok(encodings)) => encodings,
+        Some(Err(err)) => {
+            error!("Invalid --encodings: {}", err);
+            return 1;
+        }
+        None => ENCODINGS.to_vec(),
+    };
+    // Savings are measured against the uncompressed response, so it's always requested first
+    encodings.retain(|e| *e != Encoding::Identity);
+    encodings.insert(0, Encoding::Identity);
+    let timeout_ms = parse_opt(&matches, "timeout");
+    let samples = parse_opt(&matches, "samples").unwrap_or(DEFAULT_SAMPLES);
+    if samples == 0 {
+        error!("--samples must be greater than zero");
+        return 1;
+    }
+
+    let run = Run::start(args[1..].to_vec(), None);
+    let targets: Vec<Target> = encodings.iter().map(|encoding| {
+        let mut target = Target::get(url.clone());
+        target.headers.push(("Accept-Encoding".to_string(), encoding.name().to_string()));
+        target.measure = Some(Measure::FullBody);
+        target.timeout_ms = timeout_ms;
+        target
+    }).collect();
+    // Each round requests every encoding once, so a change in the server or network over the run
+    // affects every encoding alike
+    let mut results: Vec<(Encoding, Vec<_>)> = encodings.iter().map(|&e| (e, Vec::new())).collect();
+    for _ in 0..samples {
+        for (target, result) in targets.iter().zip(results.iter_mut()) {
+            result.1.push(httplatency::record_target(target, &RetryPolicy::none()));
+        }
+    }
+    let report = compression::savings(results);
+    let output = matches.opt_str("o");
+    print_summary(&output, |out| print_report(out, &report));
+    match write_json(&run.finish(report), output) {
+        Ok(_) => 0,
+        Err(err) => {
+            error!("Error writing to file! {}", err);
+            1
+        }
+    }
+}
+
+/// Print the compression subcommand's instructions
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} compression URL [options]", program);
+    print!("{}", opts.usage(&brief));
+}
+
+/// Print a line for each encoding with the median size and time of its responses and what it saved
+fn print_report(out: &mut Write, report: &[Savings]) -> io::Result<()> {
+    try!(writeln!(out, "{:<9} {:>7} {:<17} {:>10} {:>10} {:>9} {:>8} {:>11}",
+                  "Accept", "Samples", "Content-Encoding", "Bytes", "Decoded", "Saved", "Latency", "Decoding"));
+    for savings in report {
+        if savings.samples == 0 {
+            try!(writeln!(out, "{:<9} {:>7} {}", savings.encoding.name(), 0, savings.error.clone().unwrap_or_default()));
+            continue;
+        }
+        try!(writeln!(out, "{:<9} {:>7} {:<17} {:>10} {:>10} {:>9} {:>8} {:>11}",
+                      savings.encoding.name(),
+                      savings.samples,
+                      savings.content_encoding.clone().unwrap_or("(none)".to_string()),
+                      show(savings.bytes),
+                      show(savings.decompressed_bytes),
+                      savings.saved_pct.map(|p| format!("{:.1}%", p)).unwrap_or("-".to_string()),
+                      show(savings.latency_ms.map(|ms| format!("{}ms", ms))),
+                      show(savings.decompress_ms.map(|ms| format!("{:.2}ms", ms)))));
+        if let Some(ref err) = savings.error {
+            try!(writeln!(out, "{:<9} {:>7} failed, the last with: {}", "", savings.failures, err));
+        }
+    }
+    Ok(())
+}
+
+/// ``value`` as text, or ``-`` if there is none
+fn show<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or("-".to_string())
+}
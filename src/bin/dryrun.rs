@@ -0,0 +1,241 @@
+//! The ``--dry-run`` report: checks every line of the input without requesting any of its urls.
+
+use std::cell::Cell;
+
+use httplatency;
+use httplatency::config;
+use httplatency::curl;
+use httplatency::har;
+use httplatency::normalize::NormalizePolicy;
+use httplatency::openapi;
+use httplatency::postman;
+use httplatency::sitemap;
+use httplatency::target::{SchemePolicy, Target};
+use httplatency::urllist::{self, Entry};
+
+use super::Args;
+
+/// Prints what each line of the input would be requested as, after applying the ``--scheme``
+/// policy and normalizing as ``--normalize`` asks, returning the exit code
+///
+/// The exit code is 1 if any line was rejected and 0 otherwise. Duplicates (targets which
+/// ``--dedupe`` would merge) are reported but aren't counted as rejections. Remote sitemaps are
+/// still downloaded, and say so, so that the urls they list can be checked, but with ``--crawl``
+/// no pages are crawled.
+pub fn main(args: &Args, contents: &[u8]) -> i32 {
+    let code = check_input(args, contents);
+    if args.crawl.is_some() {
+        println!("With --crawl, these urls are the seeds. Pages they link to are only found when crawling.");
+    }
+    code
+}
+
+/// Checks the input as ``main`` does, dispatching on its kind
+fn check_input(args: &Args, contents: &[u8]) -> i32 {
+    if postman::is_collection_file(&args.input) {
+        return check_postman(args, contents);
+    }
+    if openapi::is_openapi_file(&args.input, contents) {
+        return check_openapi(args, contents);
+    }
+    if sitemap::is_sitemap(&args.input) {
+        return check_sitemap(args, contents);
+    }
+    if har::is_har_file(&args.input) {
+        return check_har(args, contents);
+    }
+    if curl::is_curl_file(&args.input) {
+        return check_curl(args, contents);
+    }
+    if config::is_config_file(&args.input) {
+        return check_config(contents, args.scheme, &args.normalize);
+    }
+    let entries: Vec<Entry> = urllist::entries(contents).into_iter().map(revalidate).collect();
+    report(&entries, "line", 0, args.scheme, &args.normalize)
+}
+
+/// Prints what each entry would be requested as, with entries numbered as ``unit`` (e.g. line 3),
+/// and a summary counting ``failed`` problems found before the entries were read as rejections
+fn report(entries: &[Entry], unit: &str, failed: usize, scheme: SchemePolicy, policy: &NormalizePolicy) -> i32 {
+    let mut seen: Vec<(Target, usize)> = Vec::new();
+    let mut duplicates = 0;
+    let mut rejected = failed;
+    for entry in entries.iter() {
+        match entry.target {
+            Ok(ref target) => {
+                for target in prepare(target, scheme, policy) {
+                    let first = seen.iter().find(|s| s.0 == target).map(|s| s.1);
+                    match first {
+                        Some(first) => {
+                            duplicates += 1;
+                            println!("{} {}: {} -> {} (duplicate of {} {})",
+                                     unit, entry.line, entry.text, describe(&target), unit, first);
+                        }
+                        None => {
+                            println!("{} {}: {} -> {}", unit, entry.line, entry.text, describe(&target));
+                            seen.push((target, entry.line));
+                        }
+                    }
+                }
+            }
+            Err(ref reason) => {
+                rejected += 1;
+                println!("{} {}: REJECTED {} ({})", unit, entry.line, entry.text, reason);
+            }
+        }
+    }
+    println!("{} urls checked: {} to request, {} duplicates, {} rejected",
+             entries.len() + failed, seen.len(), duplicates, rejected);
+    if rejected > 0 { 1 } else { 0 }
+}
+
+/// Prints what each request recorded in a HAR file would be replayed as
+fn check_har(args: &Args, contents: &[u8]) -> i32 {
+    let entries = String::from_utf8(contents.to_vec())
+        .map_err(|e| e.to_string())
+        .and_then(|text| har::entries(&text));
+    match entries {
+        Ok(entries) => {
+            let entries: Vec<Entry> = entries.into_iter().map(revalidate).collect();
+            report(&entries, "entry", 0, args.scheme, &args.normalize)
+        }
+        Err(err) => {
+            println!("REJECTED {} ({})", args.input, err);
+            1
+        }
+    }
+}
+
+/// Prints what each request of a Postman collection would be requested as
+fn check_postman(args: &Args, contents: &[u8]) -> i32 {
+    let entries = String::from_utf8(contents.to_vec())
+        .map_err(|e| e.to_string())
+        .and_then(|text| postman::entries(&text, &args.postman_env));
+    match entries {
+        Ok(entries) => {
+            let entries: Vec<Entry> = entries.into_iter().map(revalidate).collect();
+            report(&entries, "request", 0, args.scheme, &args.normalize)
+        }
+        Err(err) => {
+            println!("REJECTED {} ({})", args.input, err);
+            1
+        }
+    }
+}
+
+/// Prints what each chosen operation of an OpenAPI document would be requested as
+fn check_openapi(args: &Args, contents: &[u8]) -> i32 {
+    let entries = String::from_utf8(contents.to_vec())
+        .map_err(|e| e.to_string())
+        .and_then(|text| openapi::entries(&text, &args.openapi));
+    match entries {
+        Ok(entries) => {
+            let entries: Vec<Entry> = entries.into_iter().map(revalidate).collect();
+            report(&entries, "operation", 0, args.scheme, &args.normalize)
+        }
+        Err(err) => {
+            println!("REJECTED {} ({})", args.input, err);
+            1
+        }
+    }
+}
+
+/// Prints what each command in a file of curl commands would be requested as
+fn check_curl(args: &Args, contents: &[u8]) -> i32 {
+    match String::from_utf8(contents.to_vec()) {
+        Ok(text) => {
+            let entries: Vec<Entry> = curl::entries(&text).into_iter().map(revalidate).collect();
+            report(&entries, "line", 0, args.scheme, &args.normalize)
+        }
+        Err(err) => {
+            println!("REJECTED {} ({})", args.input, err);
+            1
+        }
+    }
+}
+
+/// Prints the urls of a sitemap which ``--sitemap-sample`` chooses, along with any sitemaps listed
+/// by an index which couldn't be read
+fn check_sitemap(args: &Args, contents: &[u8]) -> i32 {
+    // The input itself was downloaded before the dry run started
+    let downloaded = Cell::new(if sitemap::is_remote(&args.input) { 1 } else { 0 });
+    let read_location = |location: &str| {
+        if sitemap::is_remote(location) {
+            downloaded.set(downloaded.get() + 1);
+        }
+        super::read_location(location)
+    };
+    let loaded = sitemap::load(&args.input, contents, read_location);
+    if downloaded.get() > 0 {
+        println!("Downloaded {} remote sitemap(s) to list their urls; none of the urls were requested", downloaded.get());
+    }
+    let (urls, warnings) = match loaded {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            println!("REJECTED {} ({})", args.input, err);
+            return 1;
+        }
+    };
+    for warning in warnings.iter() {
+        println!("REJECTED {}", warning);
+    }
+    println!("{} urls in sitemap", urls.len());
+    let entries: Vec<Entry> = args.sitemap_sample.apply(urls.into_iter().enumerate().collect())
+        .into_iter()
+        .map(|(i, url)| revalidate(Entry { line: i + 1, target: sitemap::target(&url), text: url }))
+        .collect();
+    report(&entries, "url", warnings.len(), args.scheme, &args.normalize)
+}
+
+/// The targets to request for ``target``, as ``--scheme`` and ``--normalize`` would make them
+fn prepare(target: &Target, scheme: SchemePolicy, policy: &NormalizePolicy) -> Vec<Target> {
+    scheme.apply(target.clone()).into_iter().map(|mut t| {
+        t.url = policy.apply(&t.url);
+        t.fallback_url = t.fallback_url.map(|f| policy.apply(&f));
+        t
+    }).collect()
+}
+
+/// Describes how a target will be requested, e.g. ``GET http://www.example.com/``
+fn describe(target: &Target) -> String {
+    match target.fallback_url {
+        Some(ref fallback) => format!("{} {} (falling back to {})", target.method, target.url, fallback),
+        None => format!("{} {}", target.method, target.url),
+    }
+}
+
+/// Rejects an entry whose canonical url still isn't accepted by ``valid_http_url``
+fn revalidate(entry: Entry) -> Entry {
+    let target = match entry.target {
+        Ok(target) => match httplatency::valid_http_url(target.url.clone()) {
+            Some(_) => Ok(target),
+            None => Err(format!("canonicalized to '{}', which is not a valid http(s) url", target.url)),
+        },
+        Err(err) => Err(err),
+    };
+    Entry { line: entry.line, text: entry.text, target: target }
+}
+
+/// Prints the targets of a config file, or every problem with it
+fn check_config(contents: &[u8], scheme: SchemePolicy, policy: &NormalizePolicy) -> i32 {
+    let targets: Result<Vec<(usize, Target)>, String> = String::from_utf8(contents.to_vec())
+        .map_err(|e| e.to_string())
+        .and_then(|text| config::parse_numbered(&text));
+    match targets {
+        Ok(targets) => {
+            for &(n, ref target) in targets.iter() {
+                for t in prepare(target, scheme, policy) {
+                    println!("target {}: {}", n, describe(&t));
+                }
+            }
+            println!("{} targets checked", targets.len());
+            0
+        }
+        Err(errors) => {
+            for error in errors.lines() {
+                println!("REJECTED {}", error);
+            }
+            1
+        }
+    }
+}
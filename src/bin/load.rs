@@ -0,0 +1,101 @@
+//! The ``load`` subcommand: sends requests to a single url at a fixed rate.
+
+use std::io::{self, Write};
+use std::process;
+
+use getopts::Options;
+
+use httplatency;
+use httplatency::envelope::Run;
+use httplatency::load::{LoadConfig, LoadReport};
+
+use super::{parse_opt, print_summary, write_json};
+
+/// Runs the load subcommand given the full command line, returning the exit code
+pub fn main(args: &[String]) -> i32 {
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optopt("r", "rate", "send RPS requests per second (required)", "RPS");
+    opts.optopt("d", "duration", "keep sending requests for SECS seconds (default 10)", "SECS");
+    opts.optopt("", "max-in-flight", "allow at most N requests outstanding at once (default 100)", "N");
+    opts.optopt("o", "output", "set the output filename, or '-' for stdout. 'output.json' will be used if none is provided", "NAME");
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args[2..]) {
+        Ok(m) => { m }
+        Err(f) => { panic!(f.to_string()) }
+    };
+    if matches.opt_present("h") {
+        print_usage(&program, opts);
+        process::exit(0);
+    }
+    let url = if !matches.free.is_empty() {
+        matches.free[0].clone()
+    } else {
+        print_usage(&program, opts);
+        return 1;
+    };
+    let url = match httplatency::canonicalize_http_address(&url) {
+        Some(u) => u,
+        None => {
+            error!("Not a valid http(s) url: {}", url);
+            return 1;
+        }
+    };
+    let rate = match parse_opt::<f64>(&matches, "rate") {
+        Some(r) => r,
+        None => {
+            error!("A request rate must be given with --rate");
+            return 1;
+        }
+    };
+    let duration = parse_opt::<f64>(&matches, "duration").unwrap_or(10.0);
+    if !(duration >= 0.0) || !duration.is_finite() {
+        error!("--duration must be a number of seconds (got {})", duration);
+        return 1;
+    }
+    let config = LoadConfig {
+        rate: rate,
+        duration_ms: (duration * 1000.0) as u64,
+        max_in_flight: parse_opt(&matches, "max-in-flight").unwrap_or(100),
+    };
+    if let Err(err) = config.validate() {
+        error!("{}", err);
+        return 1;
+    }
+
+    let run = Run::start(args[1..].to_vec(), None);
+    let report = httplatency::load::run(&url, &config);
+    let output = matches.opt_str("o");
+    print_summary(&output, |out| print_report(out, &report));
+    match write_json(&run.finish(report), output) {
+        Ok(_) => 0,
+        Err(err) => {
+            error!("Error writing to file! {}", err);
+            1
+        }
+    }
+}
+
+/// Print the load subcommand's instructions
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} load URL --rate RPS [options]", program);
+    print!("{}", opts.usage(&brief));
+}
+
+/// Print a human readable summary of a load run to ``out``
+fn print_report(out: &mut Write, report: &LoadReport) -> io::Result<()> {
+    try!(writeln!(out, "Requests:   {} ({} responses, {} errors, {:.1}% error rate)",
+                  report.requests, report.responses, report.errors, report.error_rate * 100.0));
+    try!(writeln!(out, "Throughput: {:.1} responses/s (target {} requests/s)", report.throughput_rps, report.target_rps));
+    try!(match report.latency {
+        Some(ref l) => writeln!(out, "Latency:    min {}ms, p50 {}ms, p90 {}ms, p99 {}ms, p99.9 {}ms, max {}ms, mean {:.1}ms",
+                                l.min, l.p50, l.p90, l.p99, l.p999, l.max, l.mean),
+        None => writeln!(out, "Latency:    no responses received"),
+    });
+    if report.max_send_lag_ms > 100 {
+        warn!("Requests were sent up to {}ms behind schedule; latencies include this delay", report.max_send_lag_ms);
+    }
+    Ok(())
+}
@@ -1,9 +1,10 @@
 /// Simple logging facilities using the log crate.
 
 extern crate log;
+use std::io::{self, Write};
 use log::{LogRecord, LogLevel, LogMetadata, LogLevelFilter, SetLoggerError};
 
-/// Outputs all log messages to stdout.
+/// Outputs all log messages to stderr, keeping stdout free for results.
 struct ConsoleLogger;
 
 impl log::Log for ConsoleLogger {
@@ -13,7 +14,8 @@ impl log::Log for ConsoleLogger {
 
     fn log(&self, record: &LogRecord) {
         if self.enabled(record.metadata()) {
-            println!("{} - {}", record.level(), record.args());
+            // Nowhere left to report a failure to write to stderr
+            let _ = writeln!(io::stderr(), "{} - {}", record.level(), record.args());
         }
     }
 }
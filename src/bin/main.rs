@@ -5,22 +5,75 @@ extern crate log;
 extern crate getopts;
 
 use std::io;
-use std::io::BufRead;
+use std::io::Read;
 use std::io::Write;
 use std::fs::File;
 use std::env;
 use std::process;
 use std::error::Error;
+use std::str::FromStr;
 
-use getopts::Options;
+use getopts::{Matches, Options};
+use rustc_serialize::Encodable;
 use rustc_serialize::json;
 
 use httplatency::Latency;
+use httplatency::config;
+use httplatency::crawl::{self, CrawlPolicy};
+use httplatency::curl;
+use httplatency::envelope::Run;
+use httplatency::har;
+use httplatency::normalize::{self, NormalizePolicy};
+use httplatency::openapi::{self, Parameters, Selection};
+use httplatency::postman::{self, Variables};
+use httplatency::ratelimit::{HostLimiter, HostPolicy};
+use httplatency::retry::{ErrorKind, RetryPolicy};
+use httplatency::scheduler;
+use httplatency::sitemap::{self, Sampling};
+use httplatency::target::{Measure, SchemePolicy, Target};
+use httplatency::throughput::SamplingPolicy;
+use httplatency::upload::{self, Payload, UploadPolicy};
+use httplatency::urllist;
 
+mod compare;
+mod compression;
+mod dryrun;
+mod load;
 mod logger;
+mod page;
 
 const DEFAULT_OUTPUT: &'static str = "output.json";
 
+/// The output file used with ``--har`` if none is given
+const DEFAULT_HAR_OUTPUT: &'static str = "output.har";
+
+/// The file name which means stdin when given as the input, or stdout when given as the output
+const STDIO: &'static str = "-";
+
+/// The settings chosen on the command line
+struct Args {
+    command_line: Vec<String>,
+    input: String,
+    output: Option<String>,
+    har: bool,
+    retry: RetryPolicy,
+    concurrency: usize,
+    samples: usize,
+    hosts: HostPolicy,
+    scheme: SchemePolicy,
+    normalize: NormalizePolicy,
+    dedupe: bool,
+    sitemap_sample: Sampling,
+    openapi: Selection,
+    postman_env: Variables,
+    crawl: Option<CrawlPolicy>,
+    measure: Option<Measure>,
+    throughput: Option<SamplingPolicy>,
+    accept_encoding: Option<String>,
+    upload: Option<UploadPolicy>,
+    dry_run: bool,
+}
+
 /// Start or the program.
 ///
 /// Co-ordinates the command line arguments and library functions
@@ -29,12 +82,29 @@ fn main() {
         Err(err) => panic!(format!("Logging setup error : {}", err.description())),
         _ => (),
     }
-    println!("HTTP(S) Latency tool");
+    info!("HTTP(S) Latency tool");
+
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 && args[1] == "load" {
+        process::exit(load::main(&args));
+    }
+    if args.len() > 1 && args[1] == "compare" {
+        process::exit(compare::main(&args));
+    }
+    if args.len() > 1 && args[1] == "compression" {
+        process::exit(compression::main(&args));
+    }
+    if args.len() > 1 && args[1] == "page" {
+        process::exit(page::main(&args));
+    }
 
-    let (input, output) = get_args();
-    match save_latencies(input, output) {
-        Ok(_) => println!("Exiting.."),
-        Err(_) => error!("Error writing to file!")
+    let args = get_args();
+    if args.dry_run {
+        process::exit(dryrun::main(&args, &load_input(&args.input)));
+    }
+    match save_latencies(args) {
+        Ok(_) => info!("Exiting.."),
+        Err(err) => error!("Error writing results! {}", err)
     }
 }
 
@@ -42,13 +112,57 @@ fn main() {
 ///
 /// Sets and checks the valid command line arguments. Prints usage and exits if the command line
 /// arguments are not valid.
-fn get_args() -> (String, Option<String>) {
+fn get_args() -> Args {
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
 
     let mut opts = Options::new();
     opts.optopt("i", "input", "set the input filename", "NAME");
-    opts.optopt("o", "output", &format!("set the output filename. '{}' will be used if none is provided", DEFAULT_OUTPUT), "NAME");
+    opts.optopt("o", "output", &format!("set the output filename, or '-' for stdout. '{}' will be used if none is provided", DEFAULT_OUTPUT), "NAME");
+    opts.optflag("", "har", &format!("write the results as a HAR 1.2 log. '{}' will be used if no output is given", DEFAULT_HAR_OUTPUT));
+    opts.optopt("r", "retries", "retry each failed request up to N more times (default 0)", "N");
+    opts.optopt("", "backoff", "wait MS milliseconds before the first retry, doubling each retry after (default 200)", "MS");
+    opts.optopt("", "backoff-cap", "never wait more than MS milliseconds between retries (default 5000)", "MS");
+    opts.optflag("", "no-jitter", "wait exactly the backoff between retries rather than a random time up to it");
+    opts.optopt("", "retry-on", "comma separated error kinds (connect, timeout, io, tls, invalid-url, protocol, body) \
+                                 and status codes to retry (default connect,timeout,io,429,502,503,504)", "LIST");
+    opts.optopt("c", "concurrency", "make up to N requests at once (default 1)", "N");
+    opts.optopt("n", "samples", "request each url N times, giving N results per url (default 1)", "N");
+    opts.optopt("", "host-rate", "send at most RPS requests per second to any one host", "RPS");
+    opts.optopt("", "host-burst", "allow N requests to a host back-to-back before --host-rate applies (default 1)", "N");
+    opts.optopt("", "host-delay", "wait at least MS milliseconds between starting requests to the same host", "MS");
+    opts.optopt("", "scheme", "how to request urls given without a scheme: http (default), https, \
+                               https-then-http or both", "POLICY");
+    opts.optopt("", "normalize", "also normalize urls with RULES: all, or a comma separated list of \
+                                  trailing-slash and sort-query", "RULES");
+    opts.optopt("", "measure", "how much of each response to wait for: headers (default), first-byte or \
+                                full-body, which also reports its size and throughput", "MODE");
+    opts.optopt("", "sample-interval", &format!("with full-body, sample the bytes received every MS milliseconds (default {})",
+                                                SamplingPolicy::default().interval_ms), "MS");
+    opts.optopt("", "first-kb", &format!("with full-body, report the time to receive the first N KB (default {})",
+                                         SamplingPolicy::default().first_bytes / 1024), "N");
+    opts.optopt("", "stall", &format!("with full-body, report gaps of more than MS milliseconds without bytes (default {})",
+                                      SamplingPolicy::default().stall_ms), "MS");
+    opts.optopt("", "accept-encoding", "ask for responses compressed with LIST, a comma separated list of \
+                                        identity, gzip, deflate, br and zstd", "LIST");
+    opts.optopt("", "upload-size", "upload a request body of SIZE random bytes, such as 512, 64KB or 10MB, \
+                                    with POST unless a target chooses its method", "SIZE");
+    opts.optopt("", "upload-file", "upload the contents of file NAME as the request body, as --upload-size does", "NAME");
+    opts.optflag("", "chunked", "send uploads with chunked transfer encoding");
+    opts.optflag("", "expect-continue", &format!("send uploads with 'Expect: 100-continue', waiting up to {}ms for the \
+                                                  server's go-ahead", upload::CONTINUE_TIMEOUT_MS));
+    opts.optflag("", "dedupe", "request targets which are the same after normalization only once");
+    opts.optopt("", "sitemap-sample", "request only some of a sitemap's urls: random:N for N chosen at \
+                                       random, or every:N for every Nth", "SAMPLING");
+    opts.optmulti("", "openapi-tag", "probe only an OpenAPI document's operations with TAG (may be repeated)", "TAG");
+    opts.optmulti("", "openapi-operation", "probe only the OpenAPI operation with operationId ID (may be repeated)", "ID");
+    opts.optopt("", "openapi-params", "take values for OpenAPI parameters from the TOML file NAME", "NAME");
+    opts.optopt("", "openapi-server", "request OpenAPI operations from URL rather than the document's servers", "URL");
+    opts.optopt("", "postman-env", "resolve a Postman collection's variables from the exported environment NAME", "NAME");
+    opts.optflag("", "crawl", "treat the input's urls as seeds, and also request the pages on the same host they link to");
+    opts.optopt("", "crawl-depth", &format!("follow links at most N pages away from a seed (default {})", CrawlPolicy::default().max_depth), "N");
+    opts.optopt("", "crawl-max-pages", &format!("stop crawling after finding N pages (default {})", CrawlPolicy::default().max_pages), "N");
+    opts.optflag("", "dry-run", "check every url in the input and report what would be requested, without making any requests");
     opts.optflag("h", "help", "print this help menu");
 
     let matches = match opts.parse(&args[1..]) {
@@ -66,57 +180,534 @@ fn get_args() -> (String, Option<String>) {
         process::exit(1);
     };
     let output = matches.opt_str("o");
-    (input, output)
+    let hosts = HostPolicy {
+        rate: parse_opt(&matches, "host-rate"),
+        burst: parse_opt(&matches, "host-burst").unwrap_or(1),
+        min_delay_ms: parse_opt(&matches, "host-delay").unwrap_or(0),
+    };
+    if hosts.rate.map(|r| !(r > 0.0)).unwrap_or(false) {
+        error!("--host-rate must be greater than zero");
+        process::exit(1);
+    }
+    let samples = parse_opt(&matches, "samples").unwrap_or(1);
+    if samples == 0 {
+        error!("--samples must be greater than zero");
+        process::exit(1);
+    }
+    let scheme = match matches.opt_str("scheme").map(|s| (SchemePolicy::from_name(&s), s)) {
+        Some((Some(policy), _)) => policy,
+        Some((None, name)) => {
+            error!("Unknown --scheme policy: {}", name);
+            process::exit(1);
+        }
+        None => SchemePolicy::Http,
+    };
+    let measure = match matches.opt_str("measure").map(|s| (Measure::from_name(&s), s)) {
+        Some((Some(measure), _)) => Some(measure),
+        Some((None, name)) => {
+            error!("Unknown --measure mode: {}", name);
+            process::exit(1);
+        }
+        None => None,
+    };
+    // Asking for any part of the throughput curve samples the download, which means reading it all
+    let throughput = if matches.opt_present("sample-interval") || matches.opt_present("first-kb") || matches.opt_present("stall") {
+        if measure.map(|m| m != Measure::FullBody).unwrap_or(false) {
+            error!("--sample-interval, --first-kb and --stall need --measure full-body");
+            process::exit(1);
+        }
+        Some(SamplingPolicy {
+            interval_ms: parse_opt(&matches, "sample-interval").unwrap_or(SamplingPolicy::default().interval_ms),
+            first_bytes: match parse_opt::<u64>(&matches, "first-kb").map(|kb| kb.checked_mul(1024)) {
+                Some(Some(bytes)) => bytes,
+                Some(None) => {
+                    error!("--first-kb is too large");
+                    process::exit(1);
+                }
+                None => SamplingPolicy::default().first_bytes,
+            },
+            stall_ms: parse_opt(&matches, "stall").unwrap_or(SamplingPolicy::default().stall_ms),
+        })
+    } else {
+        None
+    };
+    if throughput.map(|t| t.interval_ms == 0).unwrap_or(false) {
+        error!("--sample-interval must be greater than zero");
+        process::exit(1);
+    }
+    let measure = measure.or(throughput.map(|_| Measure::FullBody));
+    let accept_encoding = match matches.opt_str("accept-encoding").map(|l| httplatency::compression::parse_list(&l)) {
+        Some(Ok(encodings)) => Some(httplatency::compression::accept_encoding(&encodings)),
+        Some(Err(err)) => {
+            error!("Invalid --accept-encoding: {}", err);
+            process::exit(1);
+        }
+        None => None,
+    };
+    let payload = match (matches.opt_str("upload-size"), matches.opt_str("upload-file")) {
+        (Some(_), Some(_)) => {
+            error!("Give either --upload-size or --upload-file, not both");
+            process::exit(1);
+        }
+        (Some(size), None) => match upload::parse_size(&size) {
+            Ok(bytes) => Some(Payload::Random(bytes)),
+            Err(err) => {
+                error!("Invalid --upload-size: {}", err);
+                process::exit(1);
+            }
+        },
+        (None, Some(file)) => {
+            let payload = Payload::File(file);
+            if let Err(err) = upload::check(&payload) {
+                error!("Invalid --upload-file: {}", err);
+                process::exit(1);
+            }
+            Some(payload)
+        }
+        (None, None) => None,
+    };
+    if payload.is_none() && (matches.opt_present("chunked") || matches.opt_present("expect-continue")) {
+        error!("--chunked and --expect-continue need --upload-size or --upload-file");
+        process::exit(1);
+    }
+    let upload = payload.map(|payload| UploadPolicy {
+        payload: payload,
+        chunked: matches.opt_present("chunked"),
+        expect_continue: matches.opt_present("expect-continue"),
+    });
+    let sitemap_sample = match matches.opt_str("sitemap-sample").map(|s| Sampling::from_spec(&s)) {
+        Some(Ok(sampling)) => sampling,
+        Some(Err(err)) => {
+            error!("Invalid --sitemap-sample: {}", err);
+            process::exit(1);
+        }
+        None => Sampling::All,
+    };
+    let parameters = match matches.opt_str("openapi-params").map(|f| read_text(&f).and_then(|t| Parameters::parse(&t))) {
+        Some(Ok(parameters)) => parameters,
+        Some(Err(err)) => {
+            error!("Invalid --openapi-params: {}", err);
+            process::exit(1);
+        }
+        None => Parameters::default(),
+    };
+    let postman_env = match matches.opt_str("postman-env").map(|f| read_text(&f).and_then(|t| postman::parse_environment(&t))) {
+        Some(Ok(variables)) => variables,
+        Some(Err(err)) => {
+            error!("Invalid --postman-env: {}", err);
+            process::exit(1);
+        }
+        None => Variables::new(),
+    };
+    let normalize = match matches.opt_str("normalize").map(|n| NormalizePolicy::from_names(&n)) {
+        Some(Ok(policy)) => policy,
+        Some(Err(err)) => {
+            error!("{}", err);
+            process::exit(1);
+        }
+        None => NormalizePolicy::none(),
+    };
+    Args {
+        command_line: args[1..].to_vec(),
+        input: input,
+        output: output,
+        har: matches.opt_present("har"),
+        retry: get_retry_policy(&matches),
+        concurrency: parse_opt(&matches, "concurrency").unwrap_or(1),
+        samples: samples,
+        hosts: hosts,
+        scheme: scheme,
+        normalize: normalize,
+        dedupe: matches.opt_present("dedupe"),
+        sitemap_sample: sitemap_sample,
+        openapi: Selection {
+            tags: matches.opt_strs("openapi-tag"),
+            operations: matches.opt_strs("openapi-operation"),
+            server: matches.opt_str("openapi-server"),
+            parameters: parameters,
+        },
+        postman_env: postman_env,
+        crawl: if matches.opt_present("crawl") {
+            Some(CrawlPolicy {
+                max_depth: parse_opt(&matches, "crawl-depth").unwrap_or(CrawlPolicy::default().max_depth),
+                max_pages: parse_opt(&matches, "crawl-max-pages").unwrap_or(CrawlPolicy::default().max_pages),
+            })
+        } else {
+            None
+        },
+        measure: measure,
+        throughput: throughput,
+        accept_encoding: accept_encoding,
+        upload: upload,
+        dry_run: matches.opt_present("dry-run"),
+    }
+}
+
+/// Reads a text file given with an option, such as an OpenAPI parameters file
+fn read_text(filename: &str) -> Result<String, String> {
+    let mut text = String::new();
+    try!(File::open(filename).and_then(|mut f| f.read_to_string(&mut text)).map_err(|e| format!("{}: {}", filename, e)));
+    Ok(text)
+}
+
+/// Builds the retry policy from the retry related command line arguments
+fn get_retry_policy(matches: &Matches) -> RetryPolicy {
+    let mut policy = RetryPolicy::default();
+    policy.max_attempts = parse_opt::<u32>(matches, "retries").unwrap_or(0).saturating_add(1);
+    policy.backoff_base_ms = parse_opt(matches, "backoff").unwrap_or(policy.backoff_base_ms);
+    policy.backoff_cap_ms = parse_opt(matches, "backoff-cap").unwrap_or(policy.backoff_cap_ms);
+    policy.jitter = !matches.opt_present("no-jitter");
+    if let Some(list) = matches.opt_str("retry-on") {
+        policy.retryable_errors = Vec::new();
+        policy.retryable_statuses = Vec::new();
+        for item in list.split(',').map(|i| i.trim()).filter(|i| !i.is_empty()) {
+            match (ErrorKind::from_name(item), item.parse::<u16>()) {
+                (Some(kind), _) => policy.retryable_errors.push(kind),
+                (None, Ok(status)) => policy.retryable_statuses.push(status),
+                (None, Err(_)) => {
+                    error!("Unknown error kind or status code in --retry-on: {}", item);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+    policy
+}
+
+/// Parses the value of an optional argument, exiting with an error if it is present but invalid
+fn parse_opt<T: FromStr>(matches: &Matches, name: &str) -> Option<T> {
+    matches.opt_str(name).map(|v| match v.parse() {
+        Ok(t) => t,
+        Err(_) => {
+            error!("Invalid value for --{}: {}", name, v);
+            process::exit(1);
+        }
+    })
 }
 
 /// Print the program's instructions
 fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} FILE [options]", program);
+    let brief = format!("Usage: {0} FILE|CONFIG.toml|SITEMAP.xml|SITEMAP_URL|CAPTURE.har|COMMANDS.curl|OPENAPI.yaml|NAME.postman_collection.json|- [options]\n       \
+                         {0} load URL --rate RPS [options]\n       \
+                         {0} compare BASELINE CANDIDATE [options]\n       \
+                         {0} page URL [options]\n       \
+                         {0} compression URL [options]", program);
     print!("{}", opts.usage(&brief));
 }
 
 /// Read from file, measure latencies and write results to file
 ///
-/// Maps over a list of strings (taken from input file),
-/// checks they're valid http urls,
-/// makes a GET request recording the times,
-/// and writes results as JSON to file
-fn save_latencies(infile: String, outfile: Option<String>) -> io::Result<()>{
-    let urls = match get_urls(&infile) {
-        Ok(u) => u,
+/// Reads the targets from a config file, a sitemap, a HAR file, curl commands, an OpenAPI document, a Postman collection, or a list of urls and their settings (taken from input file)
+/// checking they're valid http urls,
+/// makes the requests recording the times (retrying failures and limiting requests per host as configured),
+/// and writes results as JSON to file along with details of the run
+fn save_latencies(args: Args) -> io::Result<()>{
+    let contents = load_input(&args.input);
+    let run = Run::start_with_input(args.command_line.clone(), &args.input, &contents);
+    let targets = match get_targets(&contents, &args) {
+        Ok(t) => prepare_targets(t, args.scheme, &args.normalize, args.dedupe),
         Err(err) => {
-            error!("Unable to open file: {}. {}", infile, err);
+            error!("Unable to read targets from {}:\n{}", args.input, err);
             process::exit(1);
         }
     };
-    let results : Vec<Latency> = urls.iter()                                      // Get iterator
-                                     .map(httplatency::canonicalize_http_address) // Make sure all urls contain a scheme
-                                     .filter_map(|s| s)                           // Remove all None options
-                                     .map(|s| httplatency::get_latency(&s))       // Make all requests and time measurements
-                                     .filter_map(|s| s)                           // Remove all None options
-                                     .collect();                                  // Convert to Vec for serialization
+    // Crawling and probing share the limits on each host, so the probes start no sooner than allowed
+    let mut limiter = HostLimiter::new(args.hosts.clone());
+    let targets = match args.crawl {
+        Some(ref crawl_policy) => crawl_targets(targets, crawl_policy, &args.normalize, &mut limiter),
+        None => targets,
+    };
+    let policy = args.retry;
+    let samples = args.samples;
+    let measure = args.measure;
+    let throughput = args.throughput;
+    let accept_encoding = args.accept_encoding;
+    let upload = args.upload;
+    let targets: Vec<Target> = targets.into_iter()
+                                      .map(|t| Target { measure: t.measure.or(measure), ..t })  // Config files may choose their own measure
+                                      .map(|t| with_throughput(t, &throughput))
+                                      .map(|t| with_accept_encoding(t, &accept_encoding))
+                                      .map(|t| with_upload(t, &upload))
+                                      .flat_map(|t| vec![t; samples])   // Repeat each target to take several samples
+                                      .collect();
+    let probe = move |t: &Target| httplatency::get_target_latency(t, &policy);
+    let results : Vec<Latency> = scheduler::probe_all(targets, args.concurrency, limiter, probe) // Make all requests and time measurements
+                                     .into_iter()
+                                     .filter_map(|s| s)                                        // Remove all None options
+                                     .collect();                                               // Convert to Vec for serialization
     debug!("All HTTP requests complete");
+    if args.har {
+        return write_json(&har::to_har(&run.finish(results)), args.output.or(Some(DEFAULT_HAR_OUTPUT.to_string())));
+    }
+    write_json(&run.finish(results), args.output)
+}
+
+/// Makes ``target`` sample its download as ``throughput`` asks, which is only possible if it reads
+/// the whole body, so a target measuring less (as a config file may choose) is left alone with a
+/// warning
+fn with_throughput(mut target: Target, throughput: &Option<SamplingPolicy>) -> Target {
+    if let Some(policy) = *throughput {
+        if target.measure == Some(Measure::FullBody) {
+            target.throughput = Some(policy);
+        } else {
+            warn!("{}: not sampling its throughput, as it doesn't measure the full body", target.url);
+        }
+    }
+    target
+}
+
+/// Adds an ``Accept-Encoding`` header to ``target`` unless it already sends one
+fn with_accept_encoding(mut target: Target, accept_encoding: &Option<String>) -> Target {
+    if let Some(ref value) = *accept_encoding {
+        if !target.headers.iter().any(|h| h.0.eq_ignore_ascii_case("Accept-Encoding")) {
+            target.headers.push(("Accept-Encoding".to_string(), value.clone()));
+        }
+    }
+    target
+}
+
+/// Makes ``target`` send ``upload`` unless it already has an upload of its own, switching a
+/// ``GET`` to ``POST`` as there's no body to send with it
+fn with_upload(mut target: Target, upload: &Option<UploadPolicy>) -> Target {
+    if target.upload.is_none() && upload.is_some() {
+        target.upload = upload.clone();
+        if target.method == "GET" {
+            target.method = "POST".to_string();
+        }
+    }
+    target
+}
+
+/// Writes ``value`` as JSON to ``outfile``, or to the default output file if none is given
+///
+/// An ``outfile`` of ``-`` writes to stdout.
+fn write_json<T: Encodable>(value: &T, outfile: Option<String>) -> io::Result<()> {
     let outfilename = match outfile {
         Some(f) => f,
         None => DEFAULT_OUTPUT.to_string()
     };
+    let json = format!("{}\n", json::as_pretty_json(value));
+    if outfilename == STDIO {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        try!(out.write_all(json.as_bytes()));
+        return out.flush();
+    }
     let mut out = try!(File::create(&outfilename));
     debug!("Writing output to {}", outfilename);
-    let json = format!("{}\n", json::as_pretty_json(&results));
     out.write_all(json.as_bytes())
 }
 
-/// Given a file will return all the lines as a vector
+/// Prints a human readable summary with ``print``: to stdout, unless the JSON output is going there
+///
+/// A summary that can't be printed is only warned about, so it doesn't stop the report being saved
+/// or change the exit code.
+fn print_summary<F>(outfile: &Option<String>, print: F) where F: FnOnce(&mut Write) -> io::Result<()> {
+    let mut out: Box<Write> = match *outfile {
+        Some(ref f) if f == STDIO => Box::new(io::stderr()),
+        _ => Box::new(io::stdout()),
+    };
+    if let Err(err) = print(&mut *out) {
+        warn!("Couldn't print the summary: {}", err);
+    }
+}
+
+/// Reads the whole of the input file, or stdin if ``filename`` is ``-``, exiting if it can't be read
 ///
-/// Given a filename this function will try to open the file and then return each line one element
-/// of a vector.
+/// An input which is an http(s) url, i.e. a remote sitemap, is downloaded.
+fn load_input(filename: &String) -> Vec<u8> {
+    if sitemap::is_remote(filename) {
+        return match httplatency::fetch_body(filename) {
+            Ok(c) => c,
+            Err(err) => {
+                error!("Unable to fetch {}: {}", filename, err);
+                process::exit(1);
+            }
+        };
+    }
+    match read_input(filename) {
+        Ok(c) => c,
+        Err(err) => {
+            error!("Unable to open file: {}. {}", filename, err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Reads a sitemap listed by a sitemap index, downloading it if ``location`` is an http(s) url
+fn read_location(location: &str) -> Result<Vec<u8>, String> {
+    if sitemap::is_remote(location) {
+        return httplatency::fetch_body(location);
+    }
+    let mut contents = Vec::new();
+    try!(File::open(location).and_then(|mut f| f.read_to_end(&mut contents)).map_err(|e| e.to_string()));
+    Ok(contents)
+}
+
+/// Reads the whole of the input file, or stdin if ``filename`` is ``-``
 ///
 /// # Failures
 ///
-/// This function will return an Err if the file cannot be opened. (e.g. bad permissions, missing
-/// file etc.)
-fn get_urls(filename: &String) -> Result<Vec<String>, String> {
-    let file = try!(File::open(filename).map_err(|e| e.to_string()));
-    let reader = io::BufReader::new(file);
-    Ok(reader.lines().map(|l| l.unwrap()).collect())
+/// Returns an Err if the file cannot be opened or read.
+fn read_input(filename: &String) -> io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    if filename == STDIO {
+        let stdin = io::stdin();
+        try!(stdin.lock().read_to_end(&mut contents));
+    } else {
+        try!(try!(File::open(filename)).read_to_end(&mut contents));
+    }
+    Ok(contents)
+}
+
+/// Reads the targets to probe from the ``contents`` of the input: a config file, a sitemap, a HAR
+/// file, a file of curl commands, an OpenAPI document, a Postman collection, or a list of urls
+///
+/// Each target is labelled with where it came from, e.g. ``line 3``. Only the urls of a sitemap
+/// chosen by ``--sitemap-sample``, and the operations of an OpenAPI document chosen by the
+/// ``--openapi-*`` options, are used. Lines of a url list, sitemaps listed by an index, sitemap
+/// urls, HAR entries, curl commands, OpenAPI operations and Postman requests which can't be used
+/// are skipped with a warning.
+///
+/// # Failures
+///
+/// Returns an Err if the input isn't text (other than a sitemap, which may be gzipped), a config
+/// file, HAR file, OpenAPI document or Postman collection is invalid, or a sitemap can't be parsed.
+fn get_targets(contents: &[u8], args: &Args) -> Result<Vec<(String, Target)>, String> {
+    let filename = &args.input;
+    if postman::is_collection_file(filename) {
+        let text = try!(String::from_utf8(contents.to_vec()).map_err(|e| e.to_string()));
+        let mut targets = Vec::new();
+        for entry in try!(postman::entries(&text, &args.postman_env)) {
+            match entry.target {
+                Ok(target) => targets.push((format!("request {}", entry.line), target)),
+                Err(err) => warn!("{}: request {} ({}): {}", filename, entry.line, entry.text, err),
+            }
+        }
+        return Ok(targets);
+    }
+    if openapi::is_openapi_file(filename, contents) {
+        let text = try!(String::from_utf8(contents.to_vec()).map_err(|e| e.to_string()));
+        let mut targets = Vec::new();
+        for entry in try!(openapi::entries(&text, &args.openapi)) {
+            match entry.target {
+                Ok(target) => targets.push((format!("operation {}", entry.line), target)),
+                Err(err) => warn!("{}: operation {} ({}): {}", filename, entry.line, entry.text, err),
+            }
+        }
+        return Ok(targets);
+    }
+    if sitemap::is_sitemap(filename) {
+        let (urls, warnings) = try!(sitemap::load(filename, contents, read_location));
+        for warning in warnings {
+            warn!("{}: {}", filename, warning);
+        }
+        info!("Read {} urls from {}", urls.len(), filename);
+        let mut targets = Vec::new();
+        for (i, url) in args.sitemap_sample.apply(urls.into_iter().enumerate().collect()) {
+            match sitemap::target(&url) {
+                Ok(target) => targets.push((format!("url {}", i + 1), target)),
+                Err(err) => warn!("{}: url {}: {}", filename, i + 1, err),
+            }
+        }
+        return Ok(targets);
+    }
+    if har::is_har_file(filename) {
+        let text = try!(String::from_utf8(contents.to_vec()).map_err(|e| e.to_string()));
+        let mut targets = Vec::new();
+        for entry in try!(har::entries(&text)) {
+            match entry.target {
+                Ok(target) => targets.push((format!("entry {}", entry.line), target)),
+                Err(err) => warn!("{}: entry {} ({}): {}", filename, entry.line, entry.text, err),
+            }
+        }
+        return Ok(targets);
+    }
+    if curl::is_curl_file(filename) {
+        let text = try!(String::from_utf8(contents.to_vec()).map_err(|e| e.to_string()));
+        let mut targets = Vec::new();
+        for entry in curl::entries(&text) {
+            match entry.target {
+                Ok(target) => targets.push((format!("line {}", entry.line), target)),
+                Err(err) => warn!("{}: line {}: {}", filename, entry.line, err),
+            }
+        }
+        return Ok(targets);
+    }
+    if config::is_config_file(filename) {
+        let text = try!(String::from_utf8(contents.to_vec()).map_err(|e| e.to_string()));
+        let targets = try!(config::parse_numbered(&text));
+        for &(n, ref target) in &targets {
+            if let Some(ref upload) = target.upload {
+                try!(upload::check(&upload.payload).map_err(|e| format!("target {}: {}", n, e)));
+            }
+        }
+        return Ok(targets.into_iter().map(|(n, t)| (format!("target {}", n), t)).collect());
+    }
+    let mut targets = Vec::new();
+    for entry in urllist::entries(contents) {
+        match entry.target {
+            Ok(target) => targets.push((format!("line {}", entry.line), target)),
+            Err(err) => warn!("{}: line {}: {}", filename, entry.line, err),
+        }
+    }
+    Ok(targets)
+}
+
+/// Crawls from the urls of ``seeds``, returning every seed followed by a target for every page
+/// found, with each download waiting for ``limiter``
+///
+/// Every seed is kept as it is, but only seeds requested with ``GET`` or ``HEAD`` are crawled from,
+/// as crawling downloads pages with ``GET``. Pages found from a seed are requested with ``GET`` and
+/// the seed's other settings, such as its headers and expectations, and remember the page which
+/// linked to them. Their urls are normalized as ``policy`` asks.
+fn crawl_targets(seeds: Vec<Target>, crawl_policy: &CrawlPolicy, policy: &NormalizePolicy,
+                 limiter: &mut HostLimiter) -> Vec<Target> {
+    let found: Vec<Target> = {
+        let crawlable: Vec<&Target> = seeds.iter().filter(|t| t.method == "GET" || t.method == "HEAD").collect();
+        if crawlable.len() < seeds.len() {
+            info!("Not crawling from {} urls which aren't requested with GET or HEAD", seeds.len() - crawlable.len());
+        }
+        let urls: Vec<String> = crawlable.iter().map(|t| t.url.clone()).collect();
+        let (pages, warnings) = crawl::crawl(&urls, crawl_policy, limiter, |url| httplatency::fetch_document(url));
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+        info!("Found {} pages crawling from {} urls", pages.len(), urls.len());
+        pages.into_iter().map(|page| {
+            let mut target = crawlable[page.seed].clone();
+            target.url = policy.apply(&page.url);
+            target.method = "GET".to_string();
+            target.body = None;
+            target.template = None;
+            target.fallback_url = None;
+            target.linked_from = page.linked_from;
+            target
+        }).collect()
+    };
+    let mut targets = seeds;
+    targets.extend(found);
+    targets
+}
+
+/// Applies the scheme policy to the labelled targets, normalizes their urls and, if ``dedupe`` is
+/// set, merges targets which are then the same, logging which were merged
+fn prepare_targets(labelled: Vec<(String, Target)>, scheme: SchemePolicy, policy: &NormalizePolicy,
+                   dedupe: bool) -> Vec<Target> {
+    let (labels, targets): (Vec<String>, Vec<Target>) = labelled.into_iter().flat_map(|(label, target)| {
+        scheme.apply(target).into_iter().map(|mut t| {
+            t.url = policy.apply(&t.url);
+            t.fallback_url = t.fallback_url.map(|f| policy.apply(&f));
+            (label.clone(), t)
+        }).collect::<Vec<_>>()
+    }).unzip();
+    if !dedupe {
+        return targets;
+    }
+    let (targets, merged) = normalize::dedupe(targets);
+    for m in merged.iter() {
+        let from: Vec<&str> = m.indices.iter().map(|&i| &labels[i][..]).collect();
+        info!("Merged {} into one request for {}", from.join(", "), m.url);
+    }
+    targets
 }
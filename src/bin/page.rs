@@ -0,0 +1,107 @@
+//! The ``page`` subcommand: loads a page and everything it references, as a browser would.
+
+use std::io::{self, Write};
+use std::iter::repeat;
+use std::process;
+
+use getopts::Options;
+
+use httplatency;
+use httplatency::envelope::Run;
+use httplatency::har;
+use httplatency::page::{self, PageLoad};
+use httplatency::target::Target;
+
+use super::{parse_opt, print_summary, write_json, DEFAULT_HAR_OUTPUT};
+
+/// The width of the bars in the printed waterfall, in characters
+const WATERFALL_WIDTH: i64 = 40;
+
+/// Runs the page subcommand given the full command line, returning the exit code
+pub fn main(args: &[String]) -> i32 {
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optopt("", "per-host", &format!("make at most N requests to a host at once (default {})", page::DEFAULT_PER_HOST), "N");
+    opts.optopt("t", "timeout", "give up on each request after MS milliseconds", "MS");
+    opts.optopt("o", "output", "set the output filename, or '-' for stdout. 'output.json' will be used if none is provided", "NAME");
+    opts.optflag("", "har", &format!("write the waterfall as a HAR 1.2 log. '{}' will be used if no output is given", DEFAULT_HAR_OUTPUT));
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args[2..]) {
+        Ok(m) => { m }
+        Err(f) => { panic!(f.to_string()) }
+    };
+    if matches.opt_present("h") {
+        print_usage(&program, opts);
+        process::exit(0);
+    }
+    let url = if !matches.free.is_empty() {
+        matches.free[0].clone()
+    } else {
+        print_usage(&program, opts);
+        return 1;
+    };
+    let url = match httplatency::canonicalize_http_address(&url) {
+        Some(u) => u,
+        None => {
+            error!("Not a valid http(s) url: {}", url);
+            return 1;
+        }
+    };
+    let per_host = parse_opt(&matches, "per-host").unwrap_or(page::DEFAULT_PER_HOST);
+    if per_host == 0 {
+        error!("--per-host must be at least 1");
+        return 1;
+    }
+    let mut template = Target::get(url.clone());
+    template.timeout_ms = parse_opt(&matches, "timeout");
+
+    let run = Run::start(args[1..].to_vec(), None);
+    let load = page::load(&url, per_host, move |resource| {
+        let mut target = template.clone();
+        target.url = resource.to_string();
+        httplatency::fetch_timed(&target)
+    });
+    let output = matches.opt_str("o");
+    print_summary(&output, |out| print_waterfall(out, &load));
+    let written = if matches.opt_present("har") {
+        write_json(&har::page_to_har(&run.finish(load)), output.or(Some(DEFAULT_HAR_OUTPUT.to_string())))
+    } else {
+        write_json(&run.finish(load), output)
+    };
+    match written {
+        Ok(_) => 0,
+        Err(err) => {
+            error!("Error writing to file! {}", err);
+            1
+        }
+    }
+}
+
+/// Print the page subcommand's instructions
+fn print_usage(program: &str, opts: Options) {
+    let brief = format!("Usage: {} page URL [options]", program);
+    print!("{}", opts.usage(&brief));
+}
+
+/// Print each request of a page load as a bar spanning the time it was in flight, followed by
+/// the page's totals
+fn print_waterfall(out: &mut Write, load: &PageLoad) -> io::Result<()> {
+    let scale = load.page_complete_ms.max(1);
+    for resource in load.resources.iter() {
+        let start = (resource.start_ms * WATERFALL_WIDTH / scale).min(WATERFALL_WIDTH - 1);
+        let end = (resource.end_ms() * WATERFALL_WIDTH / scale).max(start + 1);
+        let bar: String = repeat(' ').take(start as usize).chain(repeat('#').take((end - start) as usize)).collect();
+        let status = match (resource.status, &resource.error) {
+            (_, &Some(ref err)) => err.clone(),
+            (Some(status), _) => status.to_string(),
+            (None, _) => "-".to_string(),
+        };
+        try!(writeln!(out, "{:<width$} {:>6}ms {:>6}ms  {:<10} {} ({})", bar, resource.start_ms, resource.total_ms,
+                      format!("{:?}", resource.kind), resource.url, status, width = WATERFALL_WIDTH as usize));
+    }
+    try!(writeln!(out, "Requests:      {} ({} failed, {} bytes)", load.requests, load.failed, load.bytes));
+    try!(writeln!(out, "Document:      {}ms", load.document_ms));
+    writeln!(out, "Page complete: {}ms", load.page_complete_ms)
+}
@@ -0,0 +1,479 @@
+//! Compares the results of two runs to find urls which have become slower or started failing.
+//!
+//! Results are joined by method and url, so a url requested with both ``GET`` and ``POST`` is
+//! compared as two. Where one was measured more than once in a run its median latency is used. A url counts as failing in a run if it has no result at all (requests which got no
+//! response are left out of output files) or every result for it has a 5xx status.
+//!
+//! When both runs have enough repeated samples of a url, its samples are also tested for a
+//! statistically significant difference, since a change in the median alone is often just noise.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+
+use rustc_serialize::Decodable;
+use rustc_serialize::json::{self, Json};
+
+use rand;
+
+use stats::{self, percentile, Interval, MannWhitney};
+
+/// The fewest samples of a url each run must have for its change to be tested for significance
+pub const MIN_SAMPLES: usize = 5;
+
+/// The method results written before methods were recorded are taken to have used
+const DEFAULT_METHOD: &'static str = "GET";
+
+/// The parts of a stored result needed to compare it
+#[derive(RustcDecodable, Debug, Clone)]
+struct StoredResult {
+    url: String,
+    method: Option<String>,
+    latency_ms: i64,
+    status: Option<u16>,
+}
+
+impl StoredResult {
+    fn is_failure(&self) -> bool {
+        self.status.map(|s| s >= 500).unwrap_or(false)
+    }
+}
+
+/// The results of one run, grouped by method and url
+#[derive(Debug, Clone)]
+pub struct ResultSet {
+    /// The hash of the input the run read its urls from, if recorded
+    pub input_hash: Option<String>,
+    samples: BTreeMap<(String, String), Vec<StoredResult>>,
+}
+
+impl ResultSet {
+    /// The methods and urls with results in this set, in sorted order
+    pub fn requests(&self) -> Vec<&(String, String)> {
+        self.samples.keys().collect()
+    }
+
+    /// All latencies of successful results for ``url`` requested with ``method``, sorted
+    pub fn latencies(&self, method: &str, url: &str) -> Vec<i64> {
+        let mut latencies: Vec<i64> = match self.samples.get(&(method.to_string(), url.to_string())) {
+            Some(results) => results.iter().filter(|r| !r.is_failure()).map(|r| r.latency_ms).collect(),
+            None => Vec::new(),
+        };
+        latencies.sort();
+        latencies
+    }
+
+    /// The median latency of successful results for ``url`` requested with ``method``, or None if
+    /// it failed or is missing
+    pub fn median(&self, method: &str, url: &str) -> Option<i64> {
+        let latencies = self.latencies(method, url);
+        if latencies.is_empty() { None } else { Some(percentile(&latencies, 50.0)) }
+    }
+}
+
+/// Reads a results file written by httplatency
+///
+/// Both the current enveloped layout and the older bare array of results are accepted.
+///
+/// # Failures
+///
+/// Returns an Err if the file can't be read or doesn't contain results.
+pub fn load_results(path: &String) -> Result<ResultSet, String> {
+    let mut file = try!(File::open(path).map_err(|e| format!("Unable to open {}: {}", path, e)));
+    let mut contents = String::new();
+    try!(file.read_to_string(&mut contents).map_err(|e| format!("Unable to read {}: {}", path, e)));
+    parse_results(&contents).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Parses the contents of a results file
+///
+/// # Examples
+///
+/// ```
+/// let set = httplatency::compare::parse_results(r#"[{"url": "http://a/", "latency_ms": 10}]"#).unwrap();
+/// assert_eq!(set.median("GET", "http://a/"), Some(10));
+/// ```
+pub fn parse_results(contents: &str) -> Result<ResultSet, String> {
+    let json = try!(Json::from_str(contents).map_err(|e| format!("Invalid JSON: {}", e)));
+    let (input_hash, results) = match json {
+        Json::Array(results) => (None, results),
+        Json::Object(mut envelope) => {
+            let hash = envelope.get("input_hash").and_then(|h| h.as_string()).map(|h| h.to_string());
+            match envelope.remove("results") {
+                Some(Json::Array(results)) => (hash, results),
+                _ => return Err("No results array found".to_string()),
+            }
+        }
+        _ => return Err("No results array found".to_string()),
+    };
+
+    let mut samples: BTreeMap<(String, String), Vec<StoredResult>> = BTreeMap::new();
+    for (i, result) in results.into_iter().enumerate() {
+        let mut decoder = json::Decoder::new(result);
+        let stored: StoredResult = try!(Decodable::decode(&mut decoder)
+                                            .map_err(|e| format!("Result {} is invalid: {}", i, e)));
+        let method = stored.method.clone().unwrap_or(DEFAULT_METHOD.to_string());
+        samples.entry((method, stored.url.clone())).or_insert_with(Vec::new).push(stored);
+    }
+    Ok(ResultSet { input_hash: input_hash, samples: samples })
+}
+
+/// How a url's results changed between the two runs
+#[derive(RustcEncodable, Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    /// Within the thresholds, or faster
+    Unchanged,
+    /// Slower by more than the thresholds allow
+    Regressed,
+    /// Succeeded in the baseline but failed in the candidate
+    NewlyFailing,
+    /// Failed in the baseline but succeeded in the candidate
+    Recovered,
+    /// Failed in both runs
+    StillFailing,
+}
+
+/// The limits on how much slower a url may get before it counts as a regression
+///
+/// A url has regressed when its median latency increases by more than every limit given. With no
+/// limits any increase at all is a regression.
+#[derive(Debug, Clone, Default)]
+pub struct Thresholds {
+    /// The largest allowed increase in milliseconds
+    pub max_increase_ms: Option<i64>,
+
+    /// The largest allowed increase as a percentage of the baseline latency
+    pub max_increase_pct: Option<f64>,
+
+    /// Only count an increase as a regression if it is also statistically significant
+    pub require_significance: bool,
+}
+
+impl Thresholds {
+    /// True if an increase of ``delta_ms`` (``delta_pct`` percent) breaks these thresholds
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::compare::Thresholds;
+    /// let t = Thresholds { max_increase_ms: Some(50), max_increase_pct: Some(10.0), require_significance: false };
+    /// assert!(!t.exceeded(60, 5.0));
+    /// assert!(!t.exceeded(40, 20.0));
+    /// assert!(t.exceeded(60, 20.0));
+    /// ```
+    pub fn exceeded(&self, delta_ms: i64, delta_pct: f64) -> bool {
+        delta_ms > 0 &&
+            self.max_increase_ms.map(|max| delta_ms > max).unwrap_or(true) &&
+            self.max_increase_pct.map(|max| delta_pct > max).unwrap_or(true)
+    }
+}
+
+/// How changes are tested for significance
+#[derive(Debug, Clone)]
+pub struct SignificanceTest {
+    /// A change is significant if the Mann-Whitney p-value is below this
+    pub alpha: f64,
+
+    /// The number of bootstrap resamples used to estimate confidence intervals
+    pub iterations: usize,
+}
+
+impl Default for SignificanceTest {
+    /// A 5% significance level with 1000 bootstrap resamples
+    fn default() -> SignificanceTest {
+        SignificanceTest { alpha: 0.05, iterations: 1000 }
+    }
+}
+
+/// Whether a url's samples differ significantly between two runs
+#[derive(RustcEncodable, Debug, Clone)]
+pub struct Significance {
+    /// The number of successful samples in the baseline run
+    pub baseline_samples: usize,
+
+    /// The number of successful samples in the candidate run
+    pub candidate_samples: usize,
+
+    /// The Mann-Whitney U test of the two runs' samples
+    pub mann_whitney: MannWhitney,
+
+    /// The confidence interval, at ``1 - alpha``, of the change in median latency in milliseconds
+    pub median_delta_ci: Interval,
+
+    /// The confidence interval, at ``1 - alpha``, of the change in 95th percentile latency in milliseconds
+    pub p95_delta_ci: Interval,
+
+    /// True if the Mann-Whitney p-value is below ``alpha``
+    pub significant: bool,
+}
+
+/// Tests whether two sorted samples differ, if both have at least ``MIN_SAMPLES`` and ``test``
+/// makes at least one bootstrap resample
+pub fn test_significance(before: &[i64], after: &[i64], test: &SignificanceTest) -> Option<Significance> {
+    if before.len() < MIN_SAMPLES || after.len() < MIN_SAMPLES || test.iterations == 0 {
+        return None;
+    }
+    let mut rng = rand::thread_rng();
+    let confidence = 1.0 - test.alpha;
+    let median = |s: &[i64]| percentile(s, 50.0) as f64;
+    let p95 = |s: &[i64]| percentile(s, 95.0) as f64;
+    let mann_whitney = stats::mann_whitney(before, after).unwrap();
+    Some(Significance {
+        baseline_samples: before.len(),
+        candidate_samples: after.len(),
+        significant: mann_whitney.p_value < test.alpha,
+        mann_whitney: mann_whitney,
+        median_delta_ci: stats::bootstrap_ci(before, after, &median, test.iterations, confidence, &mut rng).unwrap(),
+        p95_delta_ci: stats::bootstrap_ci(before, after, &p95, test.iterations, confidence, &mut rng).unwrap(),
+    })
+}
+
+/// The comparison of a single url between two runs
+#[derive(RustcEncodable, Debug, Clone)]
+pub struct UrlComparison {
+    pub url: String,
+
+    /// The method the url was requested with
+    pub method: String,
+
+    /// The median latency in the baseline run, if it succeeded
+    pub baseline_ms: Option<i64>,
+
+    /// The median latency in the candidate run, if it succeeded
+    pub candidate_ms: Option<i64>,
+
+    /// ``candidate_ms - baseline_ms``, if both succeeded
+    pub delta_ms: Option<i64>,
+
+    /// ``delta_ms`` as a percentage of ``baseline_ms``, if both succeeded
+    pub delta_pct: Option<f64>,
+
+    /// The significance of the change, if both runs have enough samples to test
+    pub significance: Option<Significance>,
+
+    pub change: Change,
+}
+
+/// The comparison of every url found in either run
+#[derive(RustcEncodable, Debug, Clone)]
+pub struct Comparison {
+    pub urls: Vec<UrlComparison>,
+
+    /// The number of urls which regressed
+    pub regressions: usize,
+
+    /// The number of urls which succeeded in the baseline but failed in the candidate
+    pub newly_failing: usize,
+
+    /// The number of urls which failed in the baseline but succeeded in the candidate
+    pub recovered: usize,
+
+    /// True if both runs read the same input, or if it isn't known
+    pub same_input: bool,
+}
+
+impl Comparison {
+    /// True if any url regressed or newly failed
+    pub fn has_regressions(&self) -> bool {
+        self.regressions > 0 || self.newly_failing > 0
+    }
+}
+
+/// Compares every method and url in ``baseline`` and ``candidate``
+pub fn compare(baseline: &ResultSet, candidate: &ResultSet, thresholds: &Thresholds,
+               test: &SignificanceTest) -> Comparison {
+    let mut requests: Vec<&(String, String)> = baseline.requests();
+    requests.extend(candidate.requests());
+    requests.sort();
+    requests.dedup();
+
+    let compared: Vec<UrlComparison> = requests.into_iter().map(|&(ref method, ref url)| {
+        let before = baseline.median(method, url);
+        let after = candidate.median(method, url);
+        let significance = test_significance(&baseline.latencies(method, url), &candidate.latencies(method, url),
+                                             test);
+        let (delta_ms, delta_pct, change) = match (before, after) {
+            (Some(b), Some(a)) => {
+                let delta = a - b;
+                let pct = if b == 0 { 0.0 } else { delta as f64 * 100.0 / b as f64 };
+                let significant = significance.as_ref().map(|s| s.significant).unwrap_or(false);
+                let change = if thresholds.exceeded(delta, pct) && (significant || !thresholds.require_significance) {
+                    Change::Regressed
+                } else {
+                    Change::Unchanged
+                };
+                (Some(delta), Some(pct), change)
+            }
+            (Some(_), None) => (None, None, Change::NewlyFailing),
+            (None, Some(_)) => (None, None, Change::Recovered),
+            (None, None) => (None, None, Change::StillFailing),
+        };
+        UrlComparison {
+            url: url.clone(),
+            method: method.clone(),
+            baseline_ms: before,
+            candidate_ms: after,
+            delta_ms: delta_ms,
+            delta_pct: delta_pct,
+            significance: significance,
+            change: change,
+        }
+    }).collect();
+
+    let (regressions, newly_failing, recovered) = {
+        let count = |change: Change| compared.iter().filter(|c| c.change == change).count();
+        (count(Change::Regressed), count(Change::NewlyFailing), count(Change::Recovered))
+    };
+    Comparison {
+        regressions: regressions,
+        newly_failing: newly_failing,
+        recovered: recovered,
+        same_input: match (&baseline.input_hash, &candidate.input_hash) {
+            (&Some(ref a), &Some(ref b)) => a == b,
+            _ => true,
+        },
+        urls: compared,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn set(json: &str) -> ResultSet {
+        parse_results(json).unwrap()
+    }
+
+    #[test]
+    /// Enveloped results should be found inside the envelope
+    fn parse_envelope() {
+        let s = set(r#"{"schema_version": 1, "input_hash": "sha1:ab",
+                        "results": [{"url": "http://a/", "started_at": "x", "latency_ms": 10,
+                                     "status": 200, "attempts": []}]}"#);
+        assert_eq!(s.input_hash, Some("sha1:ab".to_string()));
+        assert_eq!(s.median("GET", "http://a/"), Some(10));
+    }
+
+    #[test]
+    /// Files which aren't results should be rejected
+    fn parse_rejects_other_json() {
+        assert!(parse_results(r#"{"foo": 1}"#).is_err());
+        assert!(parse_results(r#"[{"foo": 1}]"#).is_err());
+        assert!(parse_results("not json").is_err());
+    }
+
+    #[test]
+    /// Repeated results for a url should be reduced to their median
+    fn median_of_repeats() {
+        let s = set(r#"[{"url": "http://a/", "latency_ms": 30}, {"url": "http://a/", "latency_ms": 10},
+                        {"url": "http://a/", "latency_ms": 20}]"#);
+        assert_eq!(s.median("GET", "http://a/"), Some(20));
+    }
+
+    #[test]
+    /// Slowdowns should only be regressions once they pass the thresholds
+    fn regressions_use_thresholds() {
+        let before = set(r#"[{"url": "http://a/", "latency_ms": 100}, {"url": "http://b/", "latency_ms": 100}]"#);
+        let after = set(r#"[{"url": "http://a/", "latency_ms": 105}, {"url": "http://b/", "latency_ms": 200}]"#);
+        let t = Thresholds { max_increase_ms: Some(20), ..Thresholds::default() };
+        let c = compare(&before, &after, &t, &SignificanceTest::default());
+        assert_eq!(c.urls[0].change, Change::Unchanged);
+        assert_eq!(c.urls[1].change, Change::Regressed);
+        assert_eq!(c.urls[1].delta_ms, Some(100));
+        assert_eq!(c.urls[1].delta_pct, Some(100.0));
+        assert!(c.has_regressions());
+    }
+
+    #[test]
+    /// Getting faster is never a regression
+    fn improvements_unchanged() {
+        let before = set(r#"[{"url": "http://a/", "latency_ms": 100}]"#);
+        let after = set(r#"[{"url": "http://a/", "latency_ms": 50}]"#);
+        let c = compare(&before, &after, &Thresholds::default(), &SignificanceTest::default());
+        assert_eq!(c.urls[0].change, Change::Unchanged);
+        assert!(!c.has_regressions());
+    }
+
+    #[test]
+    /// Missing urls and server errors should count as failures
+    fn failing_and_recovered() {
+        let before = set(r#"[{"url": "http://a/", "latency_ms": 1}, {"url": "http://b/", "latency_ms": 1, "status": 503},
+                             {"url": "http://c/", "latency_ms": 1, "status": 500}]"#);
+        let after = set(r#"[{"url": "http://b/", "latency_ms": 1, "status": 200},
+                            {"url": "http://c/", "latency_ms": 1, "status": 502}]"#);
+        let c = compare(&before, &after, &Thresholds::default(), &SignificanceTest::default());
+        let changes: Vec<Change> = c.urls.iter().map(|u| u.change).collect();
+        assert_eq!(changes, vec![Change::NewlyFailing, Change::Recovered, Change::StillFailing]);
+        assert_eq!(c.newly_failing, 1);
+        assert_eq!(c.recovered, 1);
+    }
+
+    #[test]
+    /// The same url requested with different methods should be compared separately
+    fn joins_by_method() {
+        let before = set(r#"[{"url": "http://a/", "method": "GET", "latency_ms": 100},
+                             {"url": "http://a/", "method": "POST", "latency_ms": 100}]"#);
+        let after = set(r#"[{"url": "http://a/", "latency_ms": 100},
+                            {"url": "http://a/", "method": "POST", "latency_ms": 300}]"#);
+        let c = compare(&before, &after, &Thresholds::default(), &SignificanceTest::default());
+        let changes: Vec<(&str, Change)> = c.urls.iter().map(|u| (&u.method[..], u.change)).collect();
+        assert_eq!(changes, vec![("GET", Change::Unchanged), ("POST", Change::Regressed)]);
+        assert_eq!(c.urls[1].delta_ms, Some(200));
+    }
+
+    #[test]
+    /// Runs over different inputs should be flagged
+    fn different_inputs() {
+        let before = set(r#"{"input_hash": "sha1:aa", "results": []}"#);
+        let after = set(r#"{"input_hash": "sha1:bb", "results": []}"#);
+        assert!(!compare(&before, &after, &Thresholds::default(), &SignificanceTest::default()).same_input);
+    }
+
+    fn repeated(url: &str, latencies: &[i64]) -> String {
+        let results: Vec<String> = latencies.iter()
+                                            .map(|l| format!(r#"{{"url": "{}", "latency_ms": {}}}"#, url, l))
+                                            .collect();
+        format!("[{}]", results.join(","))
+    }
+
+    #[test]
+    /// Too few samples shouldn't be tested for significance
+    fn significance_needs_samples() {
+        let before = set(&repeated("http://a/", &[100, 101, 102]));
+        let after = set(&repeated("http://a/", &[200, 201, 202]));
+        let c = compare(&before, &after, &Thresholds::default(), &SignificanceTest::default());
+        assert!(c.urls[0].significance.is_none());
+    }
+
+    #[test]
+    /// Without any bootstrap resamples there are no intervals, so nothing should be tested
+    fn significance_needs_resamples() {
+        let before = set(&repeated("http://a/", &[100, 104, 98, 101, 99, 103, 97, 102, 100, 105]));
+        let after = set(&repeated("http://a/", &[150, 154, 148, 151, 149, 153, 147, 152, 150, 155]));
+        let test = SignificanceTest { alpha: 0.05, iterations: 0 };
+        assert!(compare(&before, &after, &Thresholds::default(), &test).urls[0].significance.is_none());
+    }
+
+    #[test]
+    /// A clear shift across many samples should be significant
+    fn significant_shift() {
+        let before = set(&repeated("http://a/", &[100, 104, 98, 101, 99, 103, 97, 102, 100, 105]));
+        let after = set(&repeated("http://a/", &[150, 154, 148, 151, 149, 153, 147, 152, 150, 155]));
+        let c = compare(&before, &after, &Thresholds::default(), &SignificanceTest::default());
+        let sig = c.urls[0].significance.clone().unwrap();
+        assert!(sig.significant);
+        assert!(sig.median_delta_ci.excludes_zero());
+        assert_eq!(sig.baseline_samples, 10);
+    }
+
+    #[test]
+    /// Noisy samples with a small median change shouldn't regress when significance is required
+    fn noise_not_regression() {
+        let before = set(&repeated("http://a/", &[100, 180, 90, 150, 110, 95, 170, 120]));
+        let after = set(&repeated("http://a/", &[120, 100, 175, 95, 160, 115, 185, 90]));
+        let mut t = Thresholds::default();
+        assert_eq!(compare(&before, &after, &t, &SignificanceTest::default()).urls[0].change, Change::Regressed);
+        t.require_significance = true;
+        assert_eq!(compare(&before, &after, &t, &SignificanceTest::default()).urls[0].change, Change::Unchanged);
+    }
+}
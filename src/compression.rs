@@ -0,0 +1,392 @@
+//! Negotiating compressed responses, decoding them, and comparing what each encoding saves.
+//!
+//! The encodings a request accepts are sent in its ``Accept-Encoding`` header. When the whole body
+//! is read (see ``Measure::FullBody``), a body sent with a ``Content-Encoding`` is decoded after the
+//! request is timed, recording its decoded size and how long decoding took. Bodies in every
+//! encoding which can be asked for (``gzip``, ``deflate``, ``br`` and ``zstd``) are decoded.
+//!
+//! A small compressed body can expand enormously, so bodies are only decoded while both they and
+//! what they decode to fit in ``MAX_DECODE_BYTES``.
+
+use std::io::Read;
+
+use brotli_decompressor::Decompressor;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use time;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+use Latency;
+use stats::median;
+
+/// The largest encoded body which is kept to be decoded, and the most each encoding may decode to,
+/// in bytes
+pub const MAX_DECODE_BYTES: u64 = 100 * 1024 * 1024;
+
+/// A content coding which may be asked for in ``Accept-Encoding``
+#[derive(RustcEncodable, Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+/// Every encoding, in the order they are compared by default
+pub const ENCODINGS: [Encoding; 5] = [Encoding::Identity, Encoding::Gzip, Encoding::Deflate, Encoding::Brotli, Encoding::Zstd];
+
+impl Encoding {
+    /// Looks up an encoding by its name in HTTP headers
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::compression::Encoding;
+    ///
+    /// assert_eq!(Encoding::from_name("br"), Some(Encoding::Brotli));
+    /// assert_eq!(Encoding::from_name("GZIP"), Some(Encoding::Gzip));
+    /// assert_eq!(Encoding::from_name("lzma"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match &name.trim().to_lowercase()[..] {
+            "identity" => Some(Encoding::Identity),
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            "br" => Some(Encoding::Brotli),
+            "zstd" => Some(Encoding::Zstd),
+            _ => None,
+        }
+    }
+
+    /// The encoding's name in HTTP headers
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Encoding::Identity => "identity",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+        }
+    }
+}
+
+/// Parses a comma separated list of encoding names, such as given to ``--accept-encoding``
+///
+/// # Failures
+///
+/// Returns an Err naming the first encoding which isn't known.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::compression::{parse_list, Encoding};
+///
+/// assert_eq!(parse_list("gzip, br"), Ok(vec![Encoding::Gzip, Encoding::Brotli]));
+/// assert!(parse_list("gzip,lzma").is_err());
+/// ```
+pub fn parse_list(list: &str) -> Result<Vec<Encoding>, String> {
+    list.split(',').filter(|n| !n.trim().is_empty()).map(|name| {
+        Encoding::from_name(name).ok_or(format!("unknown encoding '{}', expected identity, gzip, deflate, br or zstd",
+                                                name.trim()))
+    }).collect()
+}
+
+/// The ``Accept-Encoding`` header value asking for ``encodings``, in order of preference
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::compression::{accept_encoding, Encoding};
+///
+/// assert_eq!(accept_encoding(&[Encoding::Zstd, Encoding::Gzip]), "zstd, gzip");
+/// ```
+pub fn accept_encoding(encodings: &[Encoding]) -> String {
+    let names: Vec<&str> = encodings.iter().map(|e| e.name()).collect();
+    names.join(", ")
+}
+
+/// Decodes a body sent with the ``Content-Encoding`` ``content_encoding``
+///
+/// Encodings listed together, such as ``gzip, br``, were applied in order so are undone in reverse.
+///
+/// # Failures
+///
+/// Returns an Err if an encoding isn't known, the body isn't valid in its encoding, or it decodes
+/// to more than ``MAX_DECODE_BYTES``.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(httplatency::compression::decode("identity", b"abc"), Ok(b"abc".to_vec()));
+/// assert!(httplatency::compression::decode("gzip", b"abc").is_err());
+/// ```
+pub fn decode(content_encoding: &str, body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = body.to_vec();
+    for name in content_encoding.split(',').rev().filter(|n| !n.trim().is_empty()) {
+        let encoding = match Encoding::from_name(name) {
+            Some(encoding) => encoding,
+            None => return Err(format!("unknown encoding '{}'", name.trim())),
+        };
+        decoded = try!(decode_one(encoding, &decoded));
+    }
+    Ok(decoded)
+}
+
+/// Undoes a single encoding
+fn decode_one(encoding: Encoding, body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::new();
+    // Reading one byte more than allowed tells a body which decodes to too much from one which just fits
+    let limit = MAX_DECODE_BYTES + 1;
+    let result = match encoding {
+        Encoding::Identity => return Ok(body.to_vec()),
+        Encoding::Gzip => GzDecoder::new(body).and_then(|decoder| decoder.take(limit).read_to_end(&mut decoded)),
+        // ``deflate`` should be zlib wrapped, but some servers send raw deflate data instead
+        Encoding::Deflate => match ZlibDecoder::new(body).take(limit).read_to_end(&mut decoded) {
+            Ok(n) => Ok(n),
+            Err(_) => {
+                decoded.clear();
+                DeflateDecoder::new(body).take(limit).read_to_end(&mut decoded)
+            }
+        },
+        Encoding::Brotli => Decompressor::new(body, 4096).take(limit).read_to_end(&mut decoded),
+        Encoding::Zstd => ZstdDecoder::new(body).and_then(|decoder| decoder.take(limit).read_to_end(&mut decoded)),
+    };
+    try!(result.map_err(|e| format!("not valid {} ({})", encoding.name(), e)));
+    if decoded.len() as u64 > MAX_DECODE_BYTES {
+        return Err(format!("larger than {} bytes once decoded from {}", MAX_DECODE_BYTES, encoding.name()));
+    }
+    Ok(decoded)
+}
+
+/// Decodes a body as ``decode`` does, returning its decoded size and how long decoding took in
+/// milliseconds
+///
+/// # Failures
+///
+/// Returns an Err as ``decode`` does.
+pub fn decode_timed(content_encoding: &str, body: &[u8]) -> Result<(u64, f64), String> {
+    let start = time::precise_time_ns();
+    let decoded = try!(decode(content_encoding, body));
+    let elapsed_ms = (time::precise_time_ns() - start) as f64 / 1_000_000.0;
+    Ok((decoded.len() as u64, elapsed_ms))
+}
+
+/// How the responses asked for with one encoding compared to the uncompressed responses
+///
+/// Sizes and times are the medians of the samples which were measured.
+#[derive(RustcEncodable, Debug, Clone, PartialEq)]
+pub struct Savings {
+    /// The encoding asked for
+    pub encoding: Encoding,
+
+    /// How many samples were measured
+    pub samples: usize,
+
+    /// How many samples couldn't be measured
+    pub failures: usize,
+
+    /// The ``Content-Encoding`` the server responded with, if any
+    pub content_encoding: Option<String>,
+
+    /// The HTTP status code of the first response, if one was received
+    pub status: Option<u16>,
+
+    /// Why the last sample which failed couldn't be measured, if any failed
+    pub error: Option<String>,
+
+    /// The time in milliseconds the request took
+    pub latency_ms: Option<i64>,
+
+    /// The size of the body as sent, in bytes
+    pub bytes: Option<u64>,
+
+    /// The size of the body once decoded, in bytes
+    pub decompressed_bytes: Option<u64>,
+
+    /// How long decoding the body took, in milliseconds
+    pub decompress_ms: Option<f64>,
+
+    /// How many fewer bytes were sent than for the uncompressed response
+    pub saved_bytes: Option<i64>,
+
+    /// ``saved_bytes`` as a percentage of the uncompressed response's size
+    pub saved_pct: Option<f64>,
+
+    /// How much less time the request took than the uncompressed request, in milliseconds
+    pub saved_ms: Option<i64>,
+}
+
+/// Compares the samples of requesting a url with each encoding to its samples with ``identity``,
+/// using the median of each measurement
+///
+/// Each sample is the outcome of requesting the whole body (see ``Measure::FullBody``) with only
+/// that encoding accepted. Savings are only given when there is an uncompressed sample to compare to.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::compression::{savings, Encoding};
+///
+/// let report = savings(vec![(Encoding::Identity, vec![Err("refused".to_string())])]);
+/// assert_eq!(report[0].error, Some("refused".to_string()));
+/// assert_eq!((report[0].samples, report[0].failures), (0, 1));
+/// assert_eq!(report[0].saved_bytes, None);
+/// ```
+pub fn savings(results: Vec<(Encoding, Vec<Result<Latency, String>>)>) -> Vec<Savings> {
+    let summaries: Vec<Savings> = results.into_iter().map(|(encoding, samples)| summarise(encoding, samples)).collect();
+    let baseline = summaries.iter()
+                            .find(|s| s.encoding == Encoding::Identity)
+                            .and_then(|s| match (s.bytes, s.latency_ms) {
+                                (Some(bytes), Some(ms)) => Some((bytes, ms)),
+                                _ => None,
+                            });
+    summaries.into_iter().map(|summary| {
+        let compared = match (baseline, summary.bytes, summary.latency_ms) {
+            (Some((base_bytes, base_ms)), Some(bytes), Some(ms)) => Some((base_bytes as i64 - bytes as i64, base_bytes, base_ms - ms)),
+            _ => None,
+        };
+        Savings {
+            saved_bytes: compared.map(|c| c.0),
+            saved_pct: compared.and_then(|c| if c.1 > 0 { Some(c.0 as f64 * 100.0 / c.1 as f64) } else { None }),
+            saved_ms: compared.map(|c| c.2),
+            ..summary
+        }
+    }).collect()
+}
+
+/// The medians of the samples for one encoding, without any comparison
+fn summarise(encoding: Encoding, samples: Vec<Result<Latency, String>>) -> Savings {
+    let mut measured = Vec::new();
+    let mut failures = 0;
+    let mut error = None;
+    for sample in samples {
+        match sample {
+            Ok(latency) => measured.push(latency),
+            Err(err) => {
+                failures += 1;
+                error = Some(err);
+            }
+        }
+    }
+    Savings {
+        encoding: encoding,
+        samples: measured.len(),
+        failures: failures,
+        content_encoding: measured.first().and_then(|l| l.content_encoding.clone()),
+        status: measured.first().and_then(|l| l.status),
+        error: error,
+        latency_ms: median(measured.iter().map(|l| l.latency_ms).collect()),
+        bytes: median(measured.iter().filter_map(|l| l.bytes).collect()),
+        decompressed_bytes: median(measured.iter().filter_map(|l| l.decompressed_bytes).collect()),
+        decompress_ms: median(measured.iter().filter_map(|l| l.decompress_ms).collect()),
+        saved_bytes: None,
+        saved_pct: None,
+        saved_ms: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use Latency;
+    use target::Measure;
+
+    /// ``text`` repeated enough times to compress well
+    fn text() -> Vec<u8> {
+        (0..200).flat_map(|_| b"the quick brown fox ".to_vec()).collect()
+    }
+
+    #[test]
+    /// Each supported encoding should decode to the original body
+    fn decodes() {
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::Default);
+        gzip.write_all(&text()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        assert_eq!(decode("gzip", &gzip), Ok(text()));
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::Default);
+        zlib.write_all(&text()).unwrap();
+        assert_eq!(decode("deflate", &zlib.finish().unwrap()), Ok(text()));
+
+        let mut raw = DeflateEncoder::new(Vec::new(), Compression::Default);
+        raw.write_all(&text()).unwrap();
+        assert_eq!(decode("Deflate", &raw.finish().unwrap()), Ok(text()));
+
+        // ``text`` compressed by the reference brotli encoder at quality 11
+        let brotli = [27, 159, 15, 248, 141, 148, 110, 230, 162, 6, 49, 163, 195, 83, 104, 111, 57, 200, 36, 167,
+                      229, 8, 219, 116, 84, 22, 72, 215, 70, 7, 0, 30];
+        assert_eq!(decode("br", &brotli), Ok(text()));
+
+        let zstd = ::zstd::encode_all(&text()[..], 3).unwrap();
+        assert_eq!(decode("zstd", &zstd), Ok(text()));
+
+        let (size, ms) = decode_timed("identity, gzip", &gzip).unwrap();
+        assert_eq!(size, text().len() as u64);
+        assert!(ms >= 0.0);
+        assert!(decode("br", &gzip).unwrap_err().starts_with("not valid br"));
+        assert!(decode("zstd", &gzip).unwrap_err().starts_with("not valid zstd"));
+    }
+
+    #[test]
+    /// A body which decodes to more than the limit should be refused rather than decoded in full
+    fn limits_decoded_size() {
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::Best);
+        let zeros = vec![0; 1024 * 1024];
+        for _ in 0..(MAX_DECODE_BYTES / zeros.len() as u64 + 1) {
+            gzip.write_all(&zeros).unwrap();
+        }
+        let bomb = gzip.finish().unwrap();
+        assert!(bomb.len() < 1024 * 1024);
+        assert_eq!(decode("gzip", &bomb), Err(format!("larger than {} bytes once decoded from gzip", MAX_DECODE_BYTES)));
+    }
+
+    /// A full body result of ``bytes`` taking ``latency_ms``, sent with ``content_encoding``
+    fn result(latency_ms: i64, bytes: u64, content_encoding: Option<&str>) -> Result<Latency, String> {
+        Ok(Latency {
+            url: "http://www.example.com/".to_string(),
+            method: "GET".to_string(),
+            started_at: "2016-01-01T00:00:00.000Z".to_string(),
+            latency_ms: latency_ms,
+            status: Some(200),
+            measure: Measure::FullBody,
+            bytes: Some(bytes),
+            content_encoding: content_encoding.map(|e| e.to_string()),
+            decompressed_bytes: content_encoding.map(|_| 4000),
+            decompress_ms: content_encoding.map(|_| 0.5),
+            ..Latency::default()
+        })
+    }
+
+    #[test]
+    /// Savings should be relative to the uncompressed response, including when nothing was saved
+    fn compares_to_identity() {
+        let report = savings(vec![(Encoding::Identity, vec![result(200, 4000, None)]),
+                                  (Encoding::Gzip, vec![result(150, 1000, Some("gzip"))]),
+                                  (Encoding::Zstd, vec![result(210, 4000, None)])]);
+        assert_eq!(report[0].saved_bytes, Some(0));
+        assert_eq!(report[1].saved_bytes, Some(3000));
+        assert_eq!(report[1].saved_pct, Some(75.0));
+        assert_eq!(report[1].saved_ms, Some(50));
+        assert_eq!(report[1].decompressed_bytes, Some(4000));
+        assert_eq!(report[2].content_encoding, None);
+        assert_eq!(report[2].saved_ms, Some(-10));
+    }
+
+    #[test]
+    /// Several samples should be compared by their medians, leaving out the ones which failed
+    fn compares_medians() {
+        let report = savings(vec![(Encoding::Identity, vec![result(300, 4000, None), result(200, 4000, None),
+                                                            result(900, 4000, None)]),
+                                  (Encoding::Gzip, vec![result(100, 1000, Some("gzip")), Err("timed out".to_string()),
+                                                        result(120, 1000, Some("gzip")), result(2000, 1000, Some("gzip"))])]);
+        assert_eq!(report[0].latency_ms, Some(300));
+        assert_eq!((report[1].samples, report[1].failures), (3, 1));
+        assert_eq!(report[1].error, Some("timed out".to_string()));
+        assert_eq!(report[1].latency_ms, Some(120));
+        assert_eq!(report[1].saved_ms, Some(180));
+        assert_eq!(report[1].saved_bytes, Some(3000));
+    }
+}
@@ -0,0 +1,529 @@
+//! Reading probe targets from a TOML config file.
+//!
+//! A config file sets defaults for every target, named groups of settings, and the targets
+//! themselves. Settings are applied in that order, so a target's own settings override its
+//! group's, which override the defaults:
+//!
+//! ```toml
+//! [defaults]
+//! timeout = "5s"
+//! expect_status = [200]
+//!
+//! [groups.api]
+//! method = "HEAD"
+//! headers = { Accept = "application/json" }
+//!
+//! [[target]]
+//! url = "https://www.example.com/"
+//!
+//! [[target]]
+//! url = "https://api.example.com/health"
+//! group = "api"
+//! max_latency = "300ms"
+//! ```
+//!
+//! A target which sets ``upload_size`` (e.g. ``"10MB"`` of random bytes) or ``upload_file`` sends
+//! that body instead of a bare request (see ``upload``), with ``POST`` unless a method is given.
+//! ``chunked`` and ``expect_continue`` choose how uploads are sent.
+//!
+//! A target's ``url`` may be a template (see ``template``), such as ``"shard{1..16}.example.com"``,
+//! giving a target with the same settings for every url it expands to.
+//!
+//! Every setting is checked when the file is loaded and all problems are reported at once, each
+//! with the line of the table it was found in.
+
+use std::collections::BTreeMap;
+
+use toml::{Parser, Table, Value};
+
+use canonical;
+use target::{parse_duration_ms, parse_method, Measure, Target};
+use template;
+use upload::{self, Payload, UploadPolicy};
+
+/// The settings which may be given in ``[defaults]``, a group or a target
+const SETTINGS: [&'static str; 10] = ["method", "headers", "timeout", "expect_status", "max_latency", "measure",
+                                      "upload_size", "upload_file", "chunked", "expect_continue"];
+
+/// Settings found in one table, any of which may be missing
+#[derive(Debug, Clone, Default)]
+struct Settings {
+    method: Option<String>,
+    headers: Vec<(String, String)>,
+    timeout_ms: Option<u64>,
+    expect_status: Option<Vec<u16>>,
+    max_latency_ms: Option<u64>,
+    measure: Option<Measure>,
+    /// Set by either ``upload_size`` or ``upload_file``
+    upload: Option<Payload>,
+    chunked: Option<bool>,
+    expect_continue: Option<bool>,
+}
+
+impl Settings {
+    /// Returns these settings with any given in ``over`` taking precedence. Headers are combined.
+    fn overridden_by(&self, over: &Settings) -> Settings {
+        let mut headers: Vec<(String, String)> = self.headers.iter()
+            .filter(|h| !over.headers.iter().any(|o| o.0.eq_ignore_ascii_case(&h.0)))
+            .cloned()
+            .collect();
+        headers.extend(over.headers.iter().cloned());
+        Settings {
+            method: over.method.clone().or(self.method.clone()),
+            headers: headers,
+            timeout_ms: over.timeout_ms.or(self.timeout_ms),
+            expect_status: over.expect_status.clone().or(self.expect_status.clone()),
+            max_latency_ms: over.max_latency_ms.or(self.max_latency_ms),
+            measure: over.measure.or(self.measure),
+            upload: over.upload.clone().or(self.upload.clone()),
+            chunked: over.chunked.or(self.chunked),
+            expect_continue: over.expect_continue.or(self.expect_continue),
+        }
+    }
+}
+
+/// True if ``path`` names a config file rather than a plain list of urls
+pub fn is_config_file(path: &str) -> bool {
+    path.ends_with(".toml")
+}
+
+/// Parses the targets from the contents of a config file
+///
+/// # Failures
+///
+/// Returns an Err with one line per problem found, each naming the line and field concerned.
+///
+/// # Examples
+///
+/// ```
+/// let targets = httplatency::config::parse_targets(r#"
+///     [defaults]
+///     timeout = "2s"
+///
+///     [[target]]
+///     url = "www.google.com"
+///     method = "HEAD"
+/// "#).unwrap();
+/// assert_eq!(targets[0].url, "http://www.google.com/");
+/// assert_eq!(targets[0].method, "HEAD");
+/// assert_eq!(targets[0].timeout_ms, Some(2000));
+/// ```
+///
+/// ```
+/// let err = httplatency::config::parse_targets(r#"
+///     [[target]]
+///     url = "www.google.com"
+///     timeout = 2
+/// "#).unwrap_err();
+/// assert_eq!(err, "line 2: target 1 (www.google.com): timeout: expected a duration string such as \"2s\", found integer");
+/// ```
+pub fn parse_targets(source: &str) -> Result<Vec<Target>, String> {
+    parse_numbered(source).map(|targets| targets.into_iter().map(|(_, t)| t).collect())
+}
+
+/// Parses the targets from the contents of a config file as ``parse_targets`` does, along with
+/// the number of the ``[[target]]`` table each came from, starting from 1
+///
+/// # Examples
+///
+/// ```
+/// let targets = httplatency::config::parse_numbered(r#"
+///     [[target]]
+///     url = "{eu,us}.example.com"
+///
+///     [[target]]
+///     url = "www.example.com"
+/// "#).unwrap();
+/// let numbers: Vec<usize> = targets.iter().map(|t| t.0).collect();
+/// assert_eq!(numbers, vec![1, 1, 2]);
+/// ```
+pub fn parse_numbered(source: &str) -> Result<Vec<(usize, Target)>, String> {
+    let mut parser = Parser::new(source);
+    let root = match parser.parse() {
+        Some(table) => table,
+        None => {
+            let errors: Vec<String> = parser.errors.iter().map(|e| {
+                let (line, col) = parser.to_linecol(e.lo);
+                format!("line {}, column {}: {}", line + 1, col + 1, e.desc)
+            }).collect();
+            return Err(errors.join("\n"));
+        }
+    };
+
+    let mut errors = Vec::new();
+    for key in root.keys() {
+        if key != "defaults" && key != "groups" && key != "target" {
+            errors.push(format!("unknown section '{}', expected defaults, groups or target", key));
+        }
+    }
+
+    let defaults = match root.get("defaults") {
+        Some(&Value::Table(ref table)) => {
+            let context = format!("line {}: defaults", header_line(source, "[defaults]", 0));
+            parse_settings(table, &context, &[], &mut errors)
+        }
+        Some(other) => {
+            errors.push(format!("defaults: expected a table, found {}", other.type_str()));
+            Settings::default()
+        }
+        None => Settings::default(),
+    };
+
+    let mut groups = BTreeMap::new();
+    match root.get("groups") {
+        Some(&Value::Table(ref table)) => {
+            for (name, group) in table.iter() {
+                let header = format!("[groups.{}]", name);
+                let context = format!("line {}: group {}", header_line(source, &header, 0), name);
+                match *group {
+                    Value::Table(ref t) => {
+                        groups.insert(name.clone(), parse_settings(t, &context, &[], &mut errors));
+                    }
+                    ref other => errors.push(format!("{}: expected a table, found {}", context, other.type_str())),
+                }
+            }
+        }
+        Some(other) => errors.push(format!("groups: expected a table, found {}", other.type_str())),
+        None => (),
+    }
+
+    let mut targets = Vec::new();
+    match root.get("target") {
+        Some(&Value::Array(ref array)) => {
+            for (i, value) in array.iter().enumerate() {
+                let table = match *value {
+                    Value::Table(ref t) => t,
+                    ref other => {
+                        errors.push(format!("target {}: expected a table, found {}", i + 1, other.type_str()));
+                        continue;
+                    }
+                };
+                let url = table.get("url").and_then(|u| u.as_str()).unwrap_or("");
+                let context = format!("line {}: target {} ({})", header_line(source, "[[target]]", i), i + 1, url);
+                for target in parse_target(table, &context, &defaults, &groups, &mut errors) {
+                    targets.push((i + 1, target));
+                }
+            }
+        }
+        Some(other) => errors.push(format!("target: expected [[target]] tables, found {}", other.type_str())),
+        None => errors.push("no targets found, add one with a [[target]] table".to_string()),
+    }
+
+    if errors.is_empty() { Ok(targets) } else { Err(errors.join("\n")) }
+}
+
+/// Builds the targets for a table, one for each url its ``url`` expands to, recording any
+/// problems in ``errors``
+fn parse_target(table: &Table, context: &str, defaults: &Settings, groups: &BTreeMap<String, Settings>,
+                errors: &mut Vec<String>) -> Vec<Target> {
+    let errors_before = errors.len();
+    let own = parse_settings(table, context, &["url", "group"], errors);
+
+    let (urls, expanded_from) = match table.get("url") {
+        Some(&Value::String(ref u)) => match urls(u) {
+            Ok(urls) => (urls, if template::is_template(u) { Some(u.clone()) } else { None }),
+            Err(err) => {
+                errors.push(format!("{}: url: {}", context, err));
+                (Vec::new(), None)
+            }
+        },
+        Some(other) => {
+            errors.push(format!("{}: url: expected a string, found {}", context, other.type_str()));
+            (Vec::new(), None)
+        }
+        None => {
+            errors.push(format!("{}: url: missing", context));
+            (Vec::new(), None)
+        }
+    };
+
+    let (group, base) = match table.get("group") {
+        Some(&Value::String(ref name)) => match groups.get(name) {
+            Some(settings) => (Some(name.clone()), defaults.overridden_by(settings)),
+            None => {
+                errors.push(format!("{}: group: no group named '{}'", context, name));
+                (None, defaults.clone())
+            }
+        },
+        Some(other) => {
+            errors.push(format!("{}: group: expected a string, found {}", context, other.type_str()));
+            (None, defaults.clone())
+        }
+        None => (None, defaults.clone()),
+    };
+
+    if errors.len() > errors_before {
+        return Vec::new();
+    }
+    let settings = base.overridden_by(&own);
+    let upload = settings.upload.clone().map(|payload| UploadPolicy {
+        payload: payload,
+        chunked: settings.chunked.unwrap_or(false),
+        expect_continue: settings.expect_continue.unwrap_or(false),
+    });
+    let method = if upload.is_some() { "POST" } else { "GET" };
+    urls.into_iter().map(|url| Target {
+        url: url.url,
+        method: settings.method.clone().unwrap_or(method.to_string()),
+        headers: settings.headers.clone(),
+        body: None,
+        timeout_ms: settings.timeout_ms,
+        expect_status: settings.expect_status.clone().unwrap_or(Vec::new()),
+        max_latency_ms: settings.max_latency_ms,
+        group: group.clone(),
+        scheme_guessed: url.scheme_guessed,
+        fallback_url: None,
+        template: expanded_from.clone(),
+        resolve: Vec::new(),
+        insecure: false,
+        linked_from: None,
+        measure: settings.measure,
+        throughput: None,
+        upload: upload.clone(),
+    }).collect()
+}
+
+/// Expands a target's url if it's a template and canonicalizes each url
+fn urls(url: &str) -> Result<Vec<canonical::Canonical>, String> {
+    let expanded = if template::is_template(url) {
+        try!(template::expand(url).map_err(|e| format!("'{}' is not a valid template: {}", url, e)))
+    } else {
+        vec![url.to_string()]
+    };
+    expanded.iter().map(|u| {
+        canonical::parse(u).map_err(|err| format!("'{}' is not a valid http(s) url: {}", u, err))
+    }).collect()
+}
+
+/// Reads the settings from a table, recording any problems in ``errors``. Keys in ``extra`` are
+/// allowed in the table but otherwise ignored.
+fn parse_settings(table: &Table, context: &str, extra: &[&str], errors: &mut Vec<String>) -> Settings {
+    let mut settings = Settings::default();
+    for (key, value) in table.iter() {
+        let result = match &key[..] {
+            "method" => string(value).and_then(|m| parse_method(m)).map(|m| settings.method = Some(m)),
+            "headers" => headers(value).map(|h| settings.headers = h),
+            "timeout" => duration(value).map(|d| settings.timeout_ms = Some(d)),
+            "max_latency" => duration(value).map(|d| settings.max_latency_ms = Some(d)),
+            "expect_status" => statuses(value).map(|s| settings.expect_status = Some(s)),
+            "measure" => string(value).and_then(|m| measure(m)).map(|m| settings.measure = Some(m)),
+            "upload_file" if table.contains_key("upload_size") => {
+                Err("give either upload_size or upload_file, not both".to_string())
+            }
+            "upload_size" => size(value).map(|s| settings.upload = Some(Payload::Random(s))),
+            "upload_file" => string(value).map(|f| settings.upload = Some(Payload::File(f.to_string()))),
+            "chunked" => boolean(value).map(|b| settings.chunked = Some(b)),
+            "expect_continue" => boolean(value).map(|b| settings.expect_continue = Some(b)),
+            k if extra.contains(&k) => Ok(()),
+            _ => Err(format!("unknown field, expected one of {}", SETTINGS.join(", "))),
+        };
+        if let Err(err) = result {
+            errors.push(format!("{}: {}: {}", context, key, err));
+        }
+    }
+    settings
+}
+
+fn string(value: &Value) -> Result<&str, String> {
+    value.as_str().ok_or(format!("expected a string, found {}", value.type_str()))
+}
+
+fn boolean(value: &Value) -> Result<bool, String> {
+    value.as_bool().ok_or(format!("expected true or false, found {}", value.type_str()))
+}
+
+fn size(value: &Value) -> Result<u64, String> {
+    match *value {
+        Value::String(ref s) => upload::parse_size(s),
+        Value::Integer(i) if i >= 0 => upload::check_size(i as u64),
+        ref other => Err(format!("expected a size such as \"10MB\" or a number of bytes, found {}", other)),
+    }
+}
+
+fn measure(name: &str) -> Result<Measure, String> {
+    Measure::from_name(name).ok_or(format!("unknown measure '{}', expected headers, first-byte or full-body", name))
+}
+
+fn duration(value: &Value) -> Result<u64, String> {
+    match *value {
+        Value::String(ref s) => parse_duration_ms(s),
+        ref other => Err(format!("expected a duration string such as \"2s\", found {}", other.type_str())),
+    }
+}
+
+fn headers(value: &Value) -> Result<Vec<(String, String)>, String> {
+    let table = try!(value.as_table().ok_or(format!("expected a table of names to values, found {}", value.type_str())));
+    let mut headers = Vec::new();
+    for (name, v) in table.iter() {
+        match v.as_str() {
+            Some(s) => headers.push((name.clone(), s.to_string())),
+            None => return Err(format!("header {} should be a string, found {}", name, v.type_str())),
+        }
+    }
+    Ok(headers)
+}
+
+fn statuses(value: &Value) -> Result<Vec<u16>, String> {
+    let status = |v: &Value| match v.as_integer() {
+        Some(i) if i >= 100 && i <= 599 => Ok(i as u16),
+        _ => Err(format!("expected status codes between 100 and 599, found {}", v)),
+    };
+    match *value {
+        Value::Integer(_) => status(value).map(|s| vec![s]),
+        Value::Array(ref array) => array.iter().map(|v| status(v)).collect(),
+        ref other => Err(format!("expected a status code or list of status codes, found {}", other.type_str())),
+    }
+}
+
+/// Finds the (1-based) line of the ``index``th occurrence of a table header, or 0 if not found
+fn header_line(source: &str, header: &str, index: usize) -> usize {
+    source.lines()
+          .enumerate()
+          .filter(|&(_, line)| line.trim().starts_with(header))
+          .nth(index)
+          .map(|(i, _)| i + 1)
+          .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    /// Settings should come from the target, then its group, then the defaults
+    fn precedence() {
+        let targets = parse_targets(r#"
+[defaults]
+timeout = "5s"
+method = "GET"
+headers = { Accept = "text/html", X-Team = "web" }
+measure = "full-body"
+
+[groups.api]
+method = "HEAD"
+headers = { Accept = "application/json" }
+expect_status = [200, 204]
+
+[[target]]
+url = "http://api.example.com/health"
+group = "api"
+timeout = "1s"
+"#).unwrap();
+        let t = &targets[0];
+        assert_eq!(t.method, "HEAD");
+        assert_eq!(t.timeout_ms, Some(1000));
+        assert_eq!(t.expect_status, vec![200, 204]);
+        assert_eq!(t.group, Some("api".to_string()));
+        assert!(t.headers.contains(&("Accept".to_string(), "application/json".to_string())));
+        assert!(t.headers.contains(&("X-Team".to_string(), "web".to_string())));
+        assert_eq!(t.headers.len(), 2);
+        assert_eq!(t.measure, Some(::target::Measure::FullBody));
+    }
+
+    #[test]
+    /// Targets without any settings should be plain GET requests
+    fn plain_target() {
+        let targets = parse_targets("[[target]]\nurl = \"http://www.example.com/\"\n").unwrap();
+        assert_eq!(targets, vec![::target::Target::get("http://www.example.com/".to_string())]);
+    }
+
+    #[test]
+    /// Every problem should be reported with its line, not just the first
+    fn reports_every_error() {
+        let err = parse_targets(r#"
+[[target]]
+url = "ftp://www.example.com/"
+
+[[target]]
+url = "http://www.example.com/"
+group = "missing"
+expect_status = [200, 999]
+timout = "2s"
+"#).unwrap_err();
+        let lines: Vec<&str> = err.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", err);
+        assert!(lines.iter().any(|l| l.starts_with("line 2: target 1") && l.contains("not a valid http(s) url")));
+        assert!(lines.iter().any(|l| l.starts_with("line 5: target 2") && l.contains("no group named 'missing'")));
+        assert!(lines.iter().any(|l| l.contains("expect_status") && l.contains("999")));
+        assert!(lines.iter().any(|l| l.contains("timout: unknown field")));
+    }
+
+    #[test]
+    /// A templated url should give a target per url, each with the table's settings
+    fn templated_url() {
+        let err = parse_targets(r#"
+[[target]]
+url = "http://shard{1..3}.example.com/"
+method = "HEAD"
+
+[[target]]
+url = "http://{a,b.example.com/"
+"#).unwrap_err();
+        assert!(err.starts_with("line 6: target 2"), "{}", err);
+        assert!(err.contains("not a valid template"), "{}", err);
+
+        let targets = parse_numbered("[[target]]\nurl = \"http://shard{1..3}.example.com/\"\nmethod = \"HEAD\"\n")
+                          .unwrap();
+        assert_eq!(targets.len(), 3);
+        assert_eq!(targets[2].0, 1);
+        assert_eq!(targets[2].1.url, "http://shard3.example.com/");
+        assert_eq!(targets[2].1.method, "HEAD");
+        assert_eq!(targets[2].1.template, Some("http://shard{1..3}.example.com/".to_string()));
+    }
+
+    #[test]
+    /// Upload settings should combine like any other, with uploads using POST unless told otherwise
+    fn upload_settings() {
+        let targets = parse_targets(r#"
+[defaults]
+upload_size = "64KB"
+expect_continue = true
+
+[[target]]
+url = "http://www.example.com/upload"
+
+[[target]]
+url = "http://www.example.com/files"
+method = "PUT"
+upload_file = "report.pdf"
+chunked = true
+"#).unwrap();
+        assert_eq!(targets[0].method, "POST");
+        assert_eq!(targets[0].upload, Some(UploadPolicy {
+            payload: Payload::Random(64 * 1024),
+            chunked: false,
+            expect_continue: true,
+        }));
+        assert_eq!(targets[1].method, "PUT");
+        assert_eq!(targets[1].upload, Some(UploadPolicy {
+            payload: Payload::File("report.pdf".to_string()),
+            chunked: true,
+            expect_continue: true,
+        }));
+
+        let err = parse_targets("[[target]]\nurl = \"www.example.com\"\nupload_size = \"1MB\"\nupload_file = \"a\"\n")
+                      .unwrap_err();
+        assert!(err.contains("not both"), "{}", err);
+        assert!(parse_targets("[[target]]\nurl = \"www.example.com\"\nupload_size = \"lots\"\n").is_err());
+    }
+
+    #[test]
+    /// Syntax errors should point to the line and column
+    fn syntax_error() {
+        let err = parse_targets("[[target]]\nurl = \"http://www.example.com/\n").unwrap_err();
+        assert!(err.starts_with("line 2, column"), "{}", err);
+    }
+
+    #[test]
+    /// A file with no targets is almost certainly a mistake
+    fn no_targets() {
+        assert!(parse_targets("[defaults]\ntimeout = \"1s\"\n").unwrap_err().contains("no targets"));
+    }
+
+    #[test]
+    /// Config files are recognised by extension
+    fn config_extension() {
+        assert!(is_config_file("probes.toml"));
+        assert!(!is_config_file("urls.txt"));
+    }
+}
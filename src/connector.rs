@@ -0,0 +1,126 @@
+//! Connecting to servers the way a target asks, rather than as hyper's default connector would.
+//!
+//! A target may give addresses to use instead of looking up host names (like curl's
+//! ``--resolve``), or ask for the server's TLS certificate not to be verified (like curl's ``-k``).
+//! The connector also times each step of making a connection, so a request's latency can be split
+//! into phases. Its connections can also be used directly to send uploads (see ``upload``).
+
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper;
+use hyper::net::{HttpStream, HttpsStream, NetworkConnector, NetworkStream, Openssl, Ssl};
+use openssl::ssl::{SslContext, SslMethod, SSL_VERIFY_NONE};
+use time;
+
+use elapsed_ms;
+use target::Target;
+use upload;
+
+/// How long each step of the last connection made took, in milliseconds, with -1 for steps which
+/// weren't taken (such as the TLS handshake of an http connection)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectTimings {
+    /// Looking up the host's addresses
+    pub dns_ms: i64,
+
+    /// Opening the TCP connection
+    pub connect_ms: i64,
+
+    /// The TLS handshake
+    pub ssl_ms: i64,
+}
+
+impl Default for ConnectTimings {
+    fn default() -> ConnectTimings {
+        ConnectTimings { dns_ms: -1, connect_ms: -1, ssl_ms: -1 }
+    }
+}
+
+/// Connects to the addresses given by a target's ``resolve``, verifying certificates unless the
+/// target is ``insecure``
+pub struct TargetConnector {
+    resolve: Vec<(String, u16, String)>,
+    ssl: Openssl,
+    timings: Arc<Mutex<ConnectTimings>>,
+}
+
+impl TargetConnector {
+    /// A connector for the requests of ``target``
+    ///
+    /// # Failures
+    ///
+    /// Returns an Err if an OpenSSL context can't be created for an insecure target.
+    pub fn new(target: &Target) -> Result<TargetConnector, String> {
+        let ssl = if target.insecure {
+            let mut context = try!(SslContext::new(SslMethod::Sslv23).map_err(|e| e.to_string()));
+            context.set_verify(SSL_VERIFY_NONE, None);
+            Openssl { context: Arc::new(context) }
+        } else {
+            Openssl::default()
+        };
+        Ok(TargetConnector { resolve: target.resolve.clone(), ssl: ssl, timings: Arc::new(Mutex::new(Default::default())) })
+    }
+
+    /// The timings of the last connection made, which are updated as the connector is used
+    pub fn timings(&self) -> Arc<Mutex<ConnectTimings>> {
+        self.timings.clone()
+    }
+
+    /// The address to connect to for ``host`` and ``port``
+    fn address<'a>(&'a self, host: &'a str, port: u16) -> &'a str {
+        self.resolve.iter()
+            .find(|r| r.0.eq_ignore_ascii_case(host) && r.1 == port)
+            .map(|r| r.2.trim_left_matches('[').trim_right_matches(']'))
+            .unwrap_or(host)
+    }
+}
+
+impl NetworkConnector for TargetConnector {
+    type Stream = HttpsStream<<Openssl as Ssl>::Stream>;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<Self::Stream> {
+        let address = self.address(host, port);
+        if address != host {
+            debug!("Connecting to {} for {}:{}", address, host, port);
+        }
+        let mut timings = ConnectTimings::default();
+        let start = time::precise_time_ns();
+        let addresses: Vec<_> = try!((address, port).to_socket_addrs()).collect();
+        let resolved = time::precise_time_ns();
+        timings.dns_ms = elapsed_ms(start, resolved);
+        let mut result = Err(io::Error::new(io::ErrorKind::NotFound, format!("no addresses found for {}", address)));
+        for a in addresses.iter() {
+            result = TcpStream::connect(a);
+            if result.is_ok() {
+                break;
+            }
+        }
+        let stream = HttpStream(try!(result));
+        let connected = time::precise_time_ns();
+        timings.connect_ms = elapsed_ms(resolved, connected);
+        let stream = match scheme {
+            "http" => Ok(HttpsStream::Http(stream)),
+            // The certificate is checked against the host name, not the address connected to
+            "https" => {
+                let wrapped = self.ssl.wrap_client(stream, host).map(HttpsStream::Https);
+                timings.ssl_ms = elapsed_ms(connected, time::precise_time_ns());
+                wrapped
+            }
+            _ => Err(hyper::Error::Io(io::Error::new(io::ErrorKind::InvalidInput,
+                                                     format!("unsupported scheme '{}'", scheme)))),
+        };
+        if let Ok(mut last) = self.timings.lock() {
+            *last = timings;
+        }
+        stream
+    }
+}
+
+impl upload::Connection for HttpsStream<<Openssl as Ssl>::Stream> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        NetworkStream::set_read_timeout(self, timeout)
+    }
+}
@@ -0,0 +1,783 @@
+//! Discovering probe targets by crawling a site from seed urls.
+//!
+//! Each seed's HTML is fetched and the links in its ``<a>`` and ``<area>`` tags followed, as long
+//! as they stay on the same host as the page linking to them, until pages are ``max_depth`` links
+//! away from a seed or ``max_pages`` pages have been found. Links marked ``rel="nofollow"`` aren't
+//! followed. Each host's ``robots.txt`` is honoured, using the rules for ``httplatency`` if it has
+//! any and otherwise those for every robot (``*``), so a disallowed page is neither fetched nor
+//! probed. As RFC 9309 asks, a robots.txt which is missing (a 4xx response) allows everything,
+//! while one which can't be fetched (a 5xx response or no response) disallows everything.
+//!
+//! Every download waits for its host's limits, and a ``Crawl-delay`` in robots.txt slows later
+//! requests to the host down further, including the probes made once crawling has finished.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::thread;
+use std::time::Duration;
+
+use time;
+
+use canonical;
+use http_host;
+use ratelimit::HostLimiter;
+
+/// The name robots.txt rules are looked up for
+pub const ROBOTS_AGENT: &'static str = "httplatency";
+
+/// The longest ``Crawl-delay`` honoured, in seconds, so a robots.txt can't stall a run. Longer
+/// delays are shortened to this.
+pub const MAX_CRAWL_DELAY_SECS: f64 = 60.0;
+
+/// A document downloaded while crawling
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    /// The url the document was finally downloaded from, after any redirects
+    pub url: String,
+
+    /// The status code of the response
+    pub status: u16,
+
+    /// The document's ``Content-Type``, if the server gave one
+    pub content_type: Option<String>,
+
+    /// The body of the document
+    pub body: Vec<u8>,
+}
+
+/// How far to crawl from the seed urls
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CrawlPolicy {
+    /// The most links a page may be away from a seed. Seeds are at depth 0.
+    pub max_depth: usize,
+
+    /// The most pages to find, not counting the seeds
+    pub max_pages: usize,
+}
+
+impl Default for CrawlPolicy {
+    fn default() -> CrawlPolicy {
+        CrawlPolicy { max_depth: 2, max_pages: 100 }
+    }
+}
+
+/// A page found by crawling
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    /// The url of the page
+    pub url: String,
+
+    /// The index of the seed the page was found from
+    pub seed: usize,
+
+    /// How many links the page is away from its seed
+    pub depth: usize,
+
+    /// The page which first linked to this one, or None for a seed
+    pub linked_from: Option<String>,
+}
+
+/// Crawls from ``seeds``, downloading pages and robots.txt files with ``fetch`` once ``limiter``
+/// allows each request to be sent
+///
+/// Returns the pages found in the order they were found, without the seeds themselves, along with
+/// a warning for each page which couldn't be fetched or which robots.txt disallowed. The seeds are
+/// always crawled from, but only pages found from them count towards ``max_pages``. A host's
+/// ``Crawl-delay`` is passed on to ``limiter``.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::crawl::{crawl, CrawlPolicy, Document};
+/// use httplatency::ratelimit::{HostLimiter, HostPolicy};
+///
+/// let mut limiter = HostLimiter::new(HostPolicy::unlimited());
+/// let (pages, warnings) = crawl(&["http://www.example.com/".to_string()], &CrawlPolicy::default(), &mut limiter, |url| {
+///     match url {
+///         "http://www.example.com/" => Ok(Document {
+///             url: url.to_string(),
+///             status: 200,
+///             content_type: Some("text/html".to_string()),
+///             body: b"<a href=about>About</a> <a href='http://other.example.com/'>Elsewhere</a>".to_vec(),
+///         }),
+///         _ => Ok(Document { url: url.to_string(), status: 404, content_type: None, body: vec![] }),
+///     }
+/// });
+/// let urls: Vec<&str> = pages.iter().map(|p| &p.url[..]).collect();
+/// assert_eq!(urls, vec!["http://www.example.com/about"]);
+/// assert_eq!(pages[0].linked_from, Some("http://www.example.com/".to_string()));
+/// assert_eq!(warnings, vec!["http://www.example.com/about: server responded 404"]);
+/// ```
+pub fn crawl<F>(seeds: &[String], policy: &CrawlPolicy, limiter: &mut HostLimiter, mut fetch: F)
+                -> (Vec<Page>, Vec<String>)
+    where F: FnMut(&str) -> Result<Document, String>
+{
+    let mut pages = Vec::new();
+    let mut warnings = Vec::new();
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    let mut robots: HashMap<String, Robots> = HashMap::new();
+    for (i, seed) in seeds.iter().enumerate() {
+        if seen.insert(seed.clone()) {
+            queue.push_back(Page { url: seed.clone(), seed: i, depth: 0, linked_from: None });
+        }
+    }
+    while let Some(page) = queue.pop_front() {
+        // Seeds are queued first, so every seed has been crawled from before this can stop
+        let is_seed = page.linked_from.is_none();
+        if !is_seed && pages.len() >= policy.max_pages {
+            warnings.push(format!("Stopped crawling after {} pages, {} or more links weren't followed",
+                                  policy.max_pages, queue.len() + 1));
+            break;
+        }
+        let origin = origin(&page.url).to_string();
+        if !robots.contains_key(&origin) {
+            let url = format!("{}/robots.txt", origin);
+            let rules = match throttled(limiter, &url, &mut fetch) {
+                Ok(ref document) if document.status >= 200 && document.status < 300 => {
+                    Robots::parse(&String::from_utf8_lossy(&document.body), ROBOTS_AGENT)
+                }
+                Ok(ref document) if document.status >= 400 && document.status < 500 => Robots::default(),
+                Ok(document) => {
+                    warnings.push(format!("{}: server responded {}, so nothing on the host is crawled", url, document.status));
+                    Robots::disallow_all()
+                }
+                Err(err) => {
+                    warnings.push(format!("{}: {}, so nothing on the host is crawled", url, err));
+                    Robots::disallow_all()
+                }
+            };
+            if let Some(ms) = rules.crawl_delay_ms() {
+                limiter.slow_down(&limiter_host(&page.url), ms);
+            }
+            robots.insert(origin.clone(), rules);
+        }
+        if !robots[&origin].allows(&page.url[origin.len()..]) {
+            warnings.push(format!("{}: disallowed by robots.txt", page.url));
+            continue;
+        }
+        if !is_seed {
+            pages.push(page.clone());
+        }
+        if page.depth >= policy.max_depth {
+            continue;
+        }
+        let document = match throttled(limiter, &page.url, &mut fetch) {
+            Ok(ref document) if document.status < 200 || document.status >= 300 => {
+                warnings.push(format!("{}: server responded {}", page.url, document.status));
+                continue;
+            }
+            Ok(document) => document,
+            Err(err) => {
+                warnings.push(format!("{}: {}", page.url, err));
+                continue;
+            }
+        };
+        if !document.content_type.as_ref().map(|c| c.contains("html")).unwrap_or(true) {
+            continue;
+        }
+        for link in links(&document.url, &String::from_utf8_lossy(&document.body)) {
+            if host(&link) == host(&page.url) && seen.insert(link.clone()) {
+                queue.push_back(Page {
+                    url: link,
+                    seed: page.seed,
+                    depth: page.depth + 1,
+                    linked_from: Some(page.url.clone()),
+                });
+            }
+        }
+    }
+    (pages, warnings)
+}
+
+/// Fetches ``url`` with ``fetch`` once ``limiter`` allows a request to its host, waiting until then
+fn throttled<F>(limiter: &mut HostLimiter, url: &str, fetch: &mut F) -> Result<Document, String>
+    where F: FnMut(&str) -> Result<Document, String>
+{
+    let now = time::precise_time_ns();
+    let at = limiter.reserve(&limiter_host(url), now);
+    if at > now {
+        debug!("Waiting {}ms to download {}", (at - now) / 1_000_000, url);
+        let wait = at - now;
+        thread::sleep(Duration::new(wait / 1_000_000_000, (wait % 1_000_000_000) as u32));
+    }
+    fetch(url)
+}
+
+/// The name ``url``'s host is limited by, which is the same as for probes of it
+fn limiter_host(url: &str) -> String {
+    http_host(&url.to_string()).unwrap_or(url.to_string())
+}
+
+/// The canonical urls of the links in an HTML document downloaded from ``base``, in the order
+/// they appear and without duplicates
+///
+/// Only links to http(s) urls are kept, without their fragments, and links marked
+/// ``rel="nofollow"`` are left out. A ``<base href>`` changes the url links are relative to.
+///
+/// # Examples
+///
+/// ```
+/// let links = httplatency::crawl::links("https://www.example.com/docs/intro", r#"
+///     <a href="setup#install">Setup</a> <a href="/">Home</a> <a href="mailto:me@example.com">Mail</a>
+///     <!-- <a href="hidden">Hidden</a> --> <a href="/login" rel="nofollow">Log in</a>
+///     <a href="?page=2&amp;sort=new">Next</a> <a href="setup">Setup again</a>
+/// "#);
+/// assert_eq!(links, vec!["https://www.example.com/docs/setup", "https://www.example.com/",
+///                        "https://www.example.com/docs/intro?page=2&sort=new"]);
+/// ```
+pub fn links(base: &str, html: &str) -> Vec<String> {
+    let mut base = base.to_string();
+    let mut links: Vec<String> = Vec::new();
+    for tag in tags(html) {
+        let href = match tag.attribute("href") {
+            Some(href) => href,
+            None => continue,
+        };
+        let nofollow = tag.attribute("rel").map(|r| r.to_lowercase().split_whitespace().any(|r| r == "nofollow"));
+        match &tag.name[..] {
+            "a" | "area" if nofollow != Some(true) => {
+                if let Some(link) = resolve(&base, href) {
+                    if !links.contains(&link) {
+                        links.push(link);
+                    }
+                }
+            }
+            "base" => {
+                if let Some(url) = resolve(&base, href) {
+                    base = url;
+                }
+            }
+            _ => (),
+        }
+    }
+    links
+}
+
+/// A start tag in an HTML document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    /// The name of the tag, in lower case
+    pub name: String,
+
+    /// The attributes of the tag, with lower case names and decoded values
+    pub attributes: Vec<(String, String)>,
+
+    /// The text inside a ``<script>`` or ``<style>`` tag, which isn't HTML
+    pub content: Option<String>,
+}
+
+impl Tag {
+    /// The value of the attribute ``name``, if the tag has it
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|a| a.0 == name).map(|a| &a.1[..])
+    }
+}
+
+/// The start tags of an HTML document, in order, leaving out comments
+///
+/// # Examples
+///
+/// ```
+/// let tags = httplatency::crawl::tags("<P class=intro>Hi <!-- <b> --><style>a > b {}</style>");
+/// let names: Vec<&str> = tags.iter().map(|t| &t.name[..]).collect();
+/// assert_eq!(names, vec!["p", "style"]);
+/// assert_eq!(tags[0].attribute("class"), Some("intro"));
+/// assert_eq!(tags[1].content, Some("a > b {}".to_string()));
+/// ```
+pub fn tags(html: &str) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if rest.starts_with("!--") {
+            match rest.find("-->") {
+                Some(end) => rest = &rest[end + 3..],
+                None => break,
+            }
+            continue;
+        }
+        let name_end = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/').unwrap_or(rest.len());
+        let name = rest[..name_end].to_lowercase();
+        let (attributes, after) = parse_attributes(&rest[name_end..]);
+        rest = after;
+        if name.is_empty() {
+            continue;
+        }
+        let mut content = None;
+        if name == "script" || name == "style" {
+            // Their contents aren't HTML, so may contain anything which looks like a tag
+            let end = find_ignore_case(rest, &format!("</{}", name)).unwrap_or(rest.len());
+            content = Some(rest[..end].to_string());
+            rest = &rest[end..];
+        }
+        tags.push(Tag { name: name, attributes: attributes, content: content });
+    }
+    tags
+}
+
+/// Resolves a link found on the page at ``base`` to a canonical http(s) url without a fragment
+///
+/// Returns None for links to other schemes, such as ``mailto:``, and links only to a fragment of
+/// the same page.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::crawl::resolve;
+///
+/// assert_eq!(resolve("http://www.example.com/a/b?c", "../d"), Some("http://www.example.com/d".to_string()));
+/// assert_eq!(resolve("https://www.example.com/a", "//cdn.example.com/x"), Some("https://cdn.example.com/x".to_string()));
+/// assert_eq!(resolve("http://www.example.com/a", "#top"), None);
+/// assert_eq!(resolve("http://www.example.com/a", "javascript:void(0)"), None);
+/// ```
+pub fn resolve(base: &str, reference: &str) -> Option<String> {
+    let reference = reference.trim().split('#').next().unwrap_or("");
+    if reference.is_empty() {
+        return None;
+    }
+    let url = if let Some(end) = scheme_end(reference) {
+        match &reference[..end].to_lowercase()[..] {
+            "http" | "https" => reference.to_string(),
+            _ => return None,
+        }
+    } else if reference.starts_with("//") {
+        format!("{}:{}", &base[..base.find(':').unwrap_or(0)], reference)
+    } else if reference.starts_with('/') {
+        format!("{}{}", origin(base), reference)
+    } else if reference.starts_with('?') {
+        format!("{}{}", base.split('?').next().unwrap_or(base), reference)
+    } else {
+        let path = base.split('?').next().unwrap_or(base);
+        let directory_end = path.rfind('/').map(|i| i + 1).unwrap_or(path.len());
+        if directory_end <= origin(base).len() {
+            format!("{}/{}", origin(base), reference)
+        } else {
+            format!("{}{}", &path[..directory_end], reference)
+        }
+    };
+    canonical::parse(&url).ok().map(|c| c.url)
+}
+
+/// The position of the ``:`` ending the scheme of a url, if it has one, rather than one later in
+/// a relative url such as ``./b:c``
+fn scheme_end(reference: &str) -> Option<usize> {
+    let end = match reference.find(':') {
+        Some(end) if end > 0 => end,
+        _ => return None,
+    };
+    if reference[..end].chars().all(|c| c.is_alphanumeric() || c == '+' || c == '-' || c == '.') {
+        Some(end)
+    } else {
+        None
+    }
+}
+
+/// The scheme and authority of a url, e.g. ``https://www.example.com:8443``
+fn origin(url: &str) -> &str {
+    let authority_start = url.find("://").map(|i| i + 3).unwrap_or(0);
+    match url[authority_start..].find(|c: char| c == '/' || c == '?' || c == '#') {
+        Some(end) => &url[..authority_start + end],
+        None => url,
+    }
+}
+
+/// The host and port of a url, without its scheme, so http and https pages of a site are on the
+/// same host
+fn host(url: &str) -> &str {
+    let origin = origin(url);
+    let authority = &origin[origin.find("://").map(|i| i + 3).unwrap_or(0)..];
+    &authority[authority.rfind('@').map(|i| i + 1).unwrap_or(0)..]
+}
+
+/// Parses the attributes of a tag, returning them with lower case names and decoded values, along
+/// with what follows the tag
+fn parse_attributes(s: &str) -> (Vec<(String, String)>, &str) {
+    let mut attributes = Vec::new();
+    let mut rest = s;
+    loop {
+        rest = rest.trim_left_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() || rest.starts_with('>') {
+            return (attributes, if rest.is_empty() { rest } else { &rest[1..] });
+        }
+        let name_end = rest.find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/').unwrap_or(rest.len());
+        let name = rest[..name_end].to_lowercase();
+        rest = rest[name_end..].trim_left();
+        let mut value = String::new();
+        if rest.starts_with('=') {
+            rest = rest[1..].trim_left();
+            let (raw, after) = match rest.chars().next() {
+                Some(q) if q == '"' || q == '\'' => match rest[1..].find(q) {
+                    Some(end) => (&rest[1..end + 1], &rest[end + 2..]),
+                    // The rest of the document is an unclosed value, so has no tags to read
+                    None => return (attributes, ""),
+                },
+                _ => {
+                    let end = rest.find(|c: char| c.is_whitespace() || c == '>').unwrap_or(rest.len());
+                    (&rest[..end], &rest[end..])
+                }
+            };
+            value = decode_entities(raw);
+            rest = after;
+        }
+        attributes.push((name, value));
+    }
+}
+
+/// Decodes the character references commonly found in urls, such as ``&amp;``
+fn decode_entities(s: &str) -> String {
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) if end < 10 => end,
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+                u32::from_str_radix(&entity[2..], 16).ok().and_then(::std::char::from_u32)
+            }
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(::std::char::from_u32),
+            _ => None,
+        };
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// The position of ``needle`` in ``haystack``, ignoring ASCII case
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    let (haystack, needle) = (haystack.as_bytes(), needle.as_bytes());
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    (0..haystack.len() - needle.len() + 1).find(|&i| {
+        haystack[i..i + needle.len()].iter().zip(needle.iter()).all(|(a, b)| a.to_ascii_lowercase() == b.to_ascii_lowercase())
+    })
+}
+
+/// The rules of a robots.txt file which apply to one robot. The default allows everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Robots {
+    /// Each rule's path pattern and whether it allows the paths it matches
+    rules: Vec<(String, bool)>,
+
+    /// The ``Crawl-delay`` asked for, in milliseconds
+    crawl_delay_ms: Option<u64>,
+}
+
+impl Robots {
+    /// Rules which disallow everything, used when a host's robots.txt can't be fetched
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert!(!httplatency::crawl::Robots::disallow_all().allows("/"));
+    /// ```
+    pub fn disallow_all() -> Robots {
+        Robots { rules: vec![("/".to_string(), false)], crawl_delay_ms: None }
+    }
+
+    /// Parses the rules of a robots.txt file which apply to the robot called ``agent``
+    ///
+    /// The groups naming ``agent`` are used if there are any, and otherwise the groups for ``*``.
+    /// A ``Crawl-delay`` in seconds is read from the same groups.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::crawl::Robots;
+    ///
+    /// let robots = Robots::parse("User-agent: *\nDisallow: /private/\nAllow: /private/docs\nDisallow: /*.pdf$\n", "httplatency");
+    /// assert!(robots.allows("/"));
+    /// assert!(!robots.allows("/private/keys"));
+    /// assert!(robots.allows("/private/docs/a"));
+    /// assert!(!robots.allows("/files/a.pdf"));
+    /// assert!(robots.allows("/files/a.pdf?download"));
+    /// ```
+    pub fn parse(text: &str, agent: &str) -> Robots {
+        let agent = agent.to_lowercase();
+        let mut named = Vec::new();
+        let mut everyone = Vec::new();
+        let (mut named_delay, mut everyone_delay) = (None, None);
+        let mut agents: Vec<String> = Vec::new();
+        let mut in_rules = false;
+        let mut named_group = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let mut parts = line.splitn(2, ':');
+            let (field, value) = match (parts.next(), parts.next()) {
+                (Some(field), Some(value)) => (field.trim().to_lowercase(), value.trim()),
+                _ => continue,
+            };
+            match &field[..] {
+                "user-agent" => {
+                    // A user-agent line after rules starts a new group
+                    if in_rules {
+                        agents.clear();
+                        in_rules = false;
+                    }
+                    agents.push(value.to_lowercase());
+                    named_group = named_group || value.to_lowercase() == agent;
+                }
+                "allow" | "disallow" => {
+                    in_rules = true;
+                    // An empty disallow allows everything, so needs no rule
+                    if value.is_empty() {
+                        continue;
+                    }
+                    let rule = (value.to_string(), field == "allow");
+                    if agents.iter().any(|a| a == &agent) {
+                        named.push(rule);
+                    } else if agents.iter().any(|a| a == "*") {
+                        everyone.push(rule);
+                    }
+                }
+                "crawl-delay" => {
+                    in_rules = true;
+                    let delay = match value.parse::<f64>() {
+                        Ok(secs) if secs >= 0.0 => Some((secs.min(MAX_CRAWL_DELAY_SECS) * 1000.0).ceil() as u64),
+                        _ => continue,
+                    };
+                    if agents.iter().any(|a| a == &agent) {
+                        named_delay = delay;
+                    } else if agents.iter().any(|a| a == "*") {
+                        everyone_delay = delay;
+                    }
+                }
+                _ => (),
+            }
+        }
+        if named_group {
+            Robots { rules: named, crawl_delay_ms: named_delay }
+        } else {
+            Robots { rules: everyone, crawl_delay_ms: everyone_delay }
+        }
+    }
+
+    /// The ``Crawl-delay`` asked for in milliseconds, if any, to leave between requests
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::crawl::Robots;
+    ///
+    /// assert_eq!(Robots::parse("User-agent: *\nCrawl-delay: 1.5\n", "httplatency").crawl_delay_ms(), Some(1500));
+    /// assert_eq!(Robots::parse("User-agent: *\nCrawl-delay: 3600\n", "httplatency").crawl_delay_ms(), Some(60000));
+    /// assert_eq!(Robots::default().crawl_delay_ms(), None);
+    /// ```
+    pub fn crawl_delay_ms(&self) -> Option<u64> {
+        self.crawl_delay_ms
+    }
+
+    /// True if the rules allow the path (and query) of a url to be fetched
+    ///
+    /// The longest matching rule applies, with allow rules winning ties. Paths matching no rule
+    /// are allowed.
+    pub fn allows(&self, path: &str) -> bool {
+        let path = if path.is_empty() { "/" } else { path };
+        let mut best: Option<(usize, bool)> = None;
+        for &(ref pattern, allow) in self.rules.iter() {
+            if matches(pattern, path) {
+                let better = match best {
+                    Some((length, allowed)) => pattern.len() > length || (pattern.len() == length && allow && !allowed),
+                    None => true,
+                };
+                if better {
+                    best = Some((pattern.len(), allow));
+                }
+            }
+        }
+        best.map(|b| b.1).unwrap_or(true)
+    }
+}
+
+/// True if a robots.txt path pattern, which may use ``*`` for any characters and end with ``$``
+/// to match the end of the path, matches ``path``
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = if pattern.ends_with('$') {
+        (&pattern[..pattern.len() - 1], true)
+    } else {
+        (pattern, false)
+    };
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if !path.starts_with(parts[0]) {
+        return false;
+    }
+    let mut position = parts[0].len();
+    for (i, part) in parts.iter().enumerate().skip(1) {
+        if anchored && i == parts.len() - 1 {
+            return path.len() >= position + part.len() && path.ends_with(part);
+        }
+        match path[position..].find(part) {
+            Some(found) => position += found + part.len(),
+            None => return false,
+        }
+    }
+    !anchored || position == path.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ratelimit::{HostLimiter, HostPolicy};
+
+    /// A small site, as served by ``fetch``
+    fn fetch(url: &str) -> Result<Document, String> {
+        let (content_type, body) = match url {
+            "http://www.example.com/robots.txt" => ("text/plain", "User-agent: httplatency\nDisallow: /admin\n\n\
+                                                                  User-agent: *\nDisallow: /\n"),
+            "http://www.example.com/" => ("text/html", "<a href=/a>A</a><a href=/b>B</a><a href=/admin>Admin</a>"),
+            "http://www.example.com/a" => ("text/html; charset=utf-8", "<A HREF='/a/1'>1</A><a href='/report.pdf'>PDF</a>"),
+            "http://www.example.com/b" => ("text/html", "<script>var s = '<a href=/script>';</script><a href=/b/1>1</a>"),
+            "http://www.example.com/report.pdf" => ("application/pdf", "<a href=/pdf-link>"),
+            _ => return Ok(Document { url: url.to_string(), status: 404, content_type: None, body: Vec::new() }),
+        };
+        Ok(Document {
+            url: url.to_string(),
+            status: 200,
+            content_type: Some(content_type.to_string()),
+            body: body.as_bytes().to_vec(),
+        })
+    }
+
+    /// The urls of the pages found crawling from ``seeds`` with ``policy``, serving robots.txt
+    /// with ``robots`` and everything else as ``fetch`` does
+    fn crawled_with<R>(seeds: &[&str], policy: CrawlPolicy, mut robots: R) -> (Vec<String>, Vec<String>)
+        where R: FnMut(&str) -> Option<Result<Document, String>>
+    {
+        let seeds: Vec<String> = seeds.iter().map(|s| s.to_string()).collect();
+        let mut limiter = HostLimiter::new(HostPolicy::unlimited());
+        let (pages, warnings) = crawl(&seeds, &policy, &mut limiter, |url| robots(url).unwrap_or_else(|| fetch(url)));
+        (pages.into_iter().map(|p| p.url).collect(), warnings)
+    }
+
+    /// The urls of the pages found crawling www.example.com with ``policy``
+    fn crawled(policy: CrawlPolicy) -> (Vec<String>, Vec<String>) {
+        crawled_with(&["http://www.example.com/"], policy, |_| None)
+    }
+
+    /// A robots.txt response with ``status``
+    fn robots_status(url: &str, status: u16) -> Option<Result<Document, String>> {
+        if url.ends_with("/robots.txt") {
+            Some(Ok(Document { url: url.to_string(), status: status, content_type: None, body: Vec::new() }))
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    /// Pages should be found breadth first, honouring robots.txt and the depth limit
+    fn crawls_breadth_first() {
+        let (urls, warnings) = crawled(CrawlPolicy { max_depth: 2, max_pages: 100 });
+        assert_eq!(urls, vec!["http://www.example.com/a", "http://www.example.com/b",
+                              "http://www.example.com/a/1", "http://www.example.com/report.pdf",
+                              "http://www.example.com/b/1"]);
+        assert_eq!(warnings, vec!["http://www.example.com/admin: disallowed by robots.txt"]);
+
+        let (urls, _) = crawled(CrawlPolicy { max_depth: 0, max_pages: 100 });
+        assert!(urls.is_empty());
+    }
+
+    #[test]
+    /// Crawling should stop once enough pages are found, saying so
+    fn page_limit() {
+        let (urls, warnings) = crawled(CrawlPolicy { max_depth: 5, max_pages: 2 });
+        assert_eq!(urls, vec!["http://www.example.com/a", "http://www.example.com/b"]);
+        assert!(warnings.last().unwrap().starts_with("Stopped crawling after 2 pages"));
+    }
+
+    #[test]
+    /// Every seed should be crawled from, however low the page limit
+    fn seeds_not_limited() {
+        let seeds = ["http://www.example.com/a", "http://www.example.com/b", "http://www.example.com/a"];
+        let (urls, _) = crawled_with(&seeds, CrawlPolicy { max_depth: 1, max_pages: 2 }, |_| None);
+        assert_eq!(urls, vec!["http://www.example.com/a/1", "http://www.example.com/report.pdf"]);
+        let (urls, warnings) = crawled_with(&seeds[..2], CrawlPolicy { max_depth: 1, max_pages: 0 }, |_| None);
+        assert!(urls.is_empty());
+        assert_eq!(warnings, vec!["Stopped crawling after 0 pages, 3 or more links weren't followed"]);
+    }
+
+    #[test]
+    /// A missing robots.txt should allow everything, and one which can't be fetched nothing
+    fn robots_status_codes() {
+        let (urls, _) = crawled_with(&["http://www.example.com/"], CrawlPolicy::default(), |url| robots_status(url, 404));
+        assert!(urls.contains(&"http://www.example.com/admin".to_string()));
+
+        let (urls, warnings) = crawled_with(&["http://www.example.com/"], CrawlPolicy::default(), |url| robots_status(url, 503));
+        assert!(urls.is_empty());
+        assert_eq!(warnings, vec!["http://www.example.com/robots.txt: server responded 503, so nothing on the host is crawled",
+                                  "http://www.example.com/: disallowed by robots.txt"]);
+
+        let unreachable = |url: &str| if url.ends_with("/robots.txt") { Some(Err("connection refused".to_string())) } else { None };
+        let (urls, warnings) = crawled_with(&["http://www.example.com/"], CrawlPolicy::default(), unreachable);
+        assert!(urls.is_empty());
+        assert!(warnings[0].ends_with("connection refused, so nothing on the host is crawled"));
+    }
+
+    #[test]
+    /// A host's crawl delay should space out later requests to it
+    fn crawl_delay() {
+        let mut limiter = HostLimiter::new(HostPolicy::unlimited());
+        let robots = |url: &str| Ok(Document {
+            url: url.to_string(),
+            status: 200,
+            content_type: None,
+            body: b"User-agent: *\nCrawl-delay: 30\n".to_vec(),
+        });
+        let (pages, _) = crawl(&["http://www.example.com/".to_string()], &CrawlPolicy { max_depth: 0, max_pages: 1 },
+                               &mut limiter, robots);
+        assert!(pages.is_empty());
+        let now = time::precise_time_ns();
+        assert!(limiter.available_at("www.example.com", now) > now + 29 * 1_000_000_000);
+        assert_eq!(limiter.available_at("www.example.org", now), now);
+    }
+
+    #[test]
+    /// Links should be resolved against the page, or its base url, and odd markup tolerated
+    fn extracts_links() {
+        let html = "<base href='https://www.example.com/docs/'><a class=x href = \"v1/\" >v1</a>\
+                    <a href='HTTPS://WWW.EXAMPLE.COM/docs/v1/'>dup</a><a href=\"a&#x2F;b\">a/b</a>\
+                    <area shape=rect href=map><link href=/style.css><a name=top><a href=\"unclosed";
+        assert_eq!(links("http://www.example.com/", html),
+                   vec!["https://www.example.com/docs/v1/", "https://www.example.com/docs/a/b",
+                        "https://www.example.com/docs/map"]);
+        assert_eq!(resolve("http://www.example.com", "a"), Some("http://www.example.com/a".to_string()));
+        assert_eq!(resolve("http://www.example.com/a/", "b:c"), None);
+        assert_eq!(resolve("http://www.example.com/a/", "./b:c"), Some("http://www.example.com/a/b:c".to_string()));
+    }
+
+    #[test]
+    /// The rules for this robot should be used in preference to those for every robot
+    fn robots_groups() {
+        let text = "User-agent: Googlebot\nUser-agent: HTTPLatency\nDisallow: /slow # expensive\n\n\
+                    User-agent: *\nDisallow: /\n";
+        let robots = Robots::parse(text, ROBOTS_AGENT);
+        assert!(robots.allows("/"));
+        assert!(!robots.allows("/slow/page"));
+        assert!(!Robots::parse(text, "otherbot").allows("/"));
+        assert!(Robots::parse("User-agent: *\nDisallow:\n", ROBOTS_AGENT).allows("/any"));
+        assert!(Robots::default().allows(""));
+    }
+}
@@ -0,0 +1,568 @@
+//! Reading probe targets from curl command lines.
+//!
+//! Each command is written as it would be typed into a shell, and may be split over several
+//! lines with a ``\`` at the end of each line, as browsers' "Copy as cURL" does:
+//!
+//! ```text
+//! # Search, as reported in the bug
+//! curl 'https://api.example.com/search' -H 'Accept: application/json' \
+//!   -u reporter:secret --data-raw '{"q": "latency"}'
+//! ```
+//!
+//! The options which change the request are understood: ``-X``, ``-H``, ``-d`` and its variants,
+//! ``--json``, ``-G``, ``-I``, ``-u``, ``-A``, ``-b``, ``-e``, ``--compressed``, ``-m``,
+//! ``--resolve`` and ``-k``. Options which only affect curl's own output, such as ``-s`` or
+//! ``-o``, are ignored. Any other option is an error rather than being silently dropped, since the
+//! request measured would no longer be the one given.
+
+use rustc_serialize::base64::{ToBase64, STANDARD};
+
+use canonical;
+use target::{parse_method, Target};
+use urllist::Entry;
+
+/// Options which don't change the request, so are ignored
+const IGNORED_FLAGS: [&'static str; 13] = ["--silent", "--show-error", "--verbose", "--include", "--location",
+                                           "--fail", "--globoff", "--no-buffer", "--progress-bar", "--http1.1",
+                                           "--no-progress-meter", "--no-keepalive", "--tcp-nodelay"];
+
+/// Options taking a value which don't change the request, so are ignored along with their value
+const IGNORED_OPTIONS: [&'static str; 6] = ["--output", "--write-out", "--connect-timeout", "--retry",
+                                            "--retry-delay", "--max-redirs"];
+
+/// A command line split into words, starting on ``line``
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    /// The line the command starts on, starting from 1
+    pub line: usize,
+
+    /// The text of the command, with line continuations and runs of whitespace collapsed
+    pub text: String,
+
+    /// The words of the command, or why it couldn't be split into words
+    pub words: Result<Vec<String>, String>,
+}
+
+/// True if ``path`` names a file of curl commands
+pub fn is_curl_file(path: &str) -> bool {
+    path.to_lowercase().ends_with(".curl")
+}
+
+/// Parses every curl command in ``contents``, keeping commands which can't be used
+///
+/// Each entry is numbered by the line its command starts on.
+///
+/// # Examples
+///
+/// ```
+/// let entries = httplatency::curl::entries("# search\ncurl -X POST https://www.example.com/search \\\n  -d q=1\n");
+/// assert_eq!(entries.len(), 1);
+/// assert_eq!(entries[0].line, 2);
+/// assert_eq!(entries[0].text, "curl -X POST https://www.example.com/search -d q=1");
+/// let target = entries[0].target.as_ref().unwrap();
+/// assert_eq!(target.method, "POST");
+/// assert_eq!(target.body, Some("q=1".to_string()));
+/// ```
+pub fn entries(contents: &str) -> Vec<Entry> {
+    split_commands(contents).into_iter().map(|command| Entry {
+        line: command.line,
+        target: command.words.and_then(|words| parse_command(&words)),
+        text: command.text,
+    }).collect()
+}
+
+/// Builds the target for a curl command split into words
+///
+/// # Failures
+///
+/// Returns an Err if the command isn't a curl command, has no url or more than one, or uses an
+/// option which isn't supported or has an invalid value.
+///
+/// # Examples
+///
+/// ```
+/// let words: Vec<String> = vec!["curl", "-kI", "-u", "user:pass", "--resolve", "www.example.com:443:127.0.0.1",
+///                               "https://www.example.com/"].iter().map(|w| w.to_string()).collect();
+/// let target = httplatency::curl::parse_command(&words).unwrap();
+/// assert_eq!(target.method, "HEAD");
+/// assert!(target.insecure);
+/// assert_eq!(target.headers, vec![("Authorization".to_string(), "Basic dXNlcjpwYXNz".to_string())]);
+/// assert_eq!(target.resolve, vec![("www.example.com".to_string(), 443, "127.0.0.1".to_string())]);
+/// ```
+pub fn parse_command(words: &[String]) -> Result<Target, String> {
+    match words.first() {
+        Some(w) if w == "curl" => (),
+        Some(w) => return Err(format!("expected a curl command, found '{}'", w)),
+        None => return Err("empty command".to_string()),
+    }
+    let mut request = Request::default();
+    let mut args = words[1..].iter();
+    while let Some(arg) = args.next() {
+        if arg.starts_with("--") {
+            let value = if takes_value(arg) { Some(try!(next_value(arg, &mut args))) } else { None };
+            try!(request.apply(arg, value));
+        } else if arg.starts_with('-') && arg.len() > 1 {
+            // Short options may be bundled, e.g. -sSk, and the last may have its value attached, e.g. -XPOST
+            for (i, c) in arg.char_indices().skip(1) {
+                let name = try!(long_name(c));
+                if takes_value(name) {
+                    let value = if i + 1 < arg.len() {
+                        arg[i + 1..].to_string()
+                    } else {
+                        try!(next_value(&format!("-{}", c), &mut args))
+                    };
+                    try!(request.apply(name, Some(value)));
+                    break;
+                }
+                try!(request.apply(name, None));
+            }
+        } else {
+            try!(request.apply("--url", Some(arg.clone())));
+        }
+    }
+    request.target()
+}
+
+/// The settings of a curl command gathered from its options
+#[derive(Debug, Default)]
+struct Request {
+    url: Option<String>,
+    method: Option<String>,
+    headers: Vec<(String, String)>,
+    data: Vec<String>,
+    get: bool,
+    head: bool,
+    user: Option<String>,
+    timeout_ms: Option<u64>,
+    resolve: Vec<(String, u16, String)>,
+    insecure: bool,
+}
+
+impl Request {
+    /// Applies the option ``name`` (in its long form) given with ``value``
+    fn apply(&mut self, name: &str, value: Option<String>) -> Result<(), String> {
+        let value = value.unwrap_or(String::new());
+        match name {
+            "--url" => {
+                if self.url.is_some() {
+                    return Err(format!("more than one url given, found '{}'", value));
+                }
+                self.url = Some(value);
+            }
+            "--request" => self.method = Some(try!(parse_method(&value).map_err(|e| format!("{}: {}", name, e)))),
+            "--header" => {
+                let mut parts = value.splitn(2, ':');
+                match (parts.next().map(|n| n.trim()), parts.next().map(|v| v.trim())) {
+                    // curl removes a header given with no value
+                    (Some(_), Some("")) => (),
+                    (Some(n), Some(v)) if !n.is_empty() => self.headers.push((n.to_string(), v.to_string())),
+                    _ => return Err(format!("{}: expected 'Name: value', found '{}'", name, value)),
+                }
+            }
+            "--data" | "--data-ascii" | "--data-binary" if value.starts_with('@') => {
+                return Err(format!("{}: reading data from a file ('{}') isn't supported", name, value));
+            }
+            "--data" | "--data-ascii" | "--data-binary" | "--data-raw" => self.data.push(value),
+            "--data-urlencode" => self.data.push(urlencode_data(&value)),
+            "--json" => {
+                self.set_default_header("Content-Type", "application/json");
+                self.set_default_header("Accept", "application/json");
+                self.data.push(value);
+            }
+            "--get" => self.get = true,
+            "--head" => self.head = true,
+            "--user" => {
+                if !value.contains(':') {
+                    return Err(format!("{}: expected user:password, curl would prompt for the password", name));
+                }
+                self.user = Some(value);
+            }
+            "--user-agent" => self.headers.push(("User-Agent".to_string(), value)),
+            "--referer" => self.headers.push(("Referer".to_string(), value)),
+            "--cookie" if !value.contains('=') => {
+                return Err(format!("{}: reading cookies from a file ('{}') isn't supported", name, value));
+            }
+            "--cookie" => self.headers.push(("Cookie".to_string(), value)),
+            "--compressed" => self.set_default_header("Accept-Encoding", "deflate, gzip"),
+            "--max-time" => match value.parse::<f64>() {
+                Ok(seconds) if seconds > 0.0 => self.timeout_ms = Some((seconds * 1000.0) as u64),
+                _ => return Err(format!("{}: expected a number of seconds, found '{}'", name, value)),
+            },
+            "--resolve" => self.resolve.push(try!(parse_resolve(&value).map_err(|e| format!("{}: {}", name, e)))),
+            "--insecure" => self.insecure = true,
+            _ if IGNORED_FLAGS.contains(&name) || IGNORED_OPTIONS.contains(&name) => (),
+            _ => return Err(format!("unsupported option '{}'", name)),
+        }
+        Ok(())
+    }
+
+    /// Adds a header unless one of the same name has been given
+    fn set_default_header(&mut self, name: &str, value: &str) {
+        if !self.headers.iter().any(|h| h.0.eq_ignore_ascii_case(name)) {
+            self.headers.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    /// Builds the target for the request, working out its method and body as curl would
+    fn target(mut self) -> Result<Target, String> {
+        let url = try!(self.url.clone().ok_or("no url given".to_string()));
+        let url = if self.get && !self.data.is_empty() {
+            let separator = if url.contains('?') { '&' } else { '?' };
+            format!("{}{}{}", url, separator, self.data.join("&"))
+        } else {
+            url
+        };
+        let canonical = try!(canonical::parse(&url).map_err(|e| format!("'{}' is not a valid http(s) url: {}", url, e)));
+
+        let has_body = !self.get && !self.data.is_empty();
+        if has_body {
+            self.set_default_header("Content-Type", "application/x-www-form-urlencoded");
+        }
+        if let Some(ref user) = self.user {
+            let credentials = format!("Basic {}", user.as_bytes().to_base64(STANDARD));
+            self.headers.push(("Authorization".to_string(), credentials));
+        }
+        let mut target = Target::get(canonical.url);
+        target.scheme_guessed = canonical.scheme_guessed;
+        target.method = match self.method {
+            Some(ref m) => m.clone(),
+            None if self.head => "HEAD".to_string(),
+            None if has_body => "POST".to_string(),
+            None => "GET".to_string(),
+        };
+        target.headers = self.headers;
+        target.body = if has_body { Some(self.data.join("&")) } else { None };
+        target.timeout_ms = self.timeout_ms;
+        target.resolve = self.resolve;
+        target.insecure = self.insecure;
+        Ok(target)
+    }
+}
+
+/// The long form of a short option
+fn long_name(c: char) -> Result<&'static str, String> {
+    Ok(match c {
+        'X' => "--request",
+        'H' => "--header",
+        'd' => "--data",
+        'G' => "--get",
+        'I' => "--head",
+        'u' => "--user",
+        'A' => "--user-agent",
+        'e' => "--referer",
+        'b' => "--cookie",
+        'm' => "--max-time",
+        'k' => "--insecure",
+        's' => "--silent",
+        'S' => "--show-error",
+        'v' => "--verbose",
+        'i' => "--include",
+        'L' => "--location",
+        'f' => "--fail",
+        'g' => "--globoff",
+        'N' => "--no-buffer",
+        '#' => "--progress-bar",
+        'o' => "--output",
+        'w' => "--write-out",
+        _ => return Err(format!("unsupported option '-{}'", c)),
+    })
+}
+
+/// True if the long option ``name`` is followed by a value
+fn takes_value(name: &str) -> bool {
+    IGNORED_OPTIONS.contains(&name) ||
+    ["--url", "--request", "--header", "--data", "--data-ascii", "--data-binary", "--data-raw",
+     "--data-urlencode", "--json", "--user", "--user-agent", "--referer", "--cookie", "--max-time",
+     "--resolve"].contains(&name)
+}
+
+/// The value following the option ``name``
+fn next_value<'a, I: Iterator<Item = &'a String>>(name: &str, args: &mut I) -> Result<String, String> {
+    args.next().cloned().ok_or(format!("{}: expected a value", name))
+}
+
+/// Parses a ``--resolve`` value, ``host:port:address``. Only the first address is used when
+/// several are given.
+fn parse_resolve(value: &str) -> Result<(String, u16, String), String> {
+    let mut parts = value.trim_left_matches('+').splitn(3, ':');
+    match (parts.next(), parts.next().map(|p| p.parse::<u16>()), parts.next()) {
+        (Some(host), Some(Ok(port)), Some(addresses)) if !host.is_empty() && !addresses.is_empty() => {
+            let address = addresses.split(',').next().unwrap_or(addresses);
+            Ok((host.to_lowercase(), port, address.to_string()))
+        }
+        _ => Err(format!("expected host:port:address, found '{}'", value)),
+    }
+}
+
+/// Encodes a ``--data-urlencode`` value: ``name=content`` has only its content encoded
+fn urlencode_data(value: &str) -> String {
+    match value.find('=') {
+        Some(i) => format!("{}={}", &value[..i], canonical::percent_encode(&value[i + 1..])),
+        None => canonical::percent_encode(value),
+    }
+}
+
+/// Splits ``contents`` into commands and their words, as a POSIX shell would
+///
+/// Single and double quotes, ``$'...'`` strings, backslash escapes and line continuations are
+/// understood, and ``#`` starts a comment. Variables and other expansions aren't supported.
+///
+/// # Examples
+///
+/// ```
+/// let commands = httplatency::curl::split_commands("curl 'a b' \"c\\\"d\" $'e\\nf' \\\n g # h\ncurl i");
+/// assert_eq!(commands.len(), 2);
+/// assert_eq!(commands[0].words, Ok(vec!["curl".to_string(), "a b".to_string(), "c\"d".to_string(),
+///                                       "e\nf".to_string(), "g".to_string()]));
+/// assert_eq!(commands[1].line, 3);
+/// ```
+pub fn split_commands(contents: &str) -> Vec<Command> {
+    let mut commands = Vec::new();
+    let mut chars = contents.chars().peekable();
+    let mut line = 1;
+    let mut start = 1;
+    let mut text = String::new();
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut error: Option<String> = None;
+    loop {
+        let c = chars.next();
+        if c == Some('\n') || c.is_none() {
+            if in_word {
+                words.push(word.clone());
+            }
+            if !words.is_empty() || error.is_some() {
+                commands.push(Command {
+                    line: start,
+                    text: text.split_whitespace().collect::<Vec<&str>>().join(" "),
+                    words: match error.take() {
+                        Some(e) => Err(e),
+                        None => Ok(words.clone()),
+                    },
+                });
+            }
+            if c.is_none() {
+                break;
+            }
+            line += 1;
+            text.clear();
+            words.clear();
+            word.clear();
+            in_word = false;
+            continue;
+        }
+        let c = c.unwrap();
+        if words.is_empty() && !in_word {
+            start = line;
+        }
+        match c {
+            ' ' | '\t' | '\r' => {
+                if in_word {
+                    words.push(word.clone());
+                    word.clear();
+                    in_word = false;
+                }
+                text.push(c);
+            }
+            '#' if !in_word => {
+                // A comment runs to the end of the line
+                while chars.peek().map(|&n| n != '\n').unwrap_or(false) {
+                    chars.next();
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {
+                    line += 1;
+                    text.push(' ');
+                }
+                Some(e) => {
+                    word.push(e);
+                    in_word = true;
+                    text.push(c);
+                    text.push(e);
+                }
+                None => word.push('\\'),
+            },
+            '\'' | '"' => {
+                in_word = true;
+                text.push(c);
+                let quoted = quoted(c, &mut chars, &mut line, &mut text);
+                match quoted {
+                    Ok(s) => word.push_str(&s),
+                    Err(e) => error = Some(e),
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                in_word = true;
+                text.push(c);
+                text.push(chars.next().unwrap());
+                match ansi_quoted(&mut chars, &mut line, &mut text) {
+                    Ok(s) => word.push_str(&s),
+                    Err(e) => error = Some(e),
+                }
+            }
+            _ => {
+                word.push(c);
+                in_word = true;
+                text.push(c);
+            }
+        }
+    }
+    commands
+}
+
+/// Reads the rest of a string quoted with ``quote``, after the opening quote
+fn quoted<I: Iterator<Item = char>>(quote: char, chars: &mut I, line: &mut usize, text: &mut String)
+                                    -> Result<String, String> {
+    let mut s = String::new();
+    loop {
+        let c = try!(chars.next().ok_or(format!("unclosed {} quote", quote)));
+        text.push(c);
+        match c {
+            '\n' => {
+                *line += 1;
+                s.push(c);
+            }
+            c if c == quote => return Ok(s),
+            // Inside double quotes a backslash only escapes these
+            '\\' if quote == '"' => {
+                let e = try!(chars.next().ok_or(format!("unclosed {} quote", quote)));
+                text.push(e);
+                match e {
+                    '"' | '\\' | '$' | '`' => s.push(e),
+                    '\n' => *line += 1,
+                    _ => {
+                        s.push('\\');
+                        s.push(e);
+                    }
+                }
+            }
+            _ => s.push(c),
+        }
+    }
+}
+
+/// Reads the rest of a ``$'...'`` string, after the opening quote, decoding its escapes
+fn ansi_quoted<I: Iterator<Item = char>>(chars: &mut I, line: &mut usize, text: &mut String)
+                                         -> Result<String, String> {
+    let mut s = String::new();
+    loop {
+        let c = try!(chars.next().ok_or("unclosed $' quote".to_string()));
+        text.push(c);
+        match c {
+            '\'' => return Ok(s),
+            '\\' => {
+                let e = try!(chars.next().ok_or("unclosed $' quote".to_string()));
+                text.push(e);
+                match e {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    '\\' | '\'' | '"' | '?' => s.push(e),
+                    _ => {
+                        s.push('\\');
+                        s.push(e);
+                    }
+                }
+            }
+            '\n' => {
+                *line += 1;
+                s.push(c);
+            }
+            _ => s.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Splits a single command
+    fn words(command: &str) -> Vec<String> {
+        split_commands(command).remove(0).words.unwrap()
+    }
+
+    /// Parses a single command
+    fn parse(command: &str) -> Result<::target::Target, String> {
+        parse_command(&words(command))
+    }
+
+    #[test]
+    /// A command as copied from a browser's developer tools should be replayed exactly
+    fn copy_as_curl() {
+        let target = parse("curl 'https://www.example.com/api/items?page=2' \\\n  \
+                            -H 'accept: application/json' \\\n  \
+                            -H 'cookie: session=abc; theme=dark' \\\n  \
+                            --data-raw $'{\"name\":\"it\\'s\"}' \\\n  \
+                            --compressed").unwrap();
+        assert_eq!(target.url, "https://www.example.com/api/items?page=2");
+        assert_eq!(target.method, "POST");
+        assert_eq!(target.body, Some("{\"name\":\"it's\"}".to_string()));
+        assert_eq!(target.headers, vec![("accept".to_string(), "application/json".to_string()),
+                                        ("cookie".to_string(), "session=abc; theme=dark".to_string()),
+                                        ("Accept-Encoding".to_string(), "deflate, gzip".to_string()),
+                                        ("Content-Type".to_string(), "application/x-www-form-urlencoded".to_string())]);
+    }
+
+    #[test]
+    /// Methods should be worked out as curl does
+    fn methods() {
+        assert_eq!(parse("curl www.example.com").unwrap().method, "GET");
+        assert_eq!(parse("curl -I www.example.com").unwrap().method, "HEAD");
+        assert_eq!(parse("curl -d a=1 www.example.com").unwrap().method, "POST");
+        assert_eq!(parse("curl -XPUT -d a=1 www.example.com").unwrap().method, "PUT");
+        let target = parse("curl -G -d a=1 --data-urlencode 'b=x y' http://www.example.com/?c=3").unwrap();
+        assert_eq!(target.method, "GET");
+        assert_eq!(target.url, "http://www.example.com/?c=3&a=1&b=x%20y");
+        assert_eq!(target.body, None);
+    }
+
+    #[test]
+    /// Other settings should be applied to the target
+    fn settings() {
+        let target = parse("curl -sSk -m 2.5 --json '{}' -A probe --resolve '+api.example.com:443:[::1],127.0.0.1' \
+                            -u 'a:b' https://api.example.com/").unwrap();
+        assert!(target.insecure);
+        assert_eq!(target.timeout_ms, Some(2500));
+        assert_eq!(target.resolve, vec![("api.example.com".to_string(), 443, "[::1]".to_string())]);
+        assert_eq!(target.body, Some("{}".to_string()));
+        assert!(target.headers.contains(&("Content-Type".to_string(), "application/json".to_string())));
+        assert!(target.headers.contains(&("User-Agent".to_string(), "probe".to_string())));
+        assert!(target.headers.contains(&("Authorization".to_string(), "Basic YTpi".to_string())));
+    }
+
+    #[test]
+    /// Commands which can't be replayed faithfully should be rejected with the reason
+    fn errors() {
+        assert_eq!(parse("wget www.example.com").unwrap_err(), "expected a curl command, found 'wget'");
+        assert_eq!(parse("curl -x proxy:3128 www.example.com").unwrap_err(), "unsupported option '-x'");
+        assert_eq!(parse("curl --proxy proxy:3128 www.example.com").unwrap_err(), "unsupported option '--proxy'");
+        assert!(parse("curl -d @body.json www.example.com").unwrap_err().contains("from a file"));
+        assert!(parse("curl -u alice www.example.com").unwrap_err().contains("prompt"));
+        assert!(parse("curl --resolve api.example.com www.example.com").unwrap_err().starts_with("--resolve"));
+        assert_eq!(parse("curl -H").unwrap_err(), "-H: expected a value");
+        assert_eq!(parse("curl -s").unwrap_err(), "no url given");
+        assert!(parse("curl a.example.com b.example.com").unwrap_err().starts_with("more than one url"));
+    }
+
+    #[test]
+    /// Quoting should follow the shell's rules, and unclosed quotes be reported
+    fn quoting() {
+        assert_eq!(words("curl a\\ b 'c\\d' \"e\\f\" \"\" x'y'\"z\""), vec!["curl", "a b", "c\\d", "e\\f", "", "xyz"]);
+        assert_eq!(words("curl 'multi\nline'"), vec!["curl", "multi\nline"]);
+        let commands = split_commands("curl 'unclosed\n\n\ncurl next");
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].words, Err("unclosed ' quote".to_string()));
+    }
+
+    #[test]
+    /// Blank lines and comments shouldn't produce commands, and line numbers should be kept
+    fn line_numbers() {
+        let entries = entries("\n# comment\ncurl a.example.com \\\n  -I\n\ncurl 'b\n.example.com'\ncurl c.example.com\n");
+        let lines: Vec<usize> = entries.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![3, 6, 8]);
+        assert_eq!(entries[0].text, "curl a.example.com -I");
+    }
+}
@@ -0,0 +1,220 @@
+//! Wraps the results of a run with metadata describing how and where they were produced.
+//!
+//! Output files used to be a bare array of results, so files from different runs couldn't be told
+//! apart. An ``Envelope`` records the versions, timings, machine, options and input of the run
+//! alongside its results, so stored results are self-describing.
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+
+use hostname;
+use rand::{self, Rng};
+use sha1::Sha1;
+use time::{self, Tm};
+
+/// The version of the output file layout. Bumped whenever fields are renamed or removed.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The version of this tool
+pub const TOOL_VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+/// The results of a run together with metadata describing it
+#[derive(RustcEncodable, Debug, Clone)]
+pub struct Envelope<T> {
+    /// The version of the output layout, see ``SCHEMA_VERSION``
+    pub schema_version: u32,
+
+    /// The version of httplatency that produced the results
+    pub tool_version: String,
+
+    /// A random identifier unique to this run
+    pub run_id: String,
+
+    /// When the run started, as an RFC 3339 timestamp in UTC
+    pub started_at: String,
+
+    /// When the run finished, as an RFC 3339 timestamp in UTC
+    pub finished_at: String,
+
+    /// The name of the machine the run was made from, if it could be found
+    pub hostname: Option<String>,
+
+    /// The command line arguments given, not including the program name
+    pub options: Vec<String>,
+
+    /// The name of the input the urls were read from, if any
+    pub input: Option<String>,
+
+    /// The SHA-1 hash of the input, as ``sha1:<hex>``, if it could be read
+    pub input_hash: Option<String>,
+
+    /// The results of the run
+    pub results: T,
+}
+
+/// The metadata of a run in progress, which becomes an ``Envelope`` when the run finishes
+#[derive(Debug, Clone)]
+pub struct Run {
+    run_id: String,
+    started_at: String,
+    hostname: Option<String>,
+    options: Vec<String>,
+    input: Option<String>,
+    input_hash: Option<String>,
+}
+
+impl Run {
+    /// Starts a run given the command line ``options`` and the name of the ``input`` file, if any
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let run = httplatency::envelope::Run::start(vec!["urls.txt".to_string()], None);
+    /// let envelope = run.finish(vec![1, 2, 3]);
+    /// assert_eq!(envelope.schema_version, httplatency::envelope::SCHEMA_VERSION);
+    /// assert_eq!(envelope.results, vec![1, 2, 3]);
+    /// ```
+    pub fn start(options: Vec<String>, input: Option<&String>) -> Run {
+        let input_hash = input.and_then(|path| match hash_file(path) {
+            Ok(h) => Some(h),
+            Err(err) => {
+                warn!("Unable to hash input {}: {}", path, err);
+                None
+            }
+        });
+        Run {
+            run_id: new_uuid(),
+            started_at: format_timestamp(&time::now_utc()),
+            hostname: hostname::get_hostname(),
+            options: options,
+            input: input.cloned(),
+            input_hash: input_hash,
+        }
+    }
+
+    /// Starts a run whose input has already been read, such as one read from stdin
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::envelope::Run;
+    ///
+    /// let run = Run::start_with_input(vec!["-".to_string()], &"-".to_string(), b"abc");
+    /// let envelope = run.finish(());
+    /// assert_eq!(envelope.input_hash.unwrap(), "sha1:a9993e364706816aba3e25717850c26c9cd0d89d");
+    /// ```
+    pub fn start_with_input(options: Vec<String>, input: &String, contents: &[u8]) -> Run {
+        let mut run = Run::start(options, None);
+        run.input = Some(input.clone());
+        run.input_hash = Some(hash_bytes(contents));
+        run
+    }
+
+    /// Finishes the run, wrapping ``results`` with the run's metadata
+    pub fn finish<T>(self, results: T) -> Envelope<T> {
+        Envelope {
+            schema_version: SCHEMA_VERSION,
+            tool_version: TOOL_VERSION.to_string(),
+            run_id: self.run_id,
+            started_at: self.started_at,
+            finished_at: format_timestamp(&time::now_utc()),
+            hostname: self.hostname,
+            options: self.options,
+            input: self.input,
+            input_hash: self.input_hash,
+            results: results,
+        }
+    }
+}
+
+/// Formats a time as an RFC 3339 timestamp in UTC with millisecond precision
+///
+/// # Examples
+///
+/// ```
+/// extern crate time;
+/// extern crate httplatency;
+/// # fn main() {
+/// let epoch = time::at_utc(time::Timespec::new(0, 5000000));
+/// assert_eq!(httplatency::envelope::format_timestamp(&epoch), "1970-01-01T00:00:00.005Z");
+/// # }
+/// ```
+pub fn format_timestamp(tm: &Tm) -> String {
+    let utc = tm.to_utc();
+    format!("{}.{:03}Z", utc.strftime("%Y-%m-%dT%H:%M:%S").unwrap(), utc.tm_nsec / 1_000_000)
+}
+
+/// Generates a random (version 4) UUID, such as the id of a run
+pub fn new_uuid() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", hex[0..4].concat(), hex[4..6].concat(), hex[6..8].concat(),
+            hex[8..10].concat(), hex[10..16].concat())
+}
+
+/// Returns the SHA-1 hash of a file's contents, as ``sha1:<hex>``
+///
+/// # Failures
+///
+/// Returns an Err if the file cannot be read.
+pub fn hash_file(path: &String) -> io::Result<String> {
+    let mut file = try!(File::open(path));
+    let mut contents = Vec::new();
+    try!(file.read_to_end(&mut contents));
+    Ok(hash_bytes(&contents))
+}
+
+/// Returns the SHA-1 hash of ``data``, as ``sha1:<hex>``
+///
+/// # Examples
+///
+/// ```
+/// let hash = httplatency::envelope::hash_bytes(b"abc");
+/// assert_eq!(hash, "sha1:a9993e364706816aba3e25717850c26c9cd0d89d");
+/// ```
+pub fn hash_bytes(data: &[u8]) -> String {
+    let mut sha = Sha1::new();
+    sha.update(data);
+    format!("sha1:{}", sha.digest())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    /// Run ids should look like version 4 UUIDs and differ between runs
+    fn run_id_is_uuid() {
+        let id = new_uuid();
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "4");
+        assert!(id != new_uuid());
+    }
+
+    #[test]
+    /// Finishing a run should carry its metadata into the envelope
+    fn finish_keeps_metadata() {
+        let options = vec!["-o".to_string(), "out.json".to_string()];
+        let run = Run::start(options.clone(), None);
+        let id = run.run_id.clone();
+        let envelope = run.finish(Vec::<u32>::new());
+        assert_eq!(envelope.run_id, id);
+        assert_eq!(envelope.options, options);
+        assert_eq!(envelope.tool_version, TOOL_VERSION);
+        assert!(envelope.started_at <= envelope.finished_at);
+        assert!(envelope.input_hash.is_none());
+    }
+
+    #[test]
+    /// A missing input file shouldn't stop the run, just leave the hash out
+    fn missing_input_not_hashed() {
+        let input = "/this/file/does/not/exist".to_string();
+        let envelope = Run::start(vec![], Some(&input)).finish(());
+        assert_eq!(envelope.input, Some(input));
+        assert!(envelope.input_hash.is_none());
+    }
+}
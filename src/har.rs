@@ -0,0 +1,441 @@
+//! Reading probe targets from, and writing results as, HTTP Archive (HAR) files.
+//!
+//! A HAR file captured in a browser's developer tools records every request a page made. Each
+//! request can be replayed as a target, with its method, headers and body. Results can in turn be
+//! written as a HAR 1.2 log (see http://www.softwareishard.com/blog/har-12-spec/) to be opened in
+//! existing HAR viewers.
+
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::Json;
+
+use Latency;
+use canonical;
+use envelope::Envelope;
+use page::PageLoad;
+use target::{parse_method, Target};
+use urllist::Entry;
+
+/// The version of the HAR format written
+pub const HAR_VERSION: &'static str = "1.2";
+
+/// Request headers in a HAR file which aren't replayed, because they are set when the request is
+/// made. HTTP/2 pseudo-headers such as ``:authority`` aren't replayed either.
+const SKIPPED_HEADERS: [&'static str; 3] = ["Host", "Content-Length", "Connection"];
+
+/// True if ``path`` names a HAR file
+pub fn is_har_file(path: &str) -> bool {
+    path.to_lowercase().ends_with(".har")
+}
+
+/// Parses the requests recorded in a HAR file, keeping those which can't be replayed
+///
+/// Each entry is numbered by its position in the log, starting from 1, and its text is the method
+/// and url recorded.
+///
+/// # Failures
+///
+/// Returns an Err if ``contents`` isn't JSON or has no ``log.entries``.
+///
+/// # Examples
+///
+/// ```
+/// let entries = httplatency::har::entries(r#"{"log": {"entries": [
+///     {"request": {"method": "POST", "url": "http://www.example.com/search",
+///                  "headers": [{"name": "Accept", "value": "text/html"}],
+///                  "postData": {"mimeType": "text/plain", "text": "q=latency"}}},
+///     {"request": {"method": "GET", "url": "data:image/png;base64,AAAA", "headers": []}}
+/// ]}}"#).unwrap();
+/// let target = entries[0].target.as_ref().unwrap();
+/// assert_eq!(target.method, "POST");
+/// assert_eq!(target.headers, vec![("Accept".to_string(), "text/html".to_string())]);
+/// assert_eq!(target.body, Some("q=latency".to_string()));
+/// assert!(entries[1].target.is_err());
+/// ```
+pub fn entries(contents: &str) -> Result<Vec<Entry>, String> {
+    let json = try!(Json::from_str(contents).map_err(|e| format!("Invalid JSON: {}", e)));
+    let entries = match json.find_path(&["log", "entries"]) {
+        Some(&Json::Array(ref entries)) => entries,
+        _ => return Err("No log.entries array found, expected a HAR file".to_string()),
+    };
+    Ok(entries.iter().enumerate().map(|(i, entry)| {
+        let request = entry.find("request");
+        let method = request.and_then(|r| r.find("method")).and_then(|m| m.as_string()).unwrap_or("");
+        let url = request.and_then(|r| r.find("url")).and_then(|u| u.as_string()).unwrap_or("");
+        Entry {
+            line: i + 1,
+            text: format!("{} {}", method, url).trim().to_string(),
+            target: request.ok_or("no request recorded".to_string()).and_then(target),
+        }
+    }).collect())
+}
+
+/// Builds a target which replays a HAR request
+fn target(request: &Json) -> Result<Target, String> {
+    let url = try!(string(request, "url"));
+    let mut target = match canonical::parse(url) {
+        Ok(c) => Target::get(c.url),
+        Err(err) => return Err(format!("'{}' is not a valid http(s) url: {}", url, err)),
+    };
+    target.method = try!(parse_method(try!(string(request, "method"))).map_err(|e| format!("method: {}", e)));
+    match request.find("headers") {
+        Some(&Json::Array(ref headers)) => {
+            for header in headers.iter() {
+                let name = try!(string(header, "name"));
+                let value = try!(string(header, "value"));
+                if !name.starts_with(':') && !SKIPPED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name)) {
+                    target.headers.push((name.to_string(), value.to_string()));
+                }
+            }
+        }
+        Some(_) => return Err("headers: expected an array".to_string()),
+        None => (),
+    }
+    target.body = request.find_path(&["postData", "text"]).and_then(|t| t.as_string()).map(|t| t.to_string());
+    Ok(target)
+}
+
+/// The string value of ``field`` in a HAR object
+fn string<'a>(object: &'a Json, field: &str) -> Result<&'a str, String> {
+    object.find(field).and_then(|v| v.as_string()).ok_or(format!("{}: missing or not a string", field))
+}
+
+/// Converts the results of a run to a HAR 1.2 log
+///
+/// Each entry's ``timings`` are the result's ``phases``: ``dns``, ``connect`` (which, as HAR
+/// requires, includes ``ssl``) and ``receive``, with ``wait`` the rest of the request's time so the
+/// timings add up to it. ``send`` is 0 except for an upload, where it is the time taken to send its
+/// body. Phases which weren't measured, such as ``blocked``, are -1.
+/// Details HAR has no field for, such as a result's group, are kept in fields starting with ``_``
+/// as the format allows.
+///
+/// # Examples
+///
+/// ```
+/// let run = httplatency::envelope::Run::start(vec![], None);
+/// let har = httplatency::har::to_har(&run.finish(vec![]));
+/// assert_eq!(har.find_path(&["log", "version"]).unwrap().as_string(), Some("1.2"));
+/// ```
+pub fn to_har(envelope: &Envelope<Vec<Latency>>) -> Json {
+    log(envelope, vec![("entries", Json::Array(envelope.results.iter().map(entry).collect()))])
+}
+
+/// Converts the waterfall of a page load to a HAR 1.2 log with a single page
+///
+/// Each request's ``timings`` are the phases measured, with ``ssl`` included in ``connect`` as HAR
+/// requires, so HAR viewers show the waterfall. The
+/// page's ``onContentLoad`` is when the document had been read and ``onLoad`` when every resource
+/// had. Each entry's ``_kind`` and ``_initiator`` say what it was for and what referenced it.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::page::PageLoad;
+///
+/// let page = PageLoad { url: "http://www.example.com/".to_string(), document_ms: 0, page_complete_ms: 0,
+///                       requests: 0, failed: 0, bytes: 0, resources: vec![] };
+/// let run = httplatency::envelope::Run::start(vec![], None);
+/// let har = httplatency::har::page_to_har(&run.finish(page));
+/// assert_eq!(har.find_path(&["log", "pages"]).unwrap().as_array().unwrap().len(), 1);
+/// ```
+pub fn page_to_har(envelope: &Envelope<PageLoad>) -> Json {
+    let page = &envelope.results;
+    let timings = object(vec![
+        ("onContentLoad", Json::I64(page.document_ms)),
+        ("onLoad", Json::I64(page.page_complete_ms)),
+    ]);
+    let started = page.resources.first().map(|r| r.started_at.clone()).unwrap_or(envelope.started_at.clone());
+    let summary = object(vec![
+        ("startedDateTime", Json::String(started)),
+        ("id", Json::String(PAGE_ID.to_string())),
+        ("title", Json::String(page.url.clone())),
+        ("pageTimings", timings),
+    ]);
+    let entries = page.resources.iter().map(|resource| {
+        let phases = &resource.phases;
+        let connect_ms = if phases.connect_ms >= 0 { phases.connect_ms + phases.ssl_ms.max(0) } else { -1 };
+        let timings = object(vec![
+            ("blocked", Json::I64(phases.blocked_ms)),
+            ("dns", Json::I64(phases.dns_ms)),
+            ("connect", Json::I64(connect_ms)),
+            ("ssl", Json::I64(phases.ssl_ms)),
+            ("send", Json::I64(0)),
+            ("wait", Json::I64(phases.wait_ms.max(0))),
+            ("receive", Json::I64(phases.receive_ms.max(0))),
+        ]);
+        let content = object(vec![
+            ("size", Json::U64(resource.size)),
+            ("mimeType", Json::String(resource.content_type.clone().unwrap_or(String::new()))),
+        ]);
+        let mut fields = vec![
+            ("pageref", Json::String(PAGE_ID.to_string())),
+            ("startedDateTime", Json::String(resource.started_at.clone())),
+            // Blocking is part of an entry's time in HAR, but isn't part of ``total_ms``
+            ("time", Json::I64(resource.total_ms + phases.blocked_ms.max(0))),
+            ("request", request("GET", &resource.url, None)),
+            ("response", response(resource.status, content)),
+            ("cache", object(Vec::new())),
+            ("timings", timings),
+            ("_kind", Json::String(format!("{:?}", resource.kind))),
+        ];
+        if let Some(ref initiator) = resource.initiator {
+            fields.push(("_initiator", Json::String(initiator.clone())));
+        }
+        if let Some(ref error) = resource.error {
+            fields.push(("comment", Json::String(error.clone())));
+        }
+        object(fields)
+    }).collect();
+    log(envelope, vec![("pages", Json::Array(vec![summary])), ("entries", Json::Array(entries))])
+}
+
+/// The id of the page in a HAR log of a page load
+const PAGE_ID: &'static str = "page_1";
+
+/// Builds a HAR log of a run from its pages and entries
+fn log<T>(envelope: &Envelope<T>, fields: Vec<(&str, Json)>) -> Json {
+    let creator = object(vec![
+        ("name", Json::String("httplatency".to_string())),
+        ("version", Json::String(envelope.tool_version.clone())),
+    ]);
+    let comment = match envelope.hostname {
+        Some(ref host) => format!("run {} from {}", envelope.run_id, host),
+        None => format!("run {}", envelope.run_id),
+    };
+    let mut fields = fields;
+    fields.push(("version", Json::String(HAR_VERSION.to_string())));
+    fields.push(("creator", creator));
+    fields.push(("comment", Json::String(comment)));
+    object(vec![("log", object(fields))])
+}
+
+/// Converts a single result to a HAR entry
+fn entry(result: &Latency) -> Json {
+    let size = result.bytes.map(|b| b as i64).unwrap_or(0);
+    let content = object(vec![("size", Json::I64(size)), ("mimeType", Json::String(String::new()))]);
+    let phases = result.phases.unwrap_or_default();
+    let connect_ms = if phases.connect_ms >= 0 { phases.connect_ms + phases.ssl_ms.max(0) } else { -1 };
+    let send_ms = result.upload.as_ref().map(|u| u.upload_ms).unwrap_or(0);
+    let receive_ms = phases.receive_ms.max(0);
+    let wait_ms = result.latency_ms - phases.dns_ms.max(0) - connect_ms.max(0) - send_ms - receive_ms;
+    let timings = object(vec![
+        ("blocked", Json::I64(-1)),
+        ("dns", Json::I64(phases.dns_ms)),
+        ("connect", Json::I64(connect_ms)),
+        ("ssl", Json::I64(phases.ssl_ms)),
+        ("send", Json::I64(send_ms)),
+        ("wait", Json::I64(wait_ms.max(0))),
+        ("receive", Json::I64(receive_ms)),
+    ]);
+    let mut fields = vec![
+        ("startedDateTime", Json::String(result.started_at.clone())),
+        ("time", Json::I64(result.latency_ms)),
+        ("request", request(&result.method, &result.url, result.upload.as_ref().map(|u| u.bytes_sent as i64))),
+        ("response", response(result.status, content)),
+        ("cache", object(Vec::new())),
+        ("timings", timings),
+        ("_attempts", Json::U64(result.attempts.len() as u64)),
+    ];
+    if let Some(ref group) = result.group {
+        fields.push(("_group", Json::String(group.clone())));
+    }
+    if let Some(ref template) = result.template {
+        fields.push(("_template", Json::String(template.clone())));
+    }
+    if let Some(ref from) = result.fallback_from {
+        fields.push(("_fallbackFrom", Json::String(from.clone())));
+    }
+    if let Some(ref from) = result.linked_from {
+        fields.push(("_linkedFrom", Json::String(from.clone())));
+    }
+    if !result.expectation_failures.is_empty() {
+        fields.push(("comment", Json::String(result.expectation_failures.join("; "))));
+    }
+    object(fields)
+}
+
+/// A HAR request for ``url``, without the headers and body which aren't recorded, though the size
+/// of an uploaded body is
+fn request(method: &str, url: &str, body_size: Option<i64>) -> Json {
+    object(vec![
+        ("method", Json::String(method.to_string())),
+        ("url", Json::String(url.to_string())),
+        ("httpVersion", Json::String("HTTP/1.1".to_string())),
+        ("cookies", Json::Array(Vec::new())),
+        ("headers", Json::Array(Vec::new())),
+        ("queryString", Json::Array(query_string(url))),
+        ("headersSize", Json::I64(-1)),
+        ("bodySize", Json::I64(body_size.unwrap_or(-1))),
+    ])
+}
+
+/// A HAR response with ``status`` and ``content``, without the headers which aren't recorded
+fn response(status: Option<u16>, content: Json) -> Json {
+    object(vec![
+        // A status of 0 is how HAR records a request which got no response
+        ("status", Json::U64(status.unwrap_or(0) as u64)),
+        ("statusText", Json::String(String::new())),
+        ("httpVersion", Json::String("HTTP/1.1".to_string())),
+        ("cookies", Json::Array(Vec::new())),
+        ("headers", Json::Array(Vec::new())),
+        ("content", content),
+        ("redirectURL", Json::String(String::new())),
+        ("headersSize", Json::I64(-1)),
+        ("bodySize", Json::I64(-1)),
+    ])
+}
+
+/// The query parameters of ``url`` as HAR name/value pairs, left encoded as they are in the url
+fn query_string(url: &str) -> Vec<Json> {
+    let query = match url.find('?') {
+        Some(i) => &url[i + 1..],
+        None => return Vec::new(),
+    };
+    query.split('&').filter(|p| !p.is_empty()).map(|param| {
+        let mut parts = param.splitn(2, '=');
+        let name = parts.next().unwrap_or("");
+        let value = parts.next().unwrap_or("");
+        object(vec![("name", Json::String(name.to_string())), ("value", Json::String(value.to_string()))])
+    }).collect()
+}
+
+/// Builds a JSON object from its fields
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    let mut map = BTreeMap::new();
+    for (name, value) in fields {
+        map.insert(name.to_string(), value);
+    }
+    Json::Object(map)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Latency;
+    use envelope::Run;
+    use page::{PageLoad, Phases, Resource, ResourceKind};
+    use rustc_serialize::json::Json;
+    use upload::Upload;
+
+    #[test]
+    /// Headers set when making the request shouldn't be replayed, and bad entries should say why
+    fn imports_requests() {
+        let entries = entries(r#"{"log": {"version": "1.2", "entries": [
+            {"request": {"method": "GET", "url": "https://www.example.com/a?b=1", "headers": [
+                {"name": ":authority", "value": "www.example.com"},
+                {"name": "host", "value": "www.example.com"},
+                {"name": "Content-Length", "value": "0"},
+                {"name": "Cookie", "value": "session=abc"}
+            ]}},
+            {"request": {"method": "BREW", "url": "http://www.example.com/"}},
+            {"response": {}}
+        ]}}"#).unwrap();
+        assert_eq!(entries.len(), 3);
+        let target = entries[0].target.as_ref().unwrap();
+        assert_eq!(target.url, "https://www.example.com/a?b=1");
+        assert_eq!(target.headers, vec![("Cookie".to_string(), "session=abc".to_string())]);
+        assert_eq!(target.body, None);
+        assert!(entries[1].target.as_ref().unwrap_err().starts_with("method:"));
+        assert_eq!(entries[1].text, "BREW http://www.example.com/");
+        assert_eq!(entries[2].line, 3);
+        assert!(entries[2].target.is_err());
+    }
+
+    #[test]
+    /// Files which aren't HAR logs should be rejected
+    fn rejects_other_files() {
+        assert!(entries("not json").unwrap_err().starts_with("Invalid JSON"));
+        assert!(entries(r#"{"results": []}"#).unwrap_err().contains("log.entries"));
+    }
+
+    /// A result of a HEAD request which didn't meet its expected status
+    fn result() -> Latency {
+        Latency {
+            url: "http://www.example.com/?a=1&b".to_string(),
+            method: "HEAD".to_string(),
+            group: Some("home".to_string()),
+            started_at: "2016-01-20T10:15:02.114Z".to_string(),
+            latency_ms: 212,
+            status: Some(404),
+            expectation_failures: vec!["expected status 200 but got 404".to_string()],
+            ..Latency::default()
+        }
+    }
+
+    #[test]
+    /// Each result should become an entry whose timings add up to its time
+    fn exports_results() {
+        let har = to_har(&Run::start(vec![], None).finish(vec![result()]));
+        let entry = &har.find_path(&["log", "entries"]).unwrap().as_array().unwrap()[0];
+        assert_eq!(entry.find("time"), Some(&Json::I64(212)));
+        assert_eq!(entry.find_path(&["request", "method"]).unwrap().as_string(), Some("HEAD"));
+        assert_eq!(entry.find_path(&["response", "status"]), Some(&Json::U64(404)));
+        assert_eq!(entry.find_path(&["timings", "wait"]), Some(&Json::I64(212)));
+        assert_eq!(entry.find_path(&["timings", "dns"]), Some(&Json::I64(-1)));
+        assert_eq!(entry.find("_group").unwrap().as_string(), Some("home"));
+        assert!(entry.find("_template").is_none());
+        assert_eq!(entry.find("comment").unwrap().as_string(), Some("expected status 200 but got 404"));
+        let query = entry.find_path(&["request", "queryString"]).unwrap().as_array().unwrap();
+        assert_eq!(query.len(), 2);
+        assert_eq!(query[1].find("value").unwrap().as_string(), Some(""));
+    }
+
+    #[test]
+    /// Measured phases should be exported, with the TLS handshake inside ``connect`` as HAR requires
+    fn exports_phases() {
+        let mut timed = result();
+        timed.phases = Some(Phases { blocked_ms: -1, dns_ms: 10, connect_ms: 20, ssl_ms: 30, wait_ms: 112, receive_ms: 40 });
+        let har = to_har(&Run::start(vec![], None).finish(vec![timed]));
+        let timings = har.find_path(&["log", "entries"]).unwrap().as_array().unwrap()[0].find("timings").unwrap().clone();
+        let phases: Vec<Option<i64>> = ["blocked", "dns", "connect", "ssl", "send", "wait", "receive"].iter()
+                                           .map(|p| timings.find(p).and_then(|ms| ms.as_i64())).collect();
+        assert_eq!(phases, vec![Some(-1), Some(10), Some(50), Some(30), Some(0), Some(112), Some(40)]);
+    }
+
+    #[test]
+    /// An upload's time sending its body should be its ``send`` phase, and its size the request's
+    fn exports_uploads() {
+        let mut upload = result();
+        upload.method = "POST".to_string();
+        upload.upload = Some(Upload {
+            bytes: 65536,
+            bytes_sent: 65536,
+            chunked: false,
+            expect_continue: false,
+            continue_ms: None,
+            upload_ms: 150,
+            upload_bps: Some(436906.7),
+            response_ms: 40,
+        });
+        let har = to_har(&Run::start(vec![], None).finish(vec![upload]));
+        let entry = &har.find_path(&["log", "entries"]).unwrap().as_array().unwrap()[0];
+        assert_eq!(entry.find_path(&["timings", "send"]), Some(&Json::I64(150)));
+        assert_eq!(entry.find_path(&["timings", "wait"]), Some(&Json::I64(62)));
+        assert_eq!(entry.find_path(&["request", "bodySize"]), Some(&Json::I64(65536)));
+    }
+
+    #[test]
+    /// A page's requests should also count the TLS handshake as part of ``connect``
+    fn exports_page_phases() {
+        let resource = Resource {
+            url: "https://www.example.com/".to_string(),
+            kind: ResourceKind::Document,
+            initiator: None,
+            started_at: "2016-01-20T10:15:02.114Z".to_string(),
+            queued_ms: 0,
+            start_ms: 0,
+            total_ms: 100,
+            phases: Phases { blocked_ms: -1, dns_ms: 10, connect_ms: 20, ssl_ms: 30, wait_ms: 25, receive_ms: 15 },
+            status: Some(200),
+            content_type: None,
+            size: 0,
+            error: None,
+        };
+        let page = PageLoad { url: resource.url.clone(), document_ms: 100, page_complete_ms: 100,
+                              requests: 1, failed: 0, bytes: 0, resources: vec![resource] };
+        let har = page_to_har(&Run::start(vec![], None).finish(page));
+        let entry = &har.find_path(&["log", "entries"]).unwrap().as_array().unwrap()[0];
+        assert_eq!(entry.find_path(&["timings", "connect"]), Some(&Json::I64(50)));
+        assert_eq!(entry.find_path(&["timings", "ssl"]), Some(&Json::I64(30)));
+    }
+}
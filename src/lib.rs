@@ -3,26 +3,140 @@ extern crate rustc_serialize;
 extern crate time;
 #[macro_use]
 extern crate log;
-extern crate url;
+extern crate rand;
+extern crate sha1;
+extern crate hostname;
+extern crate toml;
+extern crate idna;
+extern crate flate2;
+extern crate openssl;
+extern crate xml;
+extern crate yaml_rust;
+extern crate brotli_decompressor;
+extern crate zstd;
 
+use std::io;
+use std::io::Read;
+use std::time::Duration;
 use std::error::Error;
-use std::convert::AsRef;
 
-use url::Url;
-use url::SchemeData::{Relative, NonRelative};
 
 use hyper::Client;
-use hyper::client::IntoUrl;
-use hyper::header::{Connection, UserAgent};
+use hyper::client::{IntoUrl, RedirectPolicy, Response};
+use hyper::header::{Connection, ContentEncoding, ContentType, Headers, Location, UserAgent};
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use hyper::net::{NetworkConnector, NetworkStream};
+
+use connector::{ConnectTimings, TargetConnector};
+use retry::{Attempt, ErrorKind, RetryPolicy};
+use target::{Measure, Target};
+use upload::UploadPolicy;
+
+/// The user agent requests are sent with unless a target configures its own
+const USER_AGENT: &'static str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_10_5) AppleWebKit/537.36 \
+                                  (KHTML, like Gecko) Chrome/47.0.2526.106 Safari/537.36";
+
+/// How long fetching a document, such as a sitemap, may wait for the server to connect or send
+/// more of the response, in milliseconds
+const FETCH_TIMEOUT_MS: u64 = 30 * 1000;
+
+/// The largest document ``fetch_document`` will download. Documents are held in memory whole to be
+/// parsed, and no page or sitemap worth reading is this large.
+pub const MAX_DOCUMENT_BYTES: u64 = 50 * 1024 * 1024;
+
+pub mod canonical;
+pub mod compare;
+pub mod compression;
+pub mod config;
+pub mod connector;
+pub mod crawl;
+pub mod curl;
+pub mod envelope;
+pub mod har;
+pub mod load;
+pub mod normalize;
+pub mod openapi;
+pub mod page;
+pub mod postman;
+pub mod ratelimit;
+pub mod retry;
+pub mod scheduler;
+pub mod sitemap;
+pub mod stats;
+pub mod target;
+pub mod template;
+pub mod throughput;
+pub mod upload;
+pub mod urllist;
 
 /// A Latency records the site which it is measuring and the latency of that site in milliseconds
-#[derive(RustcEncodable, Debug, Clone)]
+#[derive(RustcEncodable, Debug, Clone, Default)]
 pub struct Latency {
     /// The url of the website being tested
     pub url: String,
 
-    /// The time in milliseconds it took to retrieve ``url``
+    /// The HTTP method used to request ``url``
+    pub method: String,
+
+    /// The group of targets ``url`` was configured in, if any
+    pub group: Option<String>,
+
+    /// The template ``url`` was expanded from, if any
+    pub template: Option<String>,
+
+    /// When the first attempt to retrieve ``url`` started, as an RFC 3339 timestamp in UTC
+    pub started_at: String,
+
+    /// The time in milliseconds it took to retrieve ``url`` on the final attempt
     pub latency_ms: i64, // convert to Option<i32> ?
+
+    /// The HTTP status code returned on the final attempt
+    pub status: Option<u16>,
+
+    /// The ways the final response didn't meet the target's expectations. Empty if it met them all.
+    pub expectation_failures: Vec<String>,
+
+    /// The url first tried, if it couldn't be reached and ``url`` was requested instead
+    pub fallback_from: Option<String>,
+
+    /// The page which linked to ``url``, if it was found by crawling
+    pub linked_from: Option<String>,
+
+    /// How much of the response ``latency_ms`` waited for
+    pub measure: Measure,
+
+    /// The size of the response body in bytes, if it was read in full
+    pub bytes: Option<u64>,
+
+    /// The rate the response body was delivered at in bytes per second, averaged over the whole
+    /// request as curl's ``speed_download`` is, if it was read in full
+    pub throughput_bps: Option<f64>,
+
+    /// The ``Content-Encoding`` of the final response, if it had one
+    pub content_encoding: Option<String>,
+
+    /// The size of the response body once decoded, if it was read in full and could be decoded
+    pub decompressed_bytes: Option<u64>,
+
+    /// How long decoding the response body took in milliseconds, if it was decoded
+    pub decompress_ms: Option<f64>,
+
+    /// How the response body downloaded over time, if it was read in full and sampled (see
+    /// ``throughput``)
+    pub throughput_curve: Option<throughput::Curve>,
+
+    /// How long each part of sending the request body took, if the target is an upload (see
+    /// ``upload``)
+    pub upload: Option<upload::Upload>,
+
+    /// How long each phase of the final attempt took: looking up the host, connecting, the TLS
+    /// handshake, waiting for the response and reading the body. The connection phases are those
+    /// of the last connection made, so with redirects ``wait_ms`` covers the earlier requests too.
+    pub phases: Option<page::Phases>,
+
+    /// Every attempt made to retrieve ``url``, in order. Has more than one entry when retried.
+    pub attempts: Vec<Attempt>,
 }
 
 /// Checks that a url is a valid http or https uri
@@ -67,17 +181,17 @@ pub fn valid_http_url(s: String) -> Option<String> {
     }
 }
 
-/// Given a HTTP(S) URL returns a fully qualified version
+/// Given a HTTP(S) URL returns its canonical form
 ///
-/// If ``s`` is already a fully qualified http(s) address, returns s.
-/// If ``s`` is explicitly not http(s) or is not a parseable URL, returns None.
+/// If ``s`` is explicitly not http(s) or is not a parseable URL, logs why and returns None.
 /// If ``s`` is missing a scheme will add http scheme unless port 443 is explicitly specified.
+/// See ``canonical::canonicalize`` for the normalizations made.
 ///
 /// # Examples
 ///
 /// ```
 /// let foo = httplatency::canonicalize_http_address(&"www.google.com".to_string());
-/// assert_eq!(foo.unwrap(), "http://www.google.com".to_string());
+/// assert_eq!(foo.unwrap(), "http://www.google.com/".to_string());
 /// ```
 ///
 /// ```
@@ -87,12 +201,12 @@ pub fn valid_http_url(s: String) -> Option<String> {
 ///
 /// ```
 /// let foo = httplatency::canonicalize_http_address(&"www.google.com:443".to_string());
-/// assert_eq!(foo.unwrap(), "https://www.google.com:443".to_string());
+/// assert_eq!(foo.unwrap(), "https://www.google.com/".to_string());
 /// ```
 ///
 /// ```
 /// let foo = httplatency::canonicalize_http_address(&"www.google.com:8080".to_string());
-/// assert_eq!(foo.unwrap(), "http://www.google.com:8080".to_string());
+/// assert_eq!(foo.unwrap(), "http://www.google.com:8080/".to_string());
 /// ```
 ///
 /// ```
@@ -100,104 +214,436 @@ pub fn valid_http_url(s: String) -> Option<String> {
 /// assert!(foo.is_none());
 /// ```
 pub fn canonicalize_http_address(s: &String) -> Option<String> {
-    /* Hyper's URL parser will treat a missing scheme as invalid.
-       If the port is 443, we insert "https" as the scheme, otherwise we inster "http"
-    */
-    match s.into_url(){
-        Ok(u) => canonicalize_http_url(u),
-        Err(_) => {
-            // IntoUrl borks at missing schemes, so...
-            // we trick IntoUrl by giving the address a fake scheme
-            let url = format!("fake://{}", s).into_url();
-            match url {
-                Ok(v) => {
-                    // Having tricked IntoUrl into parsing we now set scheme back to ""
-                    let u = Url {
-                        scheme: "".to_string(),
-                        scheme_data: v.scheme_data,
-                        query: v.query,
-                        fragment: v.fragment,
-                    };
-                    canonicalize_http_url(u)
-                },
-                Err(e) => {
-                    warn!("Unable to parse URL: {} ({})", s, e);
-                    None
-                }
-            }
-        },
+    match canonical::canonicalize(s) {
+        Ok(url) => Some(url),
+        Err(e) => {
+            warn!("Unable to use URL: {} ({})", s, e);
+            None
+        }
     }
 }
 
-fn canonicalize_http_url(url: Url) -> Option<String> {
-    match url.scheme.as_ref() {
-        "http" | "https" => Some(url.serialize()),
-        _ => match url.scheme_data {
-            // Scheme set to something other than "http" or "https" AND
-            // scheme_data == Relative - means a scheme other than "http" or "https" was specified
-            Relative(_) => None,
-            // Scheme set to something other than "http" or "https" AND
-            // scheme_data == NonRelative - just means there is no scheme
-            // In this case IntoUrl will put the domain in url.scheme so we can't
-            // just check it's empty
-            NonRelative(ref port) => match port.parse::<i32>() {
-                Ok(443) => Some(format!("https://{}", url.serialize())),   // we assume any port other than 443 is http
-                Ok(_)   => Some(format!("http://{}", url.serialize())),
-                Err(_)  => {
-                    let mut p = port.clone();
-                    p.truncate(port.len()-1);
-                    match p.parse::<i32>() {  // Sometimes the port will have a trailing slash. Let's remove it and try to match again
-                        Ok(443) => Some(format!("https://{}", url.serialize())),
-                        Ok(_)   => Some(format!("http://{}", url.serialize())),
-                        _       => Some(format!("http{}", url.serialize())),   // we assume any port other than 443 is http
-                    }
+/// Returns the host of a http(s) url, in lower case, as used to group requests to the same server
+///
+/// # Examples
+///
+/// ```
+/// let host = httplatency::http_host(&"http://WWW.Google.com:8080/search".to_string());
+/// assert_eq!(host.unwrap(), "www.google.com".to_string());
+/// ```
+///
+/// ```
+/// let host = httplatency::http_host(&"www.google.com".to_string());
+/// assert!(host.is_none());
+/// ```
+pub fn http_host(s: &String) -> Option<String> {
+    match s.into_url() {
+        Ok(u) => u.serialize_host().map(|h| h.to_lowercase()),
+        Err(_) => None,
+    }
+}
+
+/// What was received in response to a request
+struct Received {
+    status: u16,
+    content_encoding: Option<String>,
+    /// The size of the body, if it was read in full
+    bytes: Option<u64>,
+    /// The body, if it was read in full and needs decoding, or why it wasn't kept
+    encoded: Option<Result<Vec<u8>, String>>,
+    /// How the body downloaded, if it was read in full and sampled
+    curve: Option<throughput::Curve>,
+    phases: page::Phases,
+}
+
+/// Makes the HTTP request described by ``target``, returning the status code of the response and,
+/// if the body was read in full, its size
+///
+/// The request returns once as much of the response as the target's ``measure`` asks for has been
+/// received. The rest of the body is never read. An encoded body read in full is kept so it can
+/// be decoded once the request has been timed, unless it's larger than
+/// ``compression::MAX_DECODE_BYTES``.
+///
+/// # Failures
+///
+/// Returns an Err if the url can't be requested (e.g. it is invalid, the domain can't be resolved,
+/// the server refuses the connection or doesn't respond within the target's timeout), or the body
+/// can't be read.
+fn fetch_target(target: &Target) -> hyper::Result<Received> {
+    // Connect as the target asks, timing each step of making the connection
+    let connector = try!(TargetConnector::new(target).map_err(|e| {
+        hyper::Error::Io(io::Error::new(io::ErrorKind::Other, e))
+    }));
+    let timings = connector.timings();
+    let mut client = Client::with_connector(connector);
+    if let Some(ms) = target.timeout_ms {
+        client.set_read_timeout(Some(Duration::from_millis(ms)));
+        client.set_write_timeout(Some(Duration::from_millis(ms)));
+    }
+    let start_ns = time::precise_time_ns();
+    let mut res = try!(send_target(&client, target));
+    let responded_ns = time::precise_time_ns();
+    let connect = timings.lock().map(|t| *t).unwrap_or_default();
+    let mut received = Received {
+        status: res.status.to_u16(),
+        content_encoding: res.headers.get::<ContentEncoding>().map(|e| e.to_string()),
+        bytes: None,
+        encoded: None,
+        curve: None,
+        phases: split_phases(&connect, start_ns, responded_ns),
+    };
+    match target.measure.unwrap_or_default() {
+        Measure::Headers => (),
+        Measure::FirstByte => {
+            try!(res.read(&mut [0; 1]));
+            received.phases.receive_ms = elapsed_ms(responded_ns, time::precise_time_ns());
+        }
+        Measure::FullBody => {
+            let body_start_ns = time::precise_time_ns();
+            // Encoded bodies are kept to be decoded, the rest are only counted
+            let mut keep = received.content_encoding.as_ref().map(|e| e != "identity").unwrap_or(false);
+            let mut body = Vec::new();
+            let mut bytes = 0;
+            let mut reads = Vec::new();
+            let mut buffer = [0; 16 * 1024];
+            loop {
+                let n = try!(res.read(&mut buffer));
+                if target.throughput.is_some() {
+                    reads.push((time::precise_time_ns(), n));
+                }
+                if n == 0 {
+                    break;
+                }
+                bytes += n as u64;
+                if keep && bytes > compression::MAX_DECODE_BYTES {
+                    keep = false;
+                    body = Vec::new();
+                    received.encoded = Some(Err(format!("larger than {} bytes, so it wasn't kept to decode",
+                                                        compression::MAX_DECODE_BYTES)));
+                }
+                if keep {
+                    body.extend_from_slice(&buffer[..n]);
                 }
             }
+            received.bytes = Some(bytes);
+            if keep {
+                received.encoded = Some(Ok(body));
+            }
+            received.curve = target.throughput.map(|policy| throughput::analyse(&policy, start_ns, body_start_ns, &reads));
+            received.phases.receive_ms = elapsed_ms(responded_ns, time::precise_time_ns());
         }
     }
+    Ok(received)
 }
 
-/// Makes a HTTP GET request for the given site
+/// The phases of a request which started at ``start_ns`` and had a response by ``responded_ns``,
+/// given the timings of its connection. The body isn't counted, so ``receive_ms`` is -1.
 ///
-/// # Panics
+/// ``wait_ms`` is the time to the response less the time taken connecting.
+fn split_phases(connect: &ConnectTimings, start_ns: u64, responded_ns: u64) -> page::Phases {
+    let setup_ms: i64 = [connect.dns_ms, connect.connect_ms, connect.ssl_ms].iter().filter(|&&ms| ms > 0).sum();
+    page::Phases {
+        dns_ms: connect.dns_ms,
+        connect_ms: connect.connect_ms,
+        ssl_ms: connect.ssl_ms,
+        wait_ms: (elapsed_ms(start_ns, responded_ns) - setup_ms).max(0),
+        ..page::Phases::default()
+    }
+}
+
+/// The whole milliseconds between two readings of ``time::precise_time_ns``, or 0 if the second
+/// is earlier
 ///
-/// This function panics when:
-///  * given a url with a domain that can't be resolved, or
-///  * given an invalid url
+/// # Examples
+///
+/// ```
+/// use httplatency::elapsed_ms;
+///
+/// assert_eq!(elapsed_ms(1_000_000, 3_500_000), 2);
+/// assert_eq!(elapsed_ms(3_500_000, 1_000_000), 0);
+/// ```
+pub fn elapsed_ms(from_ns: u64, to_ns: u64) -> i64 {
+    (to_ns.saturating_sub(from_ns) / 1_000_000) as i64
+}
+
+/// Sends the request described by ``target`` with ``client``, returning the response once its
+/// headers have been received
+fn send_target(client: &Client, target: &Target) -> hyper::Result<Response> {
+    let mut headers = Headers::new();
+    // set a header
+    headers.set(Connection::close());
+    // set a fake user agent
+    headers.set(UserAgent(USER_AGENT.to_string()));
+    // configured headers replace the defaults
+    for &(ref name, ref value) in target.headers.iter() {
+        headers.set_raw(name.clone(), vec![value.clone().into_bytes()]);
+    }
+    // Methods are checked when targets are configured, so this only fails for unusual extensions
+    let method = target.method.parse().unwrap_or(Method::Extension(target.method.clone()));
+    // Creating an outgoing request.
+    let mut request = client.request(method, &target.url).headers(headers);
+    if let Some(ref body) = target.body {
+        request = request.body(&body[..]);
+    }
+    // let 'er go!
+    request.send()
+}
+
+/// Sends the upload described by ``target`` over a connection of its own, returning the status code
+/// of the response, how long each part of the upload took and the phases of the whole request
 ///
-/// These panics are generated from within hyper. Unfortunately stable versions of rust have no way of
-/// catching this panic.
+/// The phases' ``wait_ms`` includes sending the body, which the ``Upload`` breaks down.
 ///
 /// # Failures
 ///
-/// If a webserver holds the connection open, this function will block until the full repsonse is received.
-fn fetch_url(url: &String) {
-    // Create a client.
-    let client = Client::new();
-    // Creating an outgoing request.
-    client.get(url)
-        // set a header
-        .header(Connection::close())
-        // set a fake user agent
-        .header(UserAgent("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_10_5) AppleWebKit/537.36 \
-                          (KHTML, like Gecko) Chrome/47.0.2526.106 Safari/537.36".to_string()))
-        // let 'er go!
-        .send().unwrap();
+/// Returns an Err if the url can't be requested (e.g. it is invalid, the domain can't be resolved,
+/// the server refuses the connection or doesn't respond within the target's timeout), or the
+/// response isn't valid HTTP.
+fn fetch_upload(target: &Target, policy: &UploadPolicy, body: &[u8])
+                -> hyper::Result<(u16, upload::Upload, page::Phases)> {
+    let invalid = |reason: &str| hyper::Error::Io(io::Error::new(io::ErrorKind::InvalidInput, reason.to_string()));
+    let url = try!(target.url.clone().into_url());
+    let host = try!(url.serialize_host().ok_or(invalid("the url has no host")));
+    let port = try!(url.port_or_default().ok_or(invalid("the url has no port")));
+    let mut path = url.serialize_path().unwrap_or("/".to_string());
+    if let Some(ref query) = url.query {
+        path.push('?');
+        path.push_str(query);
+    }
+    let host_header = match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.clone(),
+    };
+
+    let connector = try!(TargetConnector::new(target).map_err(|e| {
+        hyper::Error::Io(io::Error::new(io::ErrorKind::Other, e))
+    }));
+    let start_ns = time::precise_time_ns();
+    let mut stream = try!(connector.connect(&host, port, &url.scheme));
+    let timeout = target.timeout_ms.map(Duration::from_millis);
+    try!(stream.set_read_timeout(timeout));
+    try!(stream.set_write_timeout(timeout));
+
+    let mut headers = vec![("User-Agent".to_string(), USER_AGENT.to_string())];
+    headers.retain(|h| !target.headers.iter().any(|t| t.0.eq_ignore_ascii_case(&h.0)));
+    headers.extend(target.headers.iter().cloned());
+    let head = upload::request_head(&target.method, &host_header, &path, &headers, body.len() as u64, policy);
+    let (status, upload) = try!(upload::exchange(&mut stream, &head, body, policy, timeout));
+    let connect = connector.timings().lock().map(|t| *t).unwrap_or_default();
+    Ok((status, upload, split_phases(&connect, start_ns, time::precise_time_ns())))
 }
 
-/// Requests the given url measuring the time taken and returning a Result
+/// Makes the HTTP request described by ``target`` without following redirects, reading the whole
+/// response and timing each phase of the request, e.g. to load a resource of a page (see ``page``)
+///
+/// The connection's phases are timed by a ``TargetConnector``, ``wait`` is the rest of the time
+/// until the response headers arrive and ``receive`` is the time taken to read the body. Failures
+/// are recorded in the result's ``error``.
+pub fn fetch_timed(target: &Target) -> page::Fetched {
+    let mut fetched = page::Fetched {
+        status: None,
+        content_type: None,
+        location: None,
+        body: Vec::new(),
+        phases: page::Phases::default(),
+        error: None,
+    };
+    let connector = match TargetConnector::new(target) {
+        Ok(c) => c,
+        Err(err) => {
+            fetched.error = Some(err);
+            return fetched;
+        }
+    };
+    let timings = connector.timings();
+    let mut client = Client::with_connector(connector);
+    client.set_redirect_policy(RedirectPolicy::FollowNone);
+    if let Some(ms) = target.timeout_ms {
+        client.set_read_timeout(Some(Duration::from_millis(ms)));
+        client.set_write_timeout(Some(Duration::from_millis(ms)));
+    }
+
+    let start = time::precise_time_ns();
+    let result = send_target(&client, target);
+    let responded = time::precise_time_ns();
+    let connect = timings.lock().map(|t| *t).unwrap_or_default();
+    fetched.phases.dns_ms = connect.dns_ms;
+    fetched.phases.connect_ms = connect.connect_ms;
+    fetched.phases.ssl_ms = connect.ssl_ms;
+    let mut res = match result {
+        Ok(res) => res,
+        Err(err) => {
+            fetched.error = Some(err.to_string());
+            return fetched;
+        }
+    };
+    fetched.phases.wait_ms = split_phases(&connect, start, responded).wait_ms;
+    fetched.status = Some(res.status.to_u16());
+    fetched.content_type = res.headers.get::<ContentType>().map(|c| c.to_string());
+    fetched.location = res.headers.get::<Location>().map(|l| l.to_string());
+    if let Err(err) = res.read_to_end(&mut fetched.body) {
+        fetched.error = Some(err.to_string());
+    }
+    fetched.phases.receive_ms = elapsed_ms(responded, time::precise_time_ns());
+    fetched
+}
+
+/// Downloads the body of ``url``, e.g. a sitemap to read targets from
+///
+/// # Failures
+///
+/// Returns an Err if the url can't be requested, the response doesn't have a 2xx status or the
+/// body can't be read or is larger than ``MAX_DOCUMENT_BYTES``.
+pub fn fetch_body(url: &str) -> Result<Vec<u8>, String> {
+    let document = try!(fetch_document(url));
+    if document.status < 200 || document.status >= 300 {
+        return Err(format!("server responded {}", StatusCode::from_u16(document.status)));
+    }
+    Ok(document.body)
+}
+
+/// Downloads ``url`` along with its status, content type and the url it was redirected to, e.g. a
+/// page to find links in while crawling
+///
+/// The request is sent with the same user agent as probes, and gives up if the server takes more
+/// than ``FETCH_TIMEOUT_MS`` to send any part of the response. Responses with any status are
+/// returned, so callers can tell a missing document from an unavailable one.
+///
+/// # Failures
+///
+/// Returns an Err if the url can't be requested, or the body can't be read or is larger than
+/// ``MAX_DOCUMENT_BYTES``.
+pub fn fetch_document(url: &str) -> Result<crawl::Document, String> {
+    let mut client = Client::new();
+    client.set_read_timeout(Some(Duration::from_millis(FETCH_TIMEOUT_MS)));
+    client.set_write_timeout(Some(Duration::from_millis(FETCH_TIMEOUT_MS)));
+    let mut headers = Headers::new();
+    headers.set(Connection::close());
+    headers.set(UserAgent(USER_AGENT.to_string()));
+    let mut res = try!(client.get(url).headers(headers).send().map_err(|e| e.to_string()));
+    let mut body = Vec::new();
+    // Reading one byte more than allowed tells a body which is too large from one which just fits
+    try!(res.by_ref().take(MAX_DOCUMENT_BYTES + 1).read_to_end(&mut body).map_err(|e| e.to_string()));
+    if body.len() as u64 > MAX_DOCUMENT_BYTES {
+        return Err(format!("larger than {} bytes", MAX_DOCUMENT_BYTES));
+    }
+    Ok(crawl::Document {
+        url: res.url.to_string(),
+        status: res.status.to_u16(),
+        content_type: res.headers.get::<ContentType>().map(|c| c.to_string()),
+        body: body,
+    })
+}
+
+/// Sorts an error from hyper into the broad categories used by a ``RetryPolicy``
+fn classify_error(err: &hyper::Error) -> ErrorKind {
+    match *err {
+        hyper::Error::Io(ref e) => match e.kind() {
+            io::ErrorKind::ConnectionRefused |
+            io::ErrorKind::ConnectionReset |
+            io::ErrorKind::ConnectionAborted |
+            io::ErrorKind::NotConnected => ErrorKind::Connect,
+            io::ErrorKind::TimedOut |
+            io::ErrorKind::WouldBlock => ErrorKind::Timeout,
+            _ => ErrorKind::Io,
+        },
+        hyper::Error::Uri(_) => ErrorKind::InvalidUrl,
+        hyper::Error::Ssl(_) => ErrorKind::Tls,
+        _ => ErrorKind::Protocol,
+    }
+}
+
+/// Requests the given url once, recording how long it took and whether it succeeded
 ///
-/// # Panics
+/// # Examples
 ///
-/// This function panics when:
-///  * given a url with a domain that can't be resolved, or
-///  * given an invalid url
+/// ```
+/// // Fails because this url has no scheme, but the attempt is still recorded
+/// let foo = httplatency::attempt_latency(&"www.google.com".to_string());
+/// assert!(foo.error.is_some());
+/// ```
+pub fn attempt_latency(s: &String) -> Attempt {
+    attempt_target(&Target::get(s.clone()))
+}
+
+/// Makes the request described by ``target`` once, recording how long it took and whether it succeeded
 ///
-/// These panics are generated from within hyper. Unfortunately stable versions of rust have no way of
-/// catching this panic.
+/// An encoded body read in full is decoded after the request has been timed, so decoding doesn't
+/// count towards its latency.
+pub fn attempt_target(target: &Target) -> Attempt {
+    if let Some(ref policy) = target.upload {
+        return attempt_upload(target, policy);
+    }
+    let start = time::now();
+    let result = fetch_target(target);
+    let duration = (time::now() - start).num_milliseconds();
+    match result {
+        Ok(received) => {
+            let decoded = match (&received.content_encoding, &received.encoded) {
+                (&Some(ref encoding), &Some(ref body)) => {
+                    let result = match *body {
+                        Ok(ref body) => compression::decode_timed(encoding, body),
+                        Err(ref err) => Err(err.clone()),
+                    };
+                    match result {
+                        Ok(decoded) => Some(decoded),
+                        Err(err) => {
+                            warn!("{}: couldn't decode the response body: {}", target.url, err);
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
+            Attempt {
+                latency_ms: duration,
+                status: Some(received.status),
+                bytes: received.bytes,
+                content_encoding: received.content_encoding,
+                decompressed_bytes: decoded.map(|d| d.0),
+                decompress_ms: decoded.map(|d| d.1),
+                throughput_curve: received.curve,
+                phases: Some(received.phases),
+                ..Attempt::default()
+            }
+        }
+        Err(err) => Attempt::failure(duration, err.to_string(), classify_error(&err)),
+    }
+}
+
+/// Sends the upload described by ``target`` once, recording how long it took and whether it succeeded
+///
+/// The body is generated before the request is timed. A body which can't be generated (e.g. its
+/// file is missing) is recorded as an ``Io`` error without anything being sent.
+fn attempt_upload(target: &Target, policy: &UploadPolicy) -> Attempt {
+    let body = match upload::body(&policy.payload) {
+        Ok(body) => body,
+        Err(err) => return Attempt::failure(0, err, ErrorKind::Body),
+    };
+    let start = time::now();
+    let result = fetch_upload(target, policy, &body);
+    let duration = (time::now() - start).num_milliseconds();
+    match result {
+        Ok((status, upload, phases)) => Attempt {
+            latency_ms: duration,
+            status: Some(status),
+            upload: Some(upload),
+            phases: Some(phases),
+            ..Attempt::default()
+        },
+        Err(err) => Attempt::failure(duration, err.to_string(), classify_error(&err)),
+    }
+}
+
+/// Requests the given url measuring the time taken and returning a Result
+///
+/// Only a single attempt is made; see ``record_latency_with_retry`` to retry transient failures.
 ///
 /// # Failures
 ///
+/// Returns an Err when given an invalid url, a url with a domain that can't be resolved, or a
+/// url whose server can't be connected to.
+///
 /// If a webserver holds the connection open, this function will block until the full
 /// repsonse is received.
 ///
@@ -208,70 +654,195 @@ fn fetch_url(url: &String) {
 /// assert!(foo.latency_ms > 0)
 /// ```
 ///
-/// ```should_panic
-/// // Hyper will panic because this isn't a real url
+/// ```
+/// // Fails because this url has no scheme
 /// let foo = httplatency::record_latency(&"www.google.com".to_string());
+/// assert!(foo.is_err());
 /// ```
 ///
-/// ```should_panic
-/// // Hyper will panic because this isn't a real url
+/// ```
+/// // Fails because this isn't a real url
 /// let bar = httplatency::record_latency(&"abcdefgh".to_string());
+/// assert!(bar.is_err());
 /// ```
 ///
-/// ```should_panic
-/// // Hyper will panic because this URL isn't resolveable
-/// let baz = httplatency::record_latency(&"thisdomainisgarbage-hfgvjfhgdkjhdsfjhgsdjh.com".to_string());
+/// ```
+/// // Fails because this URL isn't resolveable
+/// let baz = httplatency::record_latency(&"http://thisdomainisgarbage-hfgvjfhgdkjhdsfjhgsdjh.com".to_string());
+/// assert!(baz.is_err());
 /// ```
 pub fn record_latency(s: &String) -> Result<Latency, String>  {
-    let start = time::now();
-    fetch_url(&s);
-    let duration = (time::now() - start).num_milliseconds();
-    return Ok( Latency {url: s.clone(), latency_ms: duration} );
+    record_latency_with_retry(s, &RetryPolicy::none())
 }
 
-/// Requests the given URL measuring the time taken and returning an Option
+/// Requests the given url, retrying transient failures according to ``policy``
 ///
-/// # Panics
+/// The returned ``Latency`` reports the final attempt's latency and keeps every attempt made, so
+/// a url which only succeeded on its third try can be told apart from one that succeeded first time.
 ///
-/// This function panics when:
-///  * given a url with a domain that can't be resolved, or
-///  * given an invalid url
+/// # Failures
+///
+/// Returns an Err if the final attempt failed to get a response.
+///
+/// # Examples
+/// ```
+/// use httplatency::retry::RetryPolicy;
+///
+/// let foo = httplatency::record_latency_with_retry(&"http://www.google.com".to_string(),
+///                                                  &RetryPolicy::default()).unwrap();
+/// assert!(foo.attempts.len() >= 1)
+/// ```
+pub fn record_latency_with_retry(s: &String, policy: &RetryPolicy) -> Result<Latency, String> {
+    record_target(&Target::get(s.clone()), policy)
+}
+
+/// Makes the request described by ``target``, retrying transient failures according to ``policy``
 ///
-/// These panics are generated from within hyper. Unfortunately stable versions of rust have no way of
-/// catching this panic.
+/// A response which doesn't meet the target's expectations (e.g. has an unexpected status) is
+/// still returned, with the reasons listed in ``expectation_failures``. If the target has a
+/// ``fallback_url`` it is requested when ``url`` gets no response, and every attempt at both is kept.
 ///
 /// # Failures
 ///
+/// Returns an Err if the final attempt failed to get a response.
+///
+/// # Examples
+/// ```
+/// use httplatency::retry::RetryPolicy;
+/// use httplatency::target::Target;
+///
+/// let mut target = Target::get("http://www.google.com".to_string());
+/// target.method = "HEAD".to_string();
+/// target.max_latency_ms = Some(0);
+/// let foo = httplatency::record_target(&target, &RetryPolicy::none()).unwrap();
+/// assert_eq!(foo.method, "HEAD");
+/// assert_eq!(foo.expectation_failures.len(), 1);
+/// ```
+pub fn record_target(target: &Target, policy: &RetryPolicy) -> Result<Latency, String> {
+    let started_at = envelope::format_timestamp(&time::now_utc());
+    let mut attempts = policy.run(|| attempt_target(target));
+    let reached = attempts[attempts.len() - 1].is_response();
+    let (url, fallback_from) = match target.fallback_url {
+        Some(ref fallback) if !reached => {
+            info!("{} couldn't be reached, trying {}", target.url, fallback);
+            let mut fallback_target = target.clone();
+            fallback_target.url = fallback.clone();
+            attempts.extend(policy.run(|| attempt_target(&fallback_target)));
+            (fallback.clone(), Some(target.url.clone()))
+        }
+        _ => (target.url.clone(), None),
+    };
+    let last = attempts[attempts.len() - 1].clone();
+    match last.error {
+        Some(err) if attempts.len() > 1 => Err(format!("{} (gave up after {} attempts)", err, attempts.len())),
+        Some(err) => Err(err),
+        None => {
+            if attempts.len() > 1 {
+                info!("{} succeeded after {} attempts", url, attempts.len());
+            }
+            let failures = target.check(last.latency_ms, last.status);
+            for failure in failures.iter() {
+                warn!("{} {}", url, failure);
+            }
+            Ok(Latency {
+                url: url,
+                method: target.method.clone(),
+                group: target.group.clone(),
+                template: target.template.clone(),
+                started_at: started_at,
+                latency_ms: last.latency_ms,
+                status: last.status,
+                expectation_failures: failures,
+                fallback_from: fallback_from,
+                linked_from: target.linked_from.clone(),
+                measure: target.measure.unwrap_or_default(),
+                bytes: last.bytes,
+                throughput_bps: last.bytes.and_then(|b| throughput_bps(b, last.latency_ms)),
+                content_encoding: last.content_encoding.clone(),
+                decompressed_bytes: last.decompressed_bytes,
+                decompress_ms: last.decompress_ms,
+                throughput_curve: last.throughput_curve.clone(),
+                upload: last.upload.clone(),
+                phases: last.phases,
+                attempts: attempts,
+            })
+        }
+    }
+}
+
+/// The rate ``bytes`` were delivered at over ``latency_ms``, in bytes per second, if any time passed
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(httplatency::throughput_bps(50000, 250), Some(200000.0));
+/// assert_eq!(httplatency::throughput_bps(50000, 0), None);
+/// ```
+pub fn throughput_bps(bytes: u64, latency_ms: i64) -> Option<f64> {
+    if latency_ms > 0 {
+        Some(bytes as f64 * 1000.0 / latency_ms as f64)
+    } else {
+        None
+    }
+}
+
+/// Requests the given URL measuring the time taken and returning an Option
+///
+/// # Failures
+///
+/// Returns None, logging the reason, when the url can't be requested (see ``record_latency``).
+///
 /// If a webserver holds the connection open, this function will block until the full
 /// repsonse is received.
 ///
 /// # Examples
-/// ```should_panic
-/// // Hyper will panic because this URL doesn't have a scheme
-/// let foo = httplatency::get_latency(&"www.google.com".to_string()).unwrap();
+/// ```
+/// // Fails because this URL doesn't have a scheme
+/// let foo = httplatency::get_latency(&"www.google.com".to_string());
+/// assert!(foo.is_none());
 /// ```
 ///
-/// ```should_panic
-/// // Hyper will panic because this isn't a real URL
+/// ```
+/// // Fails because this isn't a real URL
 /// let foo = httplatency::get_latency(&"abcdefgh".to_string());
+/// assert!(foo.is_none());
 /// ```
 ///
-/// ```should_panic
-/// // Hyper will panic because this URL isn't resolveable
-/// let bar = httplatency::get_latency(&"thisdomainisgarbage-hfgvjfhgdkjhdsfjhgsdjh.com".to_string());
+/// ```
+/// // Fails because this URL isn't resolveable
+/// let bar = httplatency::get_latency(&"http://thisdomainisgarbage-hfgvjfhgdkjhdsfjhgsdjh.com".to_string());
+/// assert!(bar.is_none());
 /// ```
 pub fn get_latency(site: &String) -> Option<Latency> {
-    info!("Testing {}", site);
-    let lat = record_latency(site);
+    get_latency_with_retry(site, &RetryPolicy::none())
+}
+
+/// Requests the given URL, retrying transient failures according to ``policy``, and returning an Option
+///
+/// # Failures
+///
+/// Returns None, logging the reason, when the final attempt fails (see ``record_latency_with_retry``).
+pub fn get_latency_with_retry(site: &String, policy: &RetryPolicy) -> Option<Latency> {
+    get_target_latency(&Target::get(site.clone()), policy)
+}
+
+/// Makes the request described by ``target``, retrying transient failures according to ``policy``,
+/// and returning an Option
+///
+/// # Failures
+///
+/// Returns None, logging the reason, when the final attempt fails (see ``record_target``).
+pub fn get_target_latency(target: &Target, policy: &RetryPolicy) -> Option<Latency> {
+    info!("Testing {} {}", target.method, target.url);
+    let lat = record_target(target, policy);
     if lat.is_ok() {
         lat.ok()
     } else {
-        error!("Couldn't retrieve {}: {}", site, lat.unwrap_err());
+        error!("Couldn't retrieve {}: {}", target.url, lat.unwrap_err());
         None
     }
 }
 
-
 #[cfg(test)]
 mod test {
     use super::*;
@@ -279,11 +850,17 @@ mod test {
     /************* record_latency **************/
 
     #[test]
-    #[should_panic]
-    /// Should panic and fail to get google because of missing scheme
-    /// Known issue within the hyper library
+    /// Should fail to get google because of missing scheme
     fn record_google_no_scheme() {
-        record_latency(&"www.google.com".to_string()).is_err();
+        assert!(record_latency(&"www.google.com".to_string()).is_err());
+    }
+
+    #[test]
+    /// Should record the single attempt made
+    fn record_http_google_attempts() {
+        let lat = record_latency(&"http://www.google.com".to_string()).unwrap();
+        assert_eq!(lat.attempts.len(), 1);
+        assert_eq!(lat.status, lat.attempts[0].status);
     }
 
     #[test]
@@ -318,11 +895,37 @@ mod test {
 
 
     #[test]
-    #[should_panic]
-    /// Should panic and fail to get google because of missing scheme
-    /// Known issue within the hyper library
+    /// Should fail to get google because of missing scheme
     fn get_google_no_scheme() {
-        get_latency(&"www.google.com".to_string());
+        assert!(get_latency(&"www.google.com".to_string()).is_none());
+    }
+
+    /************* record_latency_with_retry **************/
+
+    #[test]
+    /// Should give up on an unresolvable domain after the policy's maximum attempts
+    fn retry_nonexistant_domain() {
+        let policy = ::retry::RetryPolicy { max_attempts: 2, backoff_base_ms: 0, ..Default::default() };
+        let lat = record_latency_with_retry(&"http://ksdjfghlkdfsjhgfdskjghfdg.com".to_string(), &policy);
+        assert!(lat.unwrap_err().contains("2 attempts"));
+    }
+
+    #[test]
+    /// Shouldn't retry a url that can never be requested
+    fn retry_invalid_url() {
+        let attempt = attempt_latency(&"www.google.com".to_string());
+        assert_eq!(attempt.error_kind, Some(::retry::ErrorKind::InvalidUrl));
+    }
+
+    #[test]
+    /// Should request the fallback url when the first can't be reached
+    fn record_target_fallback() {
+        let mut target = ::target::Target::get("http://localhost:1/".to_string());
+        target.fallback_url = Some("http://www.google.com/".to_string());
+        let lat = record_target(&target, &RetryPolicy::none()).unwrap();
+        assert_eq!(lat.url, "http://www.google.com/");
+        assert_eq!(lat.fallback_from, Some("http://localhost:1/".to_string()));
+        assert_eq!(lat.attempts.len(), 2);
     }
 
     #[test]
@@ -360,7 +963,7 @@ mod test {
     /// More strict requirement than missing_scheme test
     fn add_scheme() {
         let url = super::canonicalize_http_address(&"www.google.com".to_string());
-        assert_eq!(url.unwrap(), "http://www.google.com")
+        assert_eq!(url.unwrap(), "http://www.google.com/")
     }
 
     #[test]
@@ -376,7 +979,7 @@ mod test {
     /// More strict requirement than missing_scheme test
     fn add_scheme_port_80() {
         let url = super::canonicalize_http_address(&"www.google.com:80".to_string());
-        assert_eq!(url.unwrap(), "http://www.google.com:80")
+        assert_eq!(url.unwrap(), "http://www.google.com/")
     }
 
     #[test]
@@ -384,7 +987,7 @@ mod test {
     /// More strict requirement than missing_scheme test
     fn add_scheme_port_80_trailing_slash() {
         let url = super::canonicalize_http_address(&"www.google.com:80/".to_string());
-        assert_eq!(url.unwrap(), "http://www.google.com:80/")
+        assert_eq!(url.unwrap(), "http://www.google.com/")
     }
 
     #[test]
@@ -392,7 +995,7 @@ mod test {
     /// More strict requirement than missing_scheme test
     fn add_scheme_port_8080() {
         let url = super::canonicalize_http_address(&"www.google.com:8080".to_string());
-        assert_eq!(url.unwrap(), "http://www.google.com:8080")
+        assert_eq!(url.unwrap(), "http://www.google.com:8080/")
     }
 
     #[test]
@@ -408,7 +1011,7 @@ mod test {
     /// More strict requirement than missing_scheme test
     fn add_scheme_port_443() {
         let url = super::canonicalize_http_address(&"www.google.com:443".to_string());
-        assert_eq!(url.unwrap(), "https://www.google.com:443")
+        assert_eq!(url.unwrap(), "https://www.google.com/")
     }
 
     #[test]
@@ -416,7 +1019,7 @@ mod test {
     /// More strict requirement than missing_scheme test
     fn add_scheme_port_443_trailing_slash() {
         let url = super::canonicalize_http_address(&"www.google.com:443/".to_string());
-        assert_eq!(url.unwrap(), "https://www.google.com:443/")
+        assert_eq!(url.unwrap(), "https://www.google.com/")
     }
 
     #[test]
@@ -593,17 +1196,14 @@ mod test {
         assert!(url.is_some(), "Hyper now rejects bad schema delimiters")
     }
 
-    /************* fetch_url **************/
+    /************* fetch_target **************/
 
     #[test]
-    #[should_panic]
-    /// Currently passes because hyper cannot resolve the domain and panic!s
-    /// In future we could try to resolve the domain in a separate step and 
-    /// then pass it to hyper only if successful - almost as a guard.
-    /// This would be susceptible to an (unlikely) race condition however where
-    /// ther domain becomes unresolvable between our guard and the call to hyper.
+    /// Hyper cannot resolve the domain so an Io error should be returned
     fn nonexistant_domain() {
-        super::fetch_url(&"http://ksdjfghlkdfsjhgfdskjghfdg.com".to_string());
+        let target = ::target::Target::get("http://ksdjfghlkdfsjhgfdskjghfdg.com".to_string());
+        let err = super::fetch_target(&target).unwrap_err();
+        assert_eq!(super::classify_error(&err), ::retry::ErrorKind::Io);
     }
     /*
      *
@@ -0,0 +1,344 @@
+//! Open-loop load generation at a fixed request rate.
+//!
+//! Requests are sent on a fixed schedule regardless of how long earlier requests take, so a slow
+//! server isn't rewarded by being asked for less ("open loop"). Each request's latency is measured
+//! from the time it was meant to be sent rather than when it actually was, so time spent queued
+//! behind slow requests is reported instead of hidden ("coordinated omission").
+
+use std::sync::Arc;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use time;
+
+use {attempt_latency, Latency};
+use envelope::format_timestamp;
+use retry::Attempt;
+use stats::Summary;
+use target::Measure;
+
+const NS_PER_MS: u64 = 1_000_000;
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+/// The most requests a single run may send, as the result of every request is kept
+pub const MAX_REQUESTS: u64 = 1_000_000;
+
+/// How much load to generate
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    /// The number of requests to send per second
+    pub rate: f64,
+
+    /// How long in milliseconds to keep sending requests for
+    pub duration_ms: u64,
+
+    /// The most requests allowed to be outstanding at once. When reached, sending waits for a
+    /// request to finish and the wait is counted in the latency of every delayed request.
+    pub max_in_flight: usize,
+}
+
+impl LoadConfig {
+    /// Checks the configuration can generate any load at all, and not more than ``MAX_REQUESTS``
+    /// requests
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::load::LoadConfig;
+    /// assert!(LoadConfig { rate: 10.0, duration_ms: 1000, max_in_flight: 5 }.validate().is_ok());
+    /// assert!(LoadConfig { rate: 0.0, duration_ms: 1000, max_in_flight: 5 }.validate().is_err());
+    /// assert!(LoadConfig { rate: 1.0 / 0.0, duration_ms: 1000, max_in_flight: 5 }.validate().is_err());
+    /// assert!(LoadConfig { rate: 1e9, duration_ms: 10000, max_in_flight: 5 }.validate().is_err());
+    /// ```
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.rate > 0.0) || !self.rate.is_finite() {
+            Err(format!("Request rate must be a number greater than zero (got {})", self.rate))
+        } else if self.max_in_flight == 0 {
+            Err("At least one request must be allowed in flight".to_string())
+        } else if self.requests() == 0 {
+            Err(format!("No requests would be sent at {} per second in {}ms", self.rate, self.duration_ms))
+        } else if self.requests() > MAX_REQUESTS {
+            Err(format!("{} per second for {}ms would send more than {} requests", self.rate, self.duration_ms, MAX_REQUESTS))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The number of requests the run will send
+    pub fn requests(&self) -> u64 {
+        // Converting a float too large for a u64 saturates, so this can't overflow
+        (self.rate * self.duration_ms as f64 / 1000.0).floor() as u64
+    }
+}
+
+/// The results of a load run against a single url
+#[derive(RustcEncodable, Debug, Clone)]
+pub struct LoadReport {
+    /// The url requested
+    pub url: String,
+
+    /// The requested rate in requests per second
+    pub target_rps: f64,
+
+    /// The total number of requests sent
+    pub requests: usize,
+
+    /// The number of requests which got a response of any status
+    pub responses: usize,
+
+    /// The number of requests which got no response or a 5xx response
+    pub errors: usize,
+
+    /// ``errors`` as a fraction of ``requests``
+    pub error_rate: f64,
+
+    /// The time in milliseconds from the first request being due to the last one finishing
+    pub elapsed_ms: i64,
+
+    /// Responses received per second over ``elapsed_ms``
+    pub throughput_rps: f64,
+
+    /// The furthest behind schedule in milliseconds any request was actually sent
+    pub max_send_lag_ms: i64,
+
+    /// The distribution of latencies of requests which got a response, measured from when each
+    /// request was due to be sent
+    pub latency: Option<Summary>,
+
+    /// Every request made, in the order they were due. ``latency_ms`` is measured from when the
+    /// request was due; the attempt's own ``latency_ms`` is the time the request itself took.
+    pub results: Vec<Latency>,
+}
+
+/// The offsets in nanoseconds from the start of a run at which each request is due, worked out
+/// as they're needed
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    interval_ns: f64,
+    next: u64,
+    total: u64,
+}
+
+impl Iterator for Schedule {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.next >= self.total {
+            return None;
+        }
+        let due = (self.next as f64 * self.interval_ns) as u64;
+        self.next += 1;
+        Some(due)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.total - self.next) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+/// Returns the offsets in nanoseconds from the start of a run at which each request is due
+///
+/// # Examples
+///
+/// ```
+/// let due: Vec<u64> = httplatency::load::schedule(&httplatency::load::LoadConfig {
+///     rate: 4.0, duration_ms: 1000, max_in_flight: 1,
+/// }).collect();
+/// assert_eq!(due, vec![0, 250000000, 500000000, 750000000]);
+/// ```
+pub fn schedule(config: &LoadConfig) -> Schedule {
+    Schedule { interval_ns: NS_PER_SEC as f64 / config.rate, next: 0, total: config.requests() }
+}
+
+/// Sends GET requests for ``url`` at the configured rate, returning a summary of the results
+///
+/// # Failures
+///
+/// Individual requests which fail are counted as errors; the run itself always completes.
+pub fn run(url: &String, config: &LoadConfig) -> LoadReport {
+    run_with(url, config, attempt_latency)
+}
+
+/// Sends requests for ``url`` at the configured rate using ``probe`` to make each one
+pub fn run_with<F>(url: &String, config: &LoadConfig, probe: F) -> LoadReport
+    where F: Fn(&String) -> Attempt + Send + Sync + 'static
+{
+    let probe = Arc::new(probe);
+    let (tx, rx) = channel();
+    let total = config.requests();
+    info!("Sending {} requests to {} at {} per second", total, url, config.rate);
+
+    let mut finished = Vec::with_capacity(total as usize);
+    let mut in_flight = 0;
+    let mut max_lag = 0;
+    let wall_start = time::now_utc();
+    let start = time::precise_time_ns();
+    for offset in schedule(config) {
+        let intended = start + offset;
+        while in_flight >= config.max_in_flight {
+            finished.push(rx.recv().unwrap());
+            in_flight -= 1;
+        }
+        let now = time::precise_time_ns();
+        if now < intended {
+            sleep_ns(intended - now);
+        }
+        let lag = time::precise_time_ns().saturating_sub(intended);
+        if lag > max_lag {
+            max_lag = lag;
+        }
+
+        let tx = tx.clone();
+        let probe = probe.clone();
+        let url = url.clone();
+        thread::spawn(move || {
+            let attempt = (*probe)(&url);
+            let done = time::precise_time_ns();
+            // The receiver outlives every sender, so this can't fail
+            tx.send((intended, done, attempt)).unwrap();
+        });
+        in_flight += 1;
+        while let Ok(result) = rx.try_recv() {
+            finished.push(result);
+            in_flight -= 1;
+        }
+    }
+    while in_flight > 0 {
+        finished.push(rx.recv().unwrap());
+        in_flight -= 1;
+    }
+    let end = time::precise_time_ns();
+    debug!("Load run against {} complete", url);
+
+    finished.sort_by(|a, b| a.0.cmp(&b.0));
+    let results: Vec<Latency> = finished.into_iter().map(|(intended, done, attempt)| {
+        Latency {
+            url: url.clone(),
+            method: "GET".to_string(),
+            group: None,
+            template: None,
+            started_at: format_timestamp(&(wall_start + time::Duration::nanoseconds((intended - start) as i64))),
+            latency_ms: ((done - intended) / NS_PER_MS) as i64,
+            status: attempt.status,
+            measure: Measure::Headers,
+            attempts: vec![attempt],
+            ..Latency::default()
+        }
+    }).collect();
+    report(url, config, results, end - start, max_lag)
+}
+
+/// Summarises the results of a run which took ``elapsed_ns`` in total
+fn report(url: &String, config: &LoadConfig, results: Vec<Latency>, elapsed_ns: u64, max_lag_ns: u64) -> LoadReport {
+    let latencies: Vec<i64> = results.iter()
+                                     .filter(|r| r.attempts[0].is_response())
+                                     .map(|r| r.latency_ms)
+                                     .collect();
+    let errors = results.iter()
+                        .filter(|r| r.status.map(|s| s >= 500).unwrap_or(true))
+                        .count();
+    let requests = results.len();
+    let elapsed_secs = elapsed_ns as f64 / NS_PER_SEC as f64;
+    LoadReport {
+        url: url.clone(),
+        target_rps: config.rate,
+        requests: requests,
+        responses: latencies.len(),
+        errors: errors,
+        error_rate: if requests == 0 { 0.0 } else { errors as f64 / requests as f64 },
+        elapsed_ms: (elapsed_ns / NS_PER_MS) as i64,
+        throughput_rps: if elapsed_secs > 0.0 { latencies.len() as f64 / elapsed_secs } else { 0.0 },
+        max_send_lag_ms: (max_lag_ns / NS_PER_MS) as i64,
+        latency: Summary::from_samples(&latencies),
+        results: results,
+    }
+}
+
+fn sleep_ns(ns: u64) {
+    thread::sleep(Duration::new(ns / NS_PER_SEC, (ns % NS_PER_SEC) as u32));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+    use retry::{Attempt, ErrorKind};
+
+    fn slow_ok(ms: u64) -> Box<Fn(&String) -> Attempt + Send + Sync> {
+        Box::new(move |_: &String| {
+            thread::sleep(Duration::from_millis(ms));
+            Attempt { latency_ms: ms as i64, status: Some(200), ..Attempt::default() }
+        })
+    }
+
+    #[test]
+    /// Requests should be evenly spaced across the duration
+    fn schedule_spacing() {
+        let due: Vec<u64> = schedule(&LoadConfig { rate: 100.0, duration_ms: 1000, max_in_flight: 1 }).collect();
+        assert_eq!(due.len(), 100);
+        assert_eq!(due[1] - due[0], 10000000);
+    }
+
+    #[test]
+    /// Rates below one per second should still be scheduled
+    fn schedule_slow_rate() {
+        let due: Vec<u64> = schedule(&LoadConfig { rate: 0.5, duration_ms: 4000, max_in_flight: 1 }).collect();
+        assert_eq!(due, vec![0, 2000000000]);
+    }
+
+    #[test]
+    /// Rates and durations giving no requests, or too many to keep, should be rejected up front
+    fn validates_request_count() {
+        let config = |rate: f64, duration_ms: u64| LoadConfig { rate: rate, duration_ms: duration_ms, max_in_flight: 1 };
+        assert!(config(::std::f64::INFINITY, 1000).validate().is_err());
+        assert!(config(::std::f64::NAN, 1000).validate().is_err());
+        assert!(config(1.0, 999).validate().is_err());
+        assert!(config(1000.0, 1000 * 1000).validate().is_ok());
+        assert!(config(1000.0, 1000 * 1001).validate().unwrap_err().contains("more than 1000000 requests"));
+        assert!(config(1.0, u64::max_value()).validate().is_err());
+    }
+
+    #[test]
+    /// Every scheduled request should be sent and answered
+    fn all_requests_complete() {
+        let config = LoadConfig { rate: 200.0, duration_ms: 100, max_in_flight: 50 };
+        let probe = slow_ok(5);
+        let report = run_with(&"http://example.com/".to_string(), &config, move |u: &String| probe(u));
+        assert_eq!(report.requests, 20);
+        assert_eq!(report.responses, 20);
+        assert_eq!(report.errors, 0);
+        assert!(report.latency.unwrap().min >= 5);
+    }
+
+    #[test]
+    /// Failed requests and server errors should count towards the error rate
+    fn errors_counted() {
+        let config = LoadConfig { rate: 100.0, duration_ms: 100, max_in_flight: 10 };
+        let report = run_with(&"http://example.com/".to_string(), &config, |_: &String| {
+            Attempt::failure(0, "refused".to_string(), ErrorKind::Connect)
+        });
+        assert_eq!(report.error_rate, 1.0);
+        assert!(report.latency.is_none());
+
+        let report = run_with(&"http://example.com/".to_string(), &config, |_: &String| {
+            Attempt { latency_ms: 0, status: Some(503), ..Attempt::default() }
+        });
+        assert_eq!(report.errors, report.requests);
+        assert_eq!(report.responses, report.requests);
+    }
+
+    #[test]
+    /// Requests held up behind a slow one should have the wait included in their latency
+    fn coordinated_omission_corrected() {
+        let config = LoadConfig { rate: 100.0, duration_ms: 100, max_in_flight: 1 };
+        let probe = slow_ok(50);
+        let report = run_with(&"http://example.com/".to_string(), &config, move |u: &String| probe(u));
+        let last = &report.results[report.results.len() - 1];
+        // The last request was due at 90ms but couldn't start until the other nine had taken 50ms each
+        assert!(last.latency_ms >= 400, "latency was {}", last.latency_ms);
+        assert_eq!(last.attempts[0].latency_ms, 50);
+    }
+}
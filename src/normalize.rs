@@ -0,0 +1,196 @@
+//! Optional normalization of canonical urls, and merging of targets which become the same.
+//!
+//! Canonicalization (see ``canonical``) only makes changes which never alter the resource a url
+//! refers to. Normalization goes further, making changes which servers almost always, but not
+//! necessarily, treat as equivalent, so that lists gathered from several places can be merged.
+
+use target::Target;
+
+/// The normalizations which can be applied, by name
+pub const RULES: [&'static str; 2] = ["trailing-slash", "sort-query"];
+
+/// Which normalizations to apply on top of canonicalization
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormalizePolicy {
+    /// Remove a trailing ``/`` from any path other than ``/``
+    pub strip_trailing_slash: bool,
+
+    /// Sort query parameters by name, keeping the order of repeated names, and drop empty ones
+    pub sort_query: bool,
+}
+
+impl NormalizePolicy {
+    /// Applies every normalization
+    pub fn all() -> NormalizePolicy {
+        NormalizePolicy { strip_trailing_slash: true, sort_query: true }
+    }
+
+    /// Applies no normalization
+    pub fn none() -> NormalizePolicy {
+        NormalizePolicy { strip_trailing_slash: false, sort_query: false }
+    }
+
+    /// Builds a policy from a comma separated list of rule names (see ``RULES``), or ``all``
+    ///
+    /// # Failures
+    ///
+    /// Returns an Err naming the first unknown rule.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::normalize::NormalizePolicy;
+    ///
+    /// let policy = NormalizePolicy::from_names("sort-query").unwrap();
+    /// assert!(policy.sort_query && !policy.strip_trailing_slash);
+    /// assert_eq!(NormalizePolicy::from_names("all").unwrap(), NormalizePolicy::all());
+    /// assert!(NormalizePolicy::from_names("lowercase-path").is_err());
+    /// ```
+    pub fn from_names(names: &str) -> Result<NormalizePolicy, String> {
+        let mut policy = NormalizePolicy::none();
+        for name in names.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+            match name {
+                "all" => policy = NormalizePolicy::all(),
+                "trailing-slash" => policy.strip_trailing_slash = true,
+                "sort-query" => policy.sort_query = true,
+                _ => return Err(format!("unknown normalization '{}', expected all or any of {}", name, RULES.join(", "))),
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Normalizes a canonical url
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::normalize::NormalizePolicy;
+    ///
+    /// let url = NormalizePolicy::all().apply("http://www.example.com/a/?b=2&a=1&&b=1");
+    /// assert_eq!(url, "http://www.example.com/a?a=1&b=2&b=1");
+    /// ```
+    pub fn apply(&self, url: &str) -> String {
+        let path_start = url.find("://").and_then(|i| url[i + 3..].find('/').map(|j| i + 3 + j))
+                            .unwrap_or(url.len());
+        let (origin, rest) = url.split_at(path_start);
+        let (path, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+
+        let mut normalized = origin.to_string();
+        if self.strip_trailing_slash && path.len() > 1 && path.ends_with('/') {
+            normalized.push_str(path.trim_right_matches('/'));
+            if normalized.len() == origin.len() {
+                normalized.push('/');
+            }
+        } else {
+            normalized.push_str(path);
+        }
+        match query {
+            Some(q) if self.sort_query => {
+                let mut params: Vec<&str> = q.split('&').filter(|p| !p.is_empty()).collect();
+                params.sort_by(|a, b| a.split('=').next().cmp(&b.split('=').next()));
+                if !params.is_empty() {
+                    normalized.push('?');
+                    normalized.push_str(&params.join("&"));
+                }
+            }
+            Some(q) => {
+                normalized.push('?');
+                normalized.push_str(q);
+            }
+            None => (),
+        }
+        normalized
+    }
+}
+
+/// Targets which were merged into one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merged {
+    /// The url of the target kept
+    pub url: String,
+
+    /// The positions in the original list of every target merged, starting with the one kept
+    pub indices: Vec<usize>,
+}
+
+/// Removes targets which are the same as an earlier target, returning the targets left and the
+/// groups of targets that were merged
+///
+/// Targets are only merged if every setting matches, so the same url requested with different
+/// methods or expectations is kept.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::normalize::dedupe;
+/// use httplatency::target::Target;
+///
+/// let targets = vec![Target::get("http://a.example.com/".to_string()),
+///                    Target::get("http://b.example.com/".to_string()),
+///                    Target::get("http://a.example.com/".to_string())];
+/// let (kept, merged) = dedupe(targets);
+/// assert_eq!(kept.len(), 2);
+/// assert_eq!(merged[0].indices, vec![0, 2]);
+/// ```
+pub fn dedupe(targets: Vec<Target>) -> (Vec<Target>, Vec<Merged>) {
+    let mut kept: Vec<Target> = Vec::new();
+    let mut indices: Vec<Vec<usize>> = Vec::new();
+    for (i, target) in targets.into_iter().enumerate() {
+        match kept.iter().position(|k| *k == target) {
+            Some(first) => indices[first].push(i),
+            None => {
+                kept.push(target);
+                indices.push(vec![i]);
+            }
+        }
+    }
+    let merged = kept.iter()
+                     .zip(indices.into_iter())
+                     .filter(|&(_, ref i)| i.len() > 1)
+                     .map(|(k, i)| Merged { url: k.url.clone(), indices: i })
+                     .collect();
+    (kept, merged)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use target::Target;
+
+    #[test]
+    /// Each rule should only make its own change
+    fn rules_are_independent() {
+        let url = "http://www.example.com/a/?b=1&a=2";
+        let slash = NormalizePolicy { strip_trailing_slash: true, sort_query: false };
+        let query = NormalizePolicy { strip_trailing_slash: false, sort_query: true };
+        assert_eq!(slash.apply(url), "http://www.example.com/a?b=1&a=2");
+        assert_eq!(query.apply(url), "http://www.example.com/a/?a=2&b=1");
+        assert_eq!(NormalizePolicy::none().apply(url), url);
+    }
+
+    #[test]
+    /// The root path and empty queries should be handled
+    fn edge_cases() {
+        let all = NormalizePolicy::all();
+        assert_eq!(all.apply("http://www.example.com/"), "http://www.example.com/");
+        assert_eq!(all.apply("http://www.example.com//"), "http://www.example.com/");
+        assert_eq!(all.apply("http://www.example.com/?"), "http://www.example.com/");
+        assert_eq!(all.apply("http://www.example.com/?&&"), "http://www.example.com/");
+        assert_eq!(all.apply("http://[::1]:8080/a/"), "http://[::1]:8080/a");
+        assert_eq!(all.apply("http://www.example.com/?a=2&a=1&a"), "http://www.example.com/?a=2&a=1&a");
+    }
+
+    #[test]
+    /// Targets with different settings shouldn't be merged
+    fn dedupe_respects_settings() {
+        let mut head = Target::get("http://a.example.com/".to_string());
+        head.method = "HEAD".to_string();
+        let targets = vec![Target::get("http://a.example.com/".to_string()), head];
+        let (kept, merged) = dedupe(targets);
+        assert_eq!(kept.len(), 2);
+        assert!(merged.is_empty());
+    }
+}
@@ -0,0 +1,685 @@
+//! Generating probe targets for the operations of an OpenAPI 3 document.
+//!
+//! Every operation of the document (or those chosen by tag or ``operationId``) becomes a target
+//! with the operation's method. Path, query, header and cookie parameters are filled in from a
+//! parameters file if it gives them, and otherwise from the examples and defaults in the document.
+//! Operations with a request body send its example, with the matching ``Content-Type``, and ask for
+//! the content type of their successful response with ``Accept``.
+//!
+//! A parameters file is TOML, with values for every operation at the top and values for a single
+//! operation in a table named by its ``operationId``:
+//!
+//! ```text
+//! petId = 42
+//! limit = 10
+//!
+//! [deletePet]
+//! petId = 9001
+//! ```
+//!
+//! Documents may be JSON or YAML. References (``$ref``) to other parts of the same document are
+//! followed, but references to other files aren't supported.
+
+use std::collections::BTreeMap;
+use std::str;
+
+use rustc_serialize::json::Json;
+use toml::{Parser, Value};
+use yaml_rust::{Yaml, YamlLoader};
+
+use canonical;
+use target::Target;
+use urllist::Entry;
+
+/// The methods an OpenAPI path item may have operations for, in the order they are probed
+const METHODS: [&'static str; 8] = ["get", "put", "post", "delete", "options", "head", "patch", "trace"];
+
+/// The most references followed to resolve a single value, so that cycles are caught
+const MAX_REFERENCES: usize = 32;
+
+/// Which operations of a document to probe, and how to fill in their requests
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selection {
+    /// Probe operations with any of these tags
+    pub tags: Vec<String>,
+
+    /// Probe the operations with these ``operationId``s
+    pub operations: Vec<String>,
+
+    /// The base url to use instead of the document's ``servers``
+    pub server: Option<String>,
+
+    /// Values to give parameters, taking priority over the document's examples
+    pub parameters: Parameters,
+}
+
+impl Selection {
+    /// True if ``operation`` is chosen. Every operation is chosen if no tags or operations are given.
+    fn chooses(&self, operation: &Json) -> bool {
+        if self.tags.is_empty() && self.operations.is_empty() {
+            return true;
+        }
+        let id = operation.find("operationId").and_then(|i| i.as_string());
+        id.map(|id| self.operations.iter().any(|o| o == id)).unwrap_or(false) ||
+        tags(operation).iter().any(|t| self.tags.iter().any(|s| s == t))
+    }
+}
+
+/// Values for parameters read from a parameters file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Parameters {
+    global: BTreeMap<String, Json>,
+    operations: BTreeMap<String, BTreeMap<String, Json>>,
+}
+
+impl Parameters {
+    /// Parses a parameters file
+    ///
+    /// # Failures
+    ///
+    /// Returns an Err giving the line and column of each error if ``source`` isn't valid TOML.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let parameters = httplatency::openapi::Parameters::parse("limit = 10\n[getPet]\npetId = 7\n").unwrap();
+    /// assert!(parameters != Default::default());
+    /// ```
+    pub fn parse(source: &str) -> Result<Parameters, String> {
+        let mut parser = Parser::new(source);
+        let root = match parser.parse() {
+            Some(table) => table,
+            None => {
+                let errors: Vec<String> = parser.errors.iter().map(|e| {
+                    let (line, col) = parser.to_linecol(e.lo);
+                    format!("line {}, column {}: {}", line + 1, col + 1, e.desc)
+                }).collect();
+                return Err(errors.join("\n"));
+            }
+        };
+        let mut parameters = Parameters::default();
+        for (name, value) in root.into_iter() {
+            match value {
+                Value::Table(table) => {
+                    let values = table.into_iter().map(|(n, v)| (n, from_toml(v))).collect();
+                    parameters.operations.insert(name, values);
+                }
+                value => {
+                    parameters.global.insert(name, from_toml(value));
+                }
+            }
+        }
+        Ok(parameters)
+    }
+
+    /// The value given for the parameter ``name`` of the operation ``id``, if any
+    fn get(&self, id: Option<&str>, name: &str) -> Option<&Json> {
+        id.and_then(|id| self.operations.get(id)).and_then(|values| values.get(name))
+            .or_else(|| self.global.get(name))
+    }
+}
+
+/// True if ``path`` names a JSON or YAML file and ``contents`` has a top-level ``openapi`` field,
+/// so that it's read as an OpenAPI document
+///
+/// Other JSON and YAML files, or ones which can't be parsed, are read as other kinds of input.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::openapi::is_openapi_file;
+///
+/// assert!(is_openapi_file("api.yaml", b"openapi: 3.0.3\npaths: {}\n"));
+/// assert!(is_openapi_file("api.JSON", br#"{"openapi": "3.1.0", "paths": {}}"#));
+/// assert!(!is_openapi_file("urls.json", br#"["http://www.example.com/"]"#));
+/// assert!(!is_openapi_file("api.txt", b"openapi: 3.0.3\n"));
+/// ```
+pub fn is_openapi_file(path: &str, contents: &[u8]) -> bool {
+    let path = path.to_lowercase();
+    if !(path.ends_with(".json") || path.ends_with(".yaml") || path.ends_with(".yml")) {
+        return false;
+    }
+    str::from_utf8(contents).ok()
+        .and_then(|text| load(text).ok())
+        .map(|document| document.find("openapi").is_some())
+        .unwrap_or(false)
+}
+
+/// Parses an OpenAPI 3 document written as JSON or YAML
+///
+/// # Failures
+///
+/// Returns an Err if ``contents`` isn't JSON or YAML, or isn't an OpenAPI 3 document.
+///
+/// # Examples
+///
+/// ```
+/// let document = httplatency::openapi::parse("openapi: 3.0.3\npaths:\n  /health:\n    get:\n      responses:\n        200:\n          description: ok\n").unwrap();
+/// assert!(document.find_path(&["paths", "/health", "get", "responses", "200"]).is_some());
+/// assert!(httplatency::openapi::parse(r#"{"swagger": "2.0"}"#).is_err());
+/// ```
+pub fn parse(contents: &str) -> Result<Json, String> {
+    let document = try!(load(contents));
+    let version = match document.find("openapi") {
+        Some(&Json::String(ref v)) => v.clone(),
+        Some(&Json::F64(v)) => v.to_string(),
+        _ => String::new(),
+    };
+    if !version.starts_with('3') {
+        return Err("No 'openapi: 3.x' field found, expected an OpenAPI 3 document".to_string());
+    }
+    Ok(document)
+}
+
+/// Reads a JSON or YAML document, whatever it describes
+///
+/// # Failures
+///
+/// Returns an Err if ``contents`` isn't JSON or YAML, or is an empty YAML stream.
+fn load(contents: &str) -> Result<Json, String> {
+    if contents.trim_left().starts_with('{') {
+        Json::from_str(contents).map_err(|e| format!("Invalid JSON: {}", e))
+    } else {
+        let mut documents = try!(YamlLoader::load_from_str(contents).map_err(|e| format!("Invalid YAML: {:?}", e)));
+        if documents.is_empty() {
+            return Err("Empty document".to_string());
+        }
+        from_yaml(documents.remove(0))
+    }
+}
+
+/// Generates a target for each chosen operation of an OpenAPI document, keeping operations which
+/// can't be probed
+///
+/// Each entry is numbered by its position among the chosen operations, starting from 1, and its
+/// text is the operation's ``operationId`` (if it has one), method and path. Each target's group is
+/// the operation's first tag, and its template is the operation's path appended to the server url.
+///
+/// # Failures
+///
+/// Returns an Err if ``contents`` isn't an OpenAPI 3 document, or a tag or operation chosen by
+/// ``selection`` isn't in it.
+///
+/// # Examples
+///
+/// ```
+/// let entries = httplatency::openapi::entries(r#"{
+///     "openapi": "3.0.0",
+///     "servers": [{"url": "https://api.example.com/v1"}],
+///     "paths": {"/pets/{petId}": {"get": {
+///         "operationId": "getPet",
+///         "tags": ["pets"],
+///         "parameters": [{"name": "petId", "in": "path", "required": true, "example": 42}]
+///     }}}
+/// }"#, &Default::default()).unwrap();
+/// assert_eq!(entries[0].text, "getPet (GET /pets/{petId})");
+/// let target = entries[0].target.as_ref().unwrap();
+/// assert_eq!(target.url, "https://api.example.com/v1/pets/42");
+/// assert_eq!(target.group, Some("pets".to_string()));
+/// ```
+pub fn entries(contents: &str, selection: &Selection) -> Result<Vec<Entry>, String> {
+    let document = try!(parse(contents));
+    let paths = match document.find("paths") {
+        Some(&Json::Object(ref paths)) => paths,
+        _ => return Err("No paths object found".to_string()),
+    };
+    let mut entries = Vec::new();
+    let mut chosen_tags = Vec::new();
+    let mut chosen_ids = Vec::new();
+    for (path, item) in paths.iter() {
+        let item = match resolve(&document, item) {
+            Ok(item) => item,
+            Err(err) => {
+                entries.push(Entry { line: entries.len() + 1, text: path.clone(), target: Err(err) });
+                continue;
+            }
+        };
+        for method in METHODS.iter() {
+            let operation = match item.find(method) {
+                Some(operation) if selection.chooses(operation) => operation,
+                _ => continue,
+            };
+            let id = operation.find("operationId").and_then(|i| i.as_string());
+            chosen_tags.extend(tags(operation));
+            chosen_ids.extend(id);
+            let description = format!("{} {}", method.to_uppercase(), path);
+            entries.push(Entry {
+                line: entries.len() + 1,
+                text: match id {
+                    Some(id) => format!("{} ({})", id, description),
+                    None => description,
+                },
+                target: target(&document, path, item, method, operation, selection),
+            });
+        }
+    }
+    for tag in selection.tags.iter() {
+        if !chosen_tags.contains(&&tag[..]) {
+            return Err(format!("No operation has the tag '{}'", tag));
+        }
+    }
+    for id in selection.operations.iter() {
+        if !chosen_ids.contains(&&id[..]) {
+            return Err(format!("No operation has the operationId '{}'", id));
+        }
+    }
+    Ok(entries)
+}
+
+/// Builds the target for an operation
+fn target(document: &Json, path: &str, item: &Json, method: &str, operation: &Json, selection: &Selection)
+          -> Result<Target, String> {
+    let id = operation.find("operationId").and_then(|i| i.as_string());
+    let server = match selection.server {
+        Some(ref server) => server.clone(),
+        None => {
+            let servers = operation.find("servers").or(item.find("servers")).or(document.find("servers"));
+            try!(server_url(servers))
+        }
+    };
+    let base = server.trim_right_matches('/');
+
+    // Operation parameters replace path item parameters with the same name and location
+    let mut parameters: Vec<&Json> = Vec::new();
+    for list in [operation.find("parameters"), item.find("parameters")].iter() {
+        if let Some(&Json::Array(ref list)) = *list {
+            for parameter in list.iter() {
+                let parameter = try!(resolve(document, parameter));
+                let key = (parameter.find("name"), parameter.find("in"));
+                if !parameters.iter().any(|p| (p.find("name"), p.find("in")) == key) {
+                    parameters.push(parameter);
+                }
+            }
+        }
+    }
+
+    let mut url_path = path.to_string();
+    let mut query = Vec::new();
+    let mut headers = Vec::new();
+    let mut cookies = Vec::new();
+    for parameter in parameters {
+        let name = try!(parameter.find("name").and_then(|n| n.as_string())
+                                 .ok_or("parameter without a name".to_string()));
+        let location = parameter.find("in").and_then(|i| i.as_string()).unwrap_or("");
+        let value = match selection.parameters.get(id, name) {
+            Some(value) => Some(value.clone()),
+            None => try!(example(document, parameter)),
+        };
+        let value = match value {
+            Some(value) => value,
+            None if location == "path" || parameter.find("required") == Some(&Json::Boolean(true)) => {
+                return Err(format!("no value for the required {} parameter '{}'", location, name));
+            }
+            None => continue,
+        };
+        let values = try!(strings(&value).map_err(|e| format!("{} parameter '{}': {}", location, name, e)));
+        match location {
+            "path" => {
+                let encoded: Vec<String> = values.iter().map(|v| canonical::percent_encode(v)).collect();
+                url_path = url_path.replace(&format!("{{{}}}", name), &encoded.join(","));
+            }
+            "query" if parameter.find("explode") == Some(&Json::Boolean(false)) => {
+                let encoded: Vec<String> = values.iter().map(|v| canonical::percent_encode(v)).collect();
+                query.push(format!("{}={}", canonical::percent_encode(name), encoded.join(",")));
+            }
+            "query" => {
+                for v in values.iter() {
+                    query.push(format!("{}={}", canonical::percent_encode(name), canonical::percent_encode(v)));
+                }
+            }
+            "header" => headers.push((name.to_string(), values.join(","))),
+            "cookie" => cookies.push(format!("{}={}", name, values.join(","))),
+            _ => return Err(format!("parameter '{}' has an unknown location '{}'", name, location)),
+        }
+    }
+    if let Some(start) = url_path.find('{') {
+        return Err(format!("no parameter given for '{}' in the path", &url_path[start..]));
+    }
+    if !cookies.is_empty() {
+        headers.push(("Cookie".to_string(), cookies.join("; ")));
+    }
+    if let Some(content_type) = try!(response_type(document, operation)) {
+        headers.push(("Accept".to_string(), content_type));
+    }
+    let body = try!(request_body(document, operation));
+    if let Some((ref content_type, _)) = body {
+        headers.push(("Content-Type".to_string(), content_type.clone()));
+    }
+
+    let url = if query.is_empty() {
+        format!("{}{}", base, url_path)
+    } else {
+        format!("{}{}?{}", base, url_path, query.join("&"))
+    };
+    let canonical = try!(canonical::parse(&url).map_err(|e| format!("'{}' is not a valid http(s) url: {}", url, e)));
+    let mut target = Target::get(canonical.url);
+    target.method = method.to_uppercase();
+    target.headers = headers;
+    target.body = body.map(|b| b.1);
+    target.group = tags(operation).first().map(|t| t.to_string());
+    target.template = Some(format!("{}{}", base, path));
+    Ok(target)
+}
+
+/// The url of the first server listed, with its variables given their defaults
+fn server_url(servers: Option<&Json>) -> Result<String, String> {
+    let server = match servers {
+        Some(&Json::Array(ref servers)) if !servers.is_empty() => &servers[0],
+        _ => return Err("no servers are listed, give a base url with --openapi-server".to_string()),
+    };
+    let mut url = try!(server.find("url").and_then(|u| u.as_string()).ok_or("server without a url".to_string()))
+        .to_string();
+    if let Some(&Json::Object(ref variables)) = server.find("variables") {
+        for (name, variable) in variables.iter() {
+            if let Some(default) = variable.find("default").and_then(|d| d.as_string()) {
+                url = url.replace(&format!("{{{}}}", name), default);
+            }
+        }
+    }
+    if !url.contains("://") {
+        return Err(format!("the server url '{}' is relative, give a base url with --openapi-server", url));
+    }
+    Ok(url)
+}
+
+/// The example value of a parameter or media type, from (in order) its ``example``, the first of
+/// its ``examples``, or its schema's ``example``, ``default`` or first ``enum`` value
+fn example(document: &Json, object: &Json) -> Result<Option<Json>, String> {
+    if let Some(value) = object.find("example") {
+        return Ok(Some(value.clone()));
+    }
+    if let Some(&Json::Object(ref examples)) = object.find("examples") {
+        for (_, example) in examples.iter() {
+            if let Some(value) = try!(resolve(document, example)).find("value") {
+                return Ok(Some(value.clone()));
+            }
+        }
+    }
+    if let Some(schema) = object.find("schema") {
+        let schema = try!(resolve(document, schema));
+        if let Some(value) = schema.find("example").or(schema.find("default")) {
+            return Ok(Some(value.clone()));
+        }
+        if let Some(&Json::Array(ref values)) = schema.find("enum") {
+            return Ok(values.first().cloned());
+        }
+    }
+    Ok(None)
+}
+
+/// The content type and example of an operation's request body, if it has one
+fn request_body(document: &Json, operation: &Json) -> Result<Option<(String, String)>, String> {
+    let body = match operation.find("requestBody") {
+        Some(body) => try!(resolve(document, body)),
+        None => return Ok(None),
+    };
+    let (content_type, media) = match body.find("content").and_then(preferred_type) {
+        Some(content) => content,
+        None => return Ok(None),
+    };
+    match try!(example(document, try!(resolve(document, media)))) {
+        Some(Json::String(ref s)) if !content_type.contains("json") => Ok(Some((content_type, s.clone()))),
+        Some(value) => Ok(Some((content_type, value.to_string()))),
+        None if body.find("required") == Some(&Json::Boolean(true)) => {
+            Err(format!("no example for the required {} request body", content_type))
+        }
+        None => Ok(None),
+    }
+}
+
+/// The content type of an operation's first successful response, if it has one
+fn response_type(document: &Json, operation: &Json) -> Result<Option<String>, String> {
+    let responses = match operation.find("responses") {
+        Some(&Json::Object(ref responses)) => responses,
+        _ => return Ok(None),
+    };
+    for (status, response) in responses.iter() {
+        if status.starts_with('2') {
+            let response = try!(resolve(document, response));
+            return Ok(response.find("content").and_then(preferred_type).map(|c| c.0));
+        }
+    }
+    Ok(None)
+}
+
+/// The content type of a ``content`` object to use, preferring JSON, along with its media type
+fn preferred_type(content: &Json) -> Option<(String, &Json)> {
+    let content = match *content {
+        Json::Object(ref content) => content,
+        _ => return None,
+    };
+    content.iter().find(|c| c.0.contains("json")).or(content.iter().next())
+        .map(|(content_type, media)| (content_type.clone(), media))
+}
+
+/// Follows ``value`` if it is a reference to another part of ``document``
+fn resolve<'a>(document: &'a Json, value: &'a Json) -> Result<&'a Json, String> {
+    let mut value = value;
+    for _ in 0..MAX_REFERENCES {
+        let reference = match value.find("$ref").and_then(|r| r.as_string()) {
+            Some(reference) => reference,
+            None => return Ok(value),
+        };
+        if !reference.starts_with("#/") {
+            return Err(format!("the reference '{}' is to another document, which isn't supported", reference));
+        }
+        value = document;
+        for segment in reference[2..].split('/') {
+            let segment = segment.replace("~1", "/").replace("~0", "~");
+            value = try!(value.find(&segment).ok_or(format!("the reference '{}' isn't in the document", reference)));
+        }
+    }
+    Err(format!("more than {} references followed, there may be a cycle", MAX_REFERENCES))
+}
+
+/// The tags of an operation
+fn tags(operation: &Json) -> Vec<&str> {
+    match operation.find("tags") {
+        Some(&Json::Array(ref tags)) => tags.iter().filter_map(|t| t.as_string()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The values of a parameter as strings, with an array giving a value for each item
+fn strings(value: &Json) -> Result<Vec<String>, String> {
+    match *value {
+        Json::Array(ref items) => items.iter().map(|i| string(i)).collect(),
+        ref value => string(value).map(|s| vec![s]),
+    }
+}
+
+/// A single value of a parameter as a string
+fn string(value: &Json) -> Result<String, String> {
+    match *value {
+        Json::String(ref s) => Ok(s.clone()),
+        Json::I64(n) => Ok(n.to_string()),
+        Json::U64(n) => Ok(n.to_string()),
+        Json::F64(n) => Ok(n.to_string()),
+        Json::Boolean(b) => Ok(b.to_string()),
+        Json::Null => Ok(String::new()),
+        Json::Array(_) | Json::Object(_) => Err(format!("the value {} isn't supported", value)),
+    }
+}
+
+/// Converts a YAML value to the equivalent JSON. Keys which aren't strings, such as response
+/// status codes, are converted to strings.
+fn from_yaml(yaml: Yaml) -> Result<Json, String> {
+    Ok(match yaml {
+        Yaml::Real(s) => s.parse().map(Json::F64).unwrap_or(Json::String(s)),
+        Yaml::Integer(n) => Json::I64(n),
+        Yaml::String(s) => Json::String(s),
+        Yaml::Boolean(b) => Json::Boolean(b),
+        Yaml::Array(items) => Json::Array(try!(items.into_iter().map(from_yaml).collect())),
+        Yaml::Hash(hash) => {
+            let mut object = BTreeMap::new();
+            for (key, value) in hash.into_iter() {
+                let key = match key {
+                    Yaml::String(s) | Yaml::Real(s) => s,
+                    Yaml::Integer(n) => n.to_string(),
+                    Yaml::Boolean(b) => b.to_string(),
+                    key => return Err(format!("unsupported key {:?}", key)),
+                };
+                object.insert(key, try!(from_yaml(value)));
+            }
+            Json::Object(object)
+        }
+        Yaml::Null => Json::Null,
+        Yaml::Alias(_) | Yaml::BadValue => return Err("YAML aliases aren't supported".to_string()),
+    })
+}
+
+/// Converts a value from a parameters file to the equivalent JSON
+fn from_toml(value: Value) -> Json {
+    match value {
+        Value::String(s) | Value::Datetime(s) => Json::String(s),
+        Value::Integer(n) => Json::I64(n),
+        Value::Float(n) => Json::F64(n),
+        Value::Boolean(b) => Json::Boolean(b),
+        Value::Array(items) => Json::Array(items.into_iter().map(from_toml).collect()),
+        Value::Table(table) => Json::Object(table.into_iter().map(|(k, v)| (k, from_toml(v))).collect()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A document describing a small pet store
+    const PETSTORE: &'static str = r#"
+openapi: 3.0.3
+info:
+  title: Pets
+  version: 1.0.0
+servers:
+  - url: https://{region}.example.com/v1
+    variables:
+      region:
+        default: eu
+paths:
+  /pets:
+    get:
+      operationId: listPets
+      tags: [pets]
+      parameters:
+        - name: limit
+          in: query
+          schema: {type: integer, default: 20}
+        - name: status
+          in: query
+          schema: {type: array, items: {type: string}}
+          example: [available, sold]
+        - name: cursor
+          in: query
+      responses:
+        200:
+          description: A page of pets
+          content:
+            application/json: {}
+    post:
+      operationId: createPet
+      tags: [pets, admin]
+      requestBody:
+        required: true
+        content:
+          application/json:
+            schema:
+              $ref: '#/components/schemas/Pet'
+      responses:
+        201:
+          description: Created
+  /pets/{petId}:
+    parameters:
+      - $ref: '#/components/parameters/PetId'
+    get:
+      operationId: getPet
+      tags: [pets]
+      parameters:
+        - name: X-Request-Id
+          in: header
+          examples:
+            fixed: {value: probe}
+      responses:
+        default:
+          description: The pet
+    delete:
+      operationId: deletePet
+      tags: [admin]
+components:
+  parameters:
+    PetId:
+      name: petId
+      in: path
+      required: true
+      schema: {type: string, example: 'rex the dog'}
+  schemas:
+    Pet:
+      type: object
+      example: {name: rex}
+"#;
+
+    /// The targets generated for ``selection``, by the text of their entry
+    fn targets(selection: &Selection) -> Vec<(String, Result<Target, String>)> {
+        entries(PETSTORE, selection).unwrap().into_iter().map(|e| (e.text, e.target)).collect()
+    }
+
+    #[test]
+    /// Every operation should be probed with values taken from the document
+    fn generates_targets() {
+        let targets = targets(&Default::default());
+        let texts: Vec<&str> = targets.iter().map(|t| &t.0[..]).collect();
+        assert_eq!(texts, vec!["listPets (GET /pets)", "createPet (POST /pets)",
+                               "getPet (GET /pets/{petId})", "deletePet (DELETE /pets/{petId})"]);
+
+        let list = targets[0].1.as_ref().unwrap();
+        assert_eq!(list.url, "https://eu.example.com/v1/pets?limit=20&status=available&status=sold");
+        assert_eq!(list.headers, vec![("Accept".to_string(), "application/json".to_string())]);
+        assert_eq!(list.template, Some("https://eu.example.com/v1/pets".to_string()));
+
+        let create = targets[1].1.as_ref().unwrap();
+        assert_eq!(create.method, "POST");
+        assert_eq!(create.body, Some(r#"{"name":"rex"}"#.to_string()));
+        assert_eq!(create.headers, vec![("Content-Type".to_string(), "application/json".to_string())]);
+
+        let get = targets[2].1.as_ref().unwrap();
+        assert_eq!(get.url, "https://eu.example.com/v1/pets/rex%20the%20dog");
+        assert_eq!(get.headers, vec![("X-Request-Id".to_string(), "probe".to_string())]);
+        assert_eq!(get.group, Some("pets".to_string()));
+        assert_eq!(targets[3].1.as_ref().unwrap().group, Some("admin".to_string()));
+    }
+
+    #[test]
+    /// Operations should be chosen by tag or operationId, and values taken from the parameters file
+    fn selects_operations() {
+        let selection = Selection {
+            tags: vec!["admin".to_string()],
+            operations: vec!["getPet".to_string()],
+            server: Some("http://localhost:8080/".to_string()),
+            parameters: Parameters::parse("petId = 7\nlimit = 5\n[deletePet]\npetId = \"old\"\n").unwrap(),
+        };
+        let targets = targets(&selection);
+        let urls: Vec<&str> = targets.iter().map(|t| &t.1.as_ref().unwrap().url[..]).collect();
+        assert_eq!(urls, vec!["http://localhost:8080/pets", "http://localhost:8080/pets/7",
+                              "http://localhost:8080/pets/old"]);
+
+        let unknown = Selection { operations: vec!["updatePet".to_string()], ..Default::default() };
+        assert_eq!(entries(PETSTORE, &unknown).unwrap_err(), "No operation has the operationId 'updatePet'");
+    }
+
+    #[test]
+    /// Operations which can't be probed should say why, without stopping the others
+    fn reports_problems() {
+        let entries = entries(r#"{"openapi": "3.1.0", "servers": [{"url": "/api"}], "paths": {
+            "/a/{id}": {"get": {"parameters": [{"name": "id", "in": "path", "required": true}]}},
+            "/b": {"get": {"parameters": [{"$ref": "other.yaml#/Limit"}]}},
+            "/c": {"post": {"requestBody": {"required": true, "content": {"text/plain": {}}}}}
+        }}"#, &Selection { server: Some("https://api.example.com".to_string()), ..Default::default() }).unwrap();
+        let errors: Vec<String> = entries.into_iter().map(|e| e.target.unwrap_err()).collect();
+        assert_eq!(errors, vec!["no value for the required path parameter 'id'",
+                                "the reference 'other.yaml#/Limit' is to another document, which isn't supported",
+                                "no example for the required text/plain request body"]);
+
+        let relative = super::entries(r#"{"openapi": "3.0.0", "servers": [{"url": "/api"}], "paths": {"/": {"get": {}}}}"#,
+                                      &Default::default()).unwrap();
+        assert!(relative[0].target.as_ref().unwrap_err().contains("relative"));
+    }
+}
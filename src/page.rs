@@ -0,0 +1,680 @@
+//! Loading a whole page the way a browser would, timing each resource it needs.
+//!
+//! The document is fetched first, following redirects. The stylesheets, scripts, images and fonts
+//! it references are then fetched in parallel, with at most ``per_host`` requests to a host at
+//! once as browsers allow, and the fonts, images and further stylesheets referenced by each
+//! stylesheet are fetched once it arrives. The result is a waterfall: when each resource was
+//! requested, how long each phase of the request took, and when the last resource arrived.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use time;
+
+use crawl::{resolve, tags};
+use envelope::format_timestamp;
+use http_host;
+
+/// How many requests are made to a host at once if not otherwise configured, as browsers do
+pub const DEFAULT_PER_HOST: usize = 6;
+
+/// How many redirects of the document are followed before giving up
+pub const MAX_REDIRECTS: usize = 5;
+
+/// What a resource is used for by the page
+#[derive(RustcEncodable, Debug, Clone, Copy, PartialEq)]
+pub enum ResourceKind {
+    Document,
+    Stylesheet,
+    Script,
+    Image,
+    Font,
+}
+
+/// How long each phase of a request took in milliseconds, with -1 for phases which weren't taken
+/// or couldn't be measured
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, Copy, PartialEq)]
+pub struct Phases {
+    /// Waiting for a free connection to the host
+    pub blocked_ms: i64,
+
+    /// Looking up the host's addresses
+    pub dns_ms: i64,
+
+    /// Opening the TCP connection
+    pub connect_ms: i64,
+
+    /// The TLS handshake
+    pub ssl_ms: i64,
+
+    /// Waiting for the response to start, after the connection was made
+    pub wait_ms: i64,
+
+    /// Reading the response body
+    pub receive_ms: i64,
+}
+
+impl Default for Phases {
+    fn default() -> Phases {
+        Phases { blocked_ms: -1, dns_ms: -1, connect_ms: -1, ssl_ms: -1, wait_ms: -1, receive_ms: -1 }
+    }
+}
+
+/// The response to a single request for a resource, or why there wasn't one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fetched {
+    /// The status of the response, if one was received
+    pub status: Option<u16>,
+
+    /// The response's ``Content-Type``
+    pub content_type: Option<String>,
+
+    /// The response's ``Location``, for redirects
+    pub location: Option<String>,
+
+    /// The response body
+    pub body: Vec<u8>,
+
+    /// How long each phase of the request took
+    pub phases: Phases,
+
+    /// Why no response was received
+    pub error: Option<String>,
+}
+
+/// The timing of one resource of a page
+#[derive(RustcEncodable, Debug, Clone, PartialEq)]
+pub struct Resource {
+    /// The url of the resource
+    pub url: String,
+
+    /// What the resource is used for
+    pub kind: ResourceKind,
+
+    /// The url of the document or stylesheet which referenced the resource, or which redirected to it
+    pub initiator: Option<String>,
+
+    /// When the request was sent, in RFC 3339 format
+    pub started_at: String,
+
+    /// When the resource was found to be needed, in milliseconds since the page load started
+    pub queued_ms: i64,
+
+    /// When the request was sent, in milliseconds since the page load started
+    pub start_ms: i64,
+
+    /// How long the request took, from being sent to the whole response being read
+    pub total_ms: i64,
+
+    /// How long each phase of the request took
+    pub phases: Phases,
+
+    /// The status of the response, if one was received
+    pub status: Option<u16>,
+
+    /// The response's ``Content-Type``
+    pub content_type: Option<String>,
+
+    /// The size of the response body in bytes
+    pub size: u64,
+
+    /// Why no response was received
+    pub error: Option<String>,
+}
+
+impl Resource {
+    /// When the whole response had been read, in milliseconds since the page load started
+    pub fn end_ms(&self) -> i64 {
+        self.start_ms + self.total_ms
+    }
+
+    /// True if the resource couldn't be loaded: there was no response or it had an error status
+    pub fn failed(&self) -> bool {
+        self.status.map(|s| s >= 400).unwrap_or(true)
+    }
+}
+
+/// The waterfall of loading a page and everything it references
+#[derive(RustcEncodable, Debug, Clone, PartialEq)]
+pub struct PageLoad {
+    /// The url of the page loaded
+    pub url: String,
+
+    /// When the whole document had been read, in milliseconds since the page load started
+    pub document_ms: i64,
+
+    /// When the last resource had been read, in milliseconds since the page load started
+    pub page_complete_ms: i64,
+
+    /// How many requests were made, including redirects of the document
+    pub requests: usize,
+
+    /// How many requests got no response or an error status
+    pub failed: usize,
+
+    /// The total size of the response bodies in bytes
+    pub bytes: u64,
+
+    /// Each request made, in the order they were sent
+    pub resources: Vec<Resource>,
+}
+
+/// A resource waiting for a free connection to its host
+struct Queued {
+    url: String,
+    kind: ResourceKind,
+    initiator: Option<String>,
+    queued_ns: u64,
+}
+
+/// A request's resource, when it was sent (as a timestamp and in nanoseconds) and completed, and
+/// its response
+type Reply = (Queued, String, u64, u64, Fetched);
+
+/// Sends the reply to a request when it's dropped, even while unwinding from a panicking fetch
+///
+/// The page load waits for a reply to every request it sends, so a fetch which panics is recorded
+/// as a failed request rather than leaving it waiting forever.
+struct ReplyGuard {
+    sender: Sender<Reply>,
+    queued: Option<Queued>,
+    started_at: String,
+    started: u64,
+    fetched: Option<Fetched>,
+}
+
+impl Drop for ReplyGuard {
+    fn drop(&mut self) {
+        if let Some(queued) = self.queued.take() {
+            let fetched = self.fetched.take().unwrap_or_else(|| Fetched {
+                status: None,
+                content_type: None,
+                location: None,
+                body: Vec::new(),
+                phases: Phases::default(),
+                error: Some("the request panicked".to_string()),
+            });
+            let reply = (queued, self.started_at.clone(), self.started, time::precise_time_ns(), fetched);
+            let _ = self.sender.send(reply);
+        }
+    }
+}
+
+/// Loads the page at ``url`` and everything it references, using ``fetch`` to make each request
+///
+/// ``fetch`` is called from several threads at once, but with at most ``per_host`` requests to
+/// the same host at a time. It shouldn't follow redirects; redirects of the document are followed
+/// here, and those of other resources are recorded as they are.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::page::{load, Fetched, Phases, ResourceKind};
+///
+/// let page = load("http://www.example.com/", 6, |url| Fetched {
+///     status: Some(if url.ends_with(".png") { 404 } else { 200 }),
+///     content_type: Some("text/html".to_string()),
+///     location: None,
+///     body: b"<img src=logo.png>".to_vec(),
+///     phases: Phases::default(),
+///     error: None,
+/// });
+/// assert_eq!(page.requests, 2);
+/// assert_eq!(page.failed, 1);
+/// assert_eq!(page.resources[1].url, "http://www.example.com/logo.png");
+/// assert_eq!(page.resources[1].kind, ResourceKind::Image);
+/// ```
+pub fn load<F>(url: &str, per_host: usize, fetch: F) -> PageLoad
+    where F: Fn(&str) -> Fetched + Send + Sync + 'static
+{
+    let start_ns = time::precise_time_ns();
+    let since_start = |ns: u64| (ns.saturating_sub(start_ns) / 1_000_000) as i64;
+    let mut resources = Vec::new();
+
+    // The document comes first, as nothing else is known until it arrives
+    let mut document_url = url.to_string();
+    let mut initiator = None;
+    let mut document = None;
+    for _ in 0..MAX_REDIRECTS + 1 {
+        let started_at = format_timestamp(&time::now_utc());
+        let started = time::precise_time_ns();
+        let fetched = fetch(&document_url);
+        let ended = time::precise_time_ns();
+        let resource = record(&document_url, ResourceKind::Document, initiator.take(), started_at,
+                              since_start(started), since_start(started), since_start(ended), &fetched);
+        resources.push(resource);
+        let redirect = match (fetched.status, &fetched.location) {
+            (Some(status), &Some(ref location)) if status >= 300 && status < 400 => resolve(&document_url, location),
+            _ => None,
+        };
+        match redirect {
+            Some(next) => {
+                initiator = Some(document_url);
+                document_url = next;
+            }
+            None => {
+                document = Some(fetched);
+                break;
+            }
+        }
+    }
+    let document_ms = resources.last().map(|r| r.end_ms()).unwrap_or(0);
+
+    let mut queue = VecDeque::new();
+    let mut seen: HashSet<String> = resources.iter().map(|r| r.url.clone()).collect();
+    if let Some(document) = document {
+        if is_success(&document) && document.content_type.as_ref().map(|c| c.contains("html")).unwrap_or(true) {
+            let html = String::from_utf8_lossy(&document.body);
+            let queued_ns = time::precise_time_ns();
+            for (url, kind) in html_resources(&document_url, &html) {
+                if seen.insert(url.clone()) {
+                    queue.push_back(Queued { url: url, kind: kind, initiator: Some(document_url.clone()), queued_ns: queued_ns });
+                }
+            }
+        }
+    } else {
+        warn!("{}: gave up after {} redirects", url, MAX_REDIRECTS);
+    }
+
+    let fetch = Arc::new(fetch);
+    let (sender, receiver) = channel();
+    let mut open: HashMap<Option<String>, usize> = HashMap::new();
+    let mut in_flight = 0;
+    let mut subresources = Vec::new();
+    loop {
+        // Send every queued request whose host has a free connection, in the order they were found
+        let mut i = 0;
+        while i < queue.len() {
+            let host = http_host(&queue[i].url);
+            if *open.get(&host).unwrap_or(&0) >= per_host.max(1) {
+                i += 1;
+                continue;
+            }
+            *open.entry(host).or_insert(0) += 1;
+            in_flight += 1;
+            let queued = queue.remove(i).unwrap();
+            let fetch = fetch.clone();
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let url = queued.url.clone();
+                let mut reply = ReplyGuard {
+                    sender: sender,
+                    queued: Some(queued),
+                    started_at: format_timestamp(&time::now_utc()),
+                    started: time::precise_time_ns(),
+                    fetched: None,
+                };
+                reply.fetched = Some(fetch(&url));
+            });
+        }
+        if in_flight == 0 {
+            break;
+        }
+        // This end keeps a sender, and every request replies even if it panics, so receiving can't
+        // fail or wait forever while requests are in flight
+        let (queued, started_at, started, ended, fetched) = receiver.recv().unwrap();
+        in_flight -= 1;
+        if let Some(count) = open.get_mut(&http_host(&queued.url)) {
+            *count -= 1;
+        }
+        if queued.kind == ResourceKind::Stylesheet && is_success(&fetched) {
+            let css = String::from_utf8_lossy(&fetched.body);
+            let queued_ns = time::precise_time_ns();
+            for (url, kind) in css_resources(&queued.url, &css) {
+                if seen.insert(url.clone()) {
+                    queue.push_back(Queued { url: url, kind: kind, initiator: Some(queued.url.clone()), queued_ns: queued_ns });
+                }
+            }
+        }
+        subresources.push((started, record(&queued.url, queued.kind, queued.initiator, started_at,
+                                           since_start(queued.queued_ns), since_start(started), since_start(ended), &fetched)));
+    }
+    subresources.sort_by(|a, b| a.0.cmp(&b.0));
+    resources.extend(subresources.into_iter().map(|(_, r)| r));
+
+    PageLoad {
+        url: url.to_string(),
+        document_ms: document_ms,
+        page_complete_ms: resources.iter().map(|r| r.end_ms()).max().unwrap_or(0),
+        requests: resources.len(),
+        failed: resources.iter().filter(|r| r.failed()).count(),
+        bytes: resources.iter().map(|r| r.size).sum(),
+        resources: resources,
+    }
+}
+
+/// True if ``fetched`` is a response with a 2xx status
+fn is_success(fetched: &Fetched) -> bool {
+    fetched.status.map(|s| s >= 200 && s < 300).unwrap_or(false)
+}
+
+/// Records the timing of a request from the times, in milliseconds since the page load started,
+/// it was queued, sent and completed
+fn record(url: &str, kind: ResourceKind, initiator: Option<String>, started_at: String,
+          queued_ms: i64, start_ms: i64, end_ms: i64, fetched: &Fetched) -> Resource {
+    let mut phases = fetched.phases;
+    phases.blocked_ms = start_ms - queued_ms;
+    Resource {
+        url: url.to_string(),
+        kind: kind,
+        initiator: initiator,
+        started_at: started_at,
+        queued_ms: queued_ms,
+        start_ms: start_ms,
+        total_ms: end_ms - start_ms,
+        phases: phases,
+        status: fetched.status,
+        content_type: fetched.content_type.clone(),
+        size: fetched.body.len() as u64,
+        error: fetched.error.clone(),
+    }
+}
+
+/// The resources an HTML document at ``base`` needs to be displayed, in the order they appear
+///
+/// These are its stylesheets (including those it preloads), scripts, images, icons, and the fonts
+/// and images used by its inline ``<style>`` tags. Links to other pages aren't included.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::page::{html_resources, ResourceKind};
+///
+/// let resources = html_resources("https://www.example.com/a/", r#"
+///     <link rel="stylesheet" href="/site.css"><script src="app.js"></script>
+///     <link rel=preload as=font href=/f.woff2><a href="/b">B</a><img src="//cdn.example.com/x.png">
+/// "#);
+/// assert_eq!(resources, vec![
+///     ("https://www.example.com/site.css".to_string(), ResourceKind::Stylesheet),
+///     ("https://www.example.com/a/app.js".to_string(), ResourceKind::Script),
+///     ("https://www.example.com/f.woff2".to_string(), ResourceKind::Font),
+///     ("https://cdn.example.com/x.png".to_string(), ResourceKind::Image),
+/// ]);
+/// ```
+pub fn html_resources(base: &str, html: &str) -> Vec<(String, ResourceKind)> {
+    let mut base = base.to_string();
+    let mut found = Vec::new();
+    for tag in tags(html) {
+        let resource = match &tag.name[..] {
+            "base" => {
+                if let Some(url) = tag.attribute("href").and_then(|href| resolve(&base, href)) {
+                    base = url;
+                }
+                None
+            }
+            "link" => {
+                let rel = tag.attribute("rel").unwrap_or("").to_lowercase();
+                let rels: Vec<&str> = rel.split_whitespace().collect();
+                let kind = if rels.contains(&"stylesheet") {
+                    Some(ResourceKind::Stylesheet)
+                } else if rels.contains(&"icon") {
+                    Some(ResourceKind::Image)
+                } else if rels.contains(&"preload") {
+                    match &tag.attribute("as").unwrap_or("").to_lowercase()[..] {
+                        "style" => Some(ResourceKind::Stylesheet),
+                        "script" => Some(ResourceKind::Script),
+                        "image" => Some(ResourceKind::Image),
+                        "font" => Some(ResourceKind::Font),
+                        _ => None,
+                    }
+                } else {
+                    None
+                };
+                kind.and_then(|kind| tag.attribute("href").map(|href| (href, kind)))
+            }
+            "script" => tag.attribute("src").map(|src| (src, ResourceKind::Script)),
+            "img" => tag.attribute("src").map(|src| (src, ResourceKind::Image)),
+            "style" => {
+                for resource in css_resources(&base, tag.content.as_ref().map(|c| &c[..]).unwrap_or("")) {
+                    if !found.contains(&resource) {
+                        found.push(resource);
+                    }
+                }
+                None
+            }
+            _ => None,
+        };
+        if let Some((reference, kind)) = resource {
+            if let Some(url) = resolve(&base, reference) {
+                if !found.iter().any(|f: &(String, ResourceKind)| f.0 == url) {
+                    found.push((url, kind));
+                }
+            }
+        }
+    }
+    found
+}
+
+/// The resources a stylesheet at ``base`` refers to: stylesheets it imports, and the fonts and
+/// images in its ``url()`` values, told apart by their extensions
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::page::{css_resources, ResourceKind};
+///
+/// let resources = css_resources("http://www.example.com/css/site.css", r#"
+///     @import "print.css";
+///     /* background: url(unused.png) */
+///     @font-face { src: url('../fonts/a.woff2?v=2') format("woff2"), url(data:font/woff;base64,AAAA) }
+///     body { background: URL( "bg.jpg" ) }
+/// "#);
+/// assert_eq!(resources, vec![
+///     ("http://www.example.com/css/print.css".to_string(), ResourceKind::Stylesheet),
+///     ("http://www.example.com/fonts/a.woff2?v=2".to_string(), ResourceKind::Font),
+///     ("http://www.example.com/css/bg.jpg".to_string(), ResourceKind::Image),
+/// ]);
+/// ```
+pub fn css_resources(base: &str, css: &str) -> Vec<(String, ResourceKind)> {
+    let css = strip_comments(css);
+    // Lower cased in place, so positions in ``lower`` are positions in ``css``
+    let lower = css.to_ascii_lowercase();
+    let mut references = Vec::new();
+    let mut position = 0;
+    while position < css.len() {
+        let rest = &lower[position..];
+        let next = match (rest.find("url("), rest.find("@import")) {
+            (Some(u), Some(i)) => u.min(i),
+            (Some(u), None) => u,
+            (None, Some(i)) => i,
+            (None, None) => break,
+        };
+        let at = position + next;
+        if lower[at..].starts_with("@import") {
+            // ``@import url(...)`` is read as a url() below; only the string form is read here
+            let after = css[at + 7..].trim_left();
+            position = css.len() - after.len();
+            if let Some((reference, end)) = quoted(after) {
+                references.push((reference, Some(ResourceKind::Stylesheet)));
+                position += end;
+            }
+        } else {
+            let after = css[at + 4..].trim_left();
+            let start = css.len() - after.len();
+            let (reference, end) = match quoted(after) {
+                Some((reference, end)) => (reference, end),
+                None => {
+                    let end = after.find(')').unwrap_or(after.len());
+                    (after[..end].trim_right().to_string(), end)
+                }
+            };
+            let imported = lower[..at].trim_right().ends_with("@import");
+            references.push((reference, if imported { Some(ResourceKind::Stylesheet) } else { None }));
+            position = start + end;
+        }
+    }
+
+    let mut found: Vec<(String, ResourceKind)> = Vec::new();
+    for (reference, kind) in references {
+        if let Some(url) = resolve(base, &reference) {
+            let kind = kind.unwrap_or_else(|| kind_from_extension(&url));
+            if !found.iter().any(|f| f.0 == url) {
+                found.push((url, kind));
+            }
+        }
+    }
+    found
+}
+
+/// Reads a CSS string at the start of ``s``, returning its contents and the length of ``s`` it took
+fn quoted(s: &str) -> Option<(String, usize)> {
+    let quote = match s.chars().next() {
+        Some(c) if c == '"' || c == '\'' => c,
+        _ => return None,
+    };
+    let end = s[1..].find(quote).map(|e| e + 1).unwrap_or(s.len());
+    Some((s[1..end].to_string(), (end + 1).min(s.len())))
+}
+
+/// ``css`` without its comments
+fn strip_comments(css: &str) -> String {
+    let mut stripped = String::new();
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        stripped.push_str(&rest[..start]);
+        rest = match rest[start + 2..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// Whether a url in a stylesheet is a font, stylesheet or (by default) an image, from its extension
+fn kind_from_extension(url: &str) -> ResourceKind {
+    let path = url.split(|c| c == '?' || c == '#').next().unwrap_or("").to_lowercase();
+    let extension = path.rsplit('.').next().unwrap_or("");
+    match extension {
+        "woff" | "woff2" | "ttf" | "otf" | "eot" => ResourceKind::Font,
+        "css" => ResourceKind::Stylesheet,
+        _ => ResourceKind::Image,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /// A response with ``status``, ``content_type`` and ``body``
+    fn response(status: u16, content_type: &str, body: &str) -> Fetched {
+        Fetched {
+            status: Some(status),
+            content_type: Some(content_type.to_string()),
+            location: None,
+            body: body.as_bytes().to_vec(),
+            phases: Phases::default(),
+            error: None,
+        }
+    }
+
+    /// A small site, as served without following redirects
+    fn fetch(url: &str) -> Fetched {
+        match url {
+            "http://example.com/" => Fetched {
+                location: Some("https://www.example.com/".to_string()),
+                ..response(301, "text/html", "")
+            },
+            "https://www.example.com/" => response(200, "text/html; charset=utf-8",
+                                                   "<link rel=stylesheet href=/site.css><script src=/app.js></script>\
+                                                    <img src=/logo.png><img src=/logo.png>"),
+            "https://www.example.com/site.css" => response(200, "text/css", "@font-face { src: url(/f.woff) }"),
+            "https://www.example.com/app.js" => response(200, "application/javascript", "var a = '<img src=x.png>';"),
+            "https://www.example.com/f.woff" => response(200, "font/woff", "wOFF"),
+            _ => Fetched { status: None, error: Some("connection refused".to_string()), ..response(0, "", "") },
+        }
+    }
+
+    #[test]
+    /// The document's redirect should be followed, and each resource fetched once
+    fn loads_page() {
+        let page = load("http://example.com/", DEFAULT_PER_HOST, fetch);
+        let requests: Vec<(&str, ResourceKind, Option<&str>)> = page.resources.iter()
+            .map(|r| (&r.url[..], r.kind, r.initiator.as_ref().map(|i| &i[..]))).collect();
+        assert_eq!(requests[0], ("http://example.com/", ResourceKind::Document, None));
+        assert_eq!(requests[1], ("https://www.example.com/", ResourceKind::Document, Some("http://example.com/")));
+        assert_eq!(requests.len(), 6);
+        for expected in &[("https://www.example.com/site.css", ResourceKind::Stylesheet, Some("https://www.example.com/")),
+                          ("https://www.example.com/app.js", ResourceKind::Script, Some("https://www.example.com/")),
+                          ("https://www.example.com/logo.png", ResourceKind::Image, Some("https://www.example.com/")),
+                          ("https://www.example.com/f.woff", ResourceKind::Font, Some("https://www.example.com/site.css"))] {
+            assert!(requests.contains(expected), "{:?} wasn't requested", expected);
+        }
+        assert_eq!(page.failed, 1);
+        assert_eq!(page.bytes, page.resources.iter().map(|r| r.size).sum());
+        assert!(page.page_complete_ms >= page.document_ms);
+        assert!(page.resources.iter().all(|r| r.phases.blocked_ms >= 0));
+    }
+
+    #[test]
+    /// Redirect loops should be given up on
+    fn stops_redirecting() {
+        let page = load("http://www.example.com/", DEFAULT_PER_HOST, |url| Fetched {
+            location: Some(format!("{}a", url)),
+            ..response(302, "text/html", "<img src=x.png>")
+        });
+        assert_eq!(page.requests, MAX_REDIRECTS + 1);
+        assert!(page.resources.iter().all(|r| r.kind == ResourceKind::Document));
+    }
+
+    #[test]
+    /// No more than ``per_host`` requests should be made to a host at once
+    fn limits_requests_per_host() {
+        let images: String = (0..8).map(|i| format!("<img src=/{}.png><img src=//cdn.example.com/{}.png>", i, i)).collect();
+        // The number of requests open at once, and the most there have been
+        let open = Arc::new(Mutex::new((0, 0)));
+        let counts = open.clone();
+        let page = load("http://www.example.com/", 2, move |url| {
+            if url == "http://www.example.com/" {
+                return response(200, "text/html", &images);
+            }
+            // Only requests to www.example.com are counted
+            let counted = url.starts_with("http://www.");
+            if counted {
+                let mut counts = counts.lock().unwrap();
+                counts.0 += 1;
+                counts.1 = counts.1.max(counts.0);
+            }
+            thread::sleep(Duration::from_millis(10));
+            if counted {
+                counts.lock().unwrap().0 -= 1;
+            }
+            response(200, "image/png", "")
+        });
+        assert_eq!(page.requests, 17);
+        assert_eq!(open.lock().unwrap().1, 2);
+        assert!(page.resources.iter().any(|r| r.phases.blocked_ms > 0));
+    }
+
+    #[test]
+    /// A fetch which panics should be recorded as a failed request
+    fn records_panicking_fetch() {
+        let page = load("http://www.example.com/", DEFAULT_PER_HOST, |url| {
+            if url == "http://www.example.com/a.png" {
+                panic!("fetch failed");
+            }
+            response(200, "text/html", "<img src=/a.png><img src=/b.png>")
+        });
+        assert_eq!(page.requests, 3);
+        assert_eq!(page.failed, 1);
+        let failed = page.resources.iter().find(|r| r.failed()).unwrap();
+        assert_eq!(failed.url, "http://www.example.com/a.png");
+        assert_eq!(failed.error, Some("the request panicked".to_string()));
+    }
+
+    #[test]
+    /// Only the stylesheet rels of links should be loaded, and inline styles' resources found
+    fn finds_html_resources() {
+        let resources = html_resources("http://www.example.com/", "<base href=/static/><link rel=canonical href=/x>\
+                                                                  <link rel='shortcut icon' href=favicon.ico>\
+                                                                  <style>p { background: url(p.gif) }</style>\
+                                                                  <link rel=preload as=fetch href=/data.json>");
+        assert_eq!(resources, vec![("http://www.example.com/static/favicon.ico".to_string(), ResourceKind::Image),
+                                   ("http://www.example.com/static/p.gif".to_string(), ResourceKind::Image)]);
+    }
+}
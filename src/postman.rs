@@ -0,0 +1,480 @@
+//! Reading probe targets from Postman collections.
+//!
+//! Every request of a Postman v2.0 or v2.1 collection becomes a target, with its method, headers,
+//! body and authorization. Requests in folders are grouped by their folder, e.g. ``Orders / Admin``.
+//! Variables such as ``{{baseUrl}}`` are resolved from an environment exported from Postman,
+//! falling back to the collection's own variables. Of Postman's dynamic variables, ``{{$guid}}``,
+//! ``{{$timestamp}}`` and ``{{$randomInt}}`` are supported. Unlike in Postman they're given a value
+//! once, when the collection is read, so retries and repeated samples of a request send the same
+//! value. Pre-request and test scripts aren't run.
+
+use std::collections::BTreeMap;
+
+use rand::{self, Rng};
+use rustc_serialize::base64::{ToBase64, STANDARD};
+use rustc_serialize::json::Json;
+use time;
+
+use canonical;
+use envelope;
+use target::{parse_method, Target};
+use urllist::Entry;
+
+/// Values of variables, by name
+pub type Variables = BTreeMap<String, String>;
+
+/// How deeply variables may refer to other variables, so that cycles are caught
+const MAX_NESTING: usize = 10;
+
+/// True if ``path`` names a collection exported from Postman
+pub fn is_collection_file(path: &str) -> bool {
+    let path = path.to_lowercase();
+    path.ends_with(".postman_collection.json") || path.ends_with(".postman_collection")
+}
+
+/// Parses the variables of an environment exported from Postman, leaving out disabled ones
+///
+/// # Failures
+///
+/// Returns an Err if ``contents`` isn't JSON or has no ``values`` array.
+///
+/// # Examples
+///
+/// ```
+/// let variables = httplatency::postman::parse_environment(r#"{"name": "Staging", "values": [
+///     {"key": "baseUrl", "value": "https://staging.example.com", "enabled": true},
+///     {"key": "token", "value": "old", "enabled": false}
+/// ]}"#).unwrap();
+/// assert_eq!(variables.get("baseUrl").map(|v| &v[..]), Some("https://staging.example.com"));
+/// assert!(variables.get("token").is_none());
+/// ```
+pub fn parse_environment(contents: &str) -> Result<Variables, String> {
+    let json = try!(Json::from_str(contents).map_err(|e| format!("Invalid JSON: {}", e)));
+    match json.find("values") {
+        Some(values) => variables(values),
+        None => Err("No values array found, expected a Postman environment".to_string()),
+    }
+}
+
+/// Parses the requests of a Postman collection, keeping those which can't be probed
+///
+/// Each entry is numbered by its position among the collection's requests, starting from 1, and
+/// its text is the names of its folders and itself. Variables are resolved from ``environment``
+/// first, then from the collection's variables.
+///
+/// # Failures
+///
+/// Returns an Err if ``contents`` isn't JSON or isn't a Postman v2 collection.
+///
+/// # Examples
+///
+/// ```
+/// let entries = httplatency::postman::entries(r#"{
+///     "info": {"name": "Shop", "schema": "https://schema.getpostman.com/json/collection/v2.1.0/collection.json"},
+///     "variable": [{"key": "baseUrl", "value": "https://shop.example.com"}],
+///     "item": [{"name": "Orders", "item": [
+///         {"name": "List orders", "request": {"method": "GET", "url": "{{baseUrl}}/orders",
+///                                             "header": [{"key": "Accept", "value": "application/json"}]}}
+///     ]}]
+/// }"#, &Default::default()).unwrap();
+/// assert_eq!(entries[0].text, "Orders / List orders");
+/// let target = entries[0].target.as_ref().unwrap();
+/// assert_eq!(target.url, "https://shop.example.com/orders");
+/// assert_eq!(target.group, Some("Orders".to_string()));
+/// ```
+pub fn entries(contents: &str, environment: &Variables) -> Result<Vec<Entry>, String> {
+    let json = try!(Json::from_str(contents).map_err(|e| format!("Invalid JSON: {}", e)));
+    let schema = json.find_path(&["info", "schema"]).and_then(|s| s.as_string()).unwrap_or("");
+    if !schema.contains("/collection/v2.") {
+        return Err("No info.schema for a v2.0 or v2.1 collection found, expected a Postman collection".to_string());
+    }
+    let mut variables = match json.find("variable") {
+        Some(values) => try!(variables(values).map_err(|e| format!("variable: {}", e))),
+        None => Variables::new(),
+    };
+    variables.extend(environment.iter().map(|(k, v)| (k.clone(), v.clone())));
+    let mut entries = Vec::new();
+    try!(walk(&json, &mut Vec::new(), json.find("auth"), &variables, &mut entries));
+    Ok(entries)
+}
+
+/// Adds an entry for each request in the items of ``folder``, and of the folders within it
+fn walk(folder: &Json, path: &mut Vec<String>, auth: Option<&Json>, variables: &Variables,
+        entries: &mut Vec<Entry>) -> Result<(), String> {
+    let items = match folder.find("item") {
+        Some(&Json::Array(ref items)) => items,
+        Some(_) => return Err(format!("{}: item: expected an array", path.join(" / "))),
+        None => return Ok(()),
+    };
+    for item in items.iter() {
+        let name = item.find("name").and_then(|n| n.as_string()).unwrap_or("(unnamed)");
+        let auth = item.find("auth").or(auth);
+        path.push(name.to_string());
+        if item.find("item").is_some() {
+            try!(walk(item, path, auth, variables, entries));
+        } else {
+            let group = if path.len() > 1 { Some(path[..path.len() - 1].join(" / ")) } else { None };
+            entries.push(Entry {
+                line: entries.len() + 1,
+                text: path.join(" / "),
+                target: item.find("request").ok_or("no request".to_string())
+                    .and_then(|request| target(request, auth, variables))
+                    .map(|mut target| {
+                        target.group = group;
+                        target
+                    }),
+            });
+        }
+        path.pop();
+    }
+    Ok(())
+}
+
+/// Builds the target for a request, using ``auth`` unless the request has its own
+fn target(request: &Json, auth: Option<&Json>, variables: &Variables) -> Result<Target, String> {
+    let url = match (request, request.find("url")) {
+        // A request may be given as just its url
+        (&Json::String(ref url), _) | (_, Some(&Json::String(ref url))) => url.clone(),
+        (_, Some(url)) => try!(object_url(url)),
+        (_, None) => return Err("no url".to_string()),
+    };
+    let mut target = try!(parse_url(&try!(substitute(&url, variables))));
+    let method = request.find("method").and_then(|m| m.as_string()).unwrap_or("GET");
+    target.method = try!(parse_method(method).map_err(|e| format!("method: {}", e)));
+
+    if let Some(&Json::Array(ref headers)) = request.find("header") {
+        for header in headers.iter().filter(|h| enabled(h)) {
+            let name = try!(header.find("key").and_then(|k| k.as_string()).ok_or("header without a key".to_string()));
+            let value = header.find("value").and_then(|v| v.as_string()).unwrap_or("");
+            target.headers.push((try!(substitute(name, variables)), try!(substitute(value, variables))));
+        }
+    }
+    if let Some(body) = request.find("body") {
+        if let Some((content_type, body)) = try!(request_body(body, variables)) {
+            if !target.headers.iter().any(|h| h.0.eq_ignore_ascii_case("Content-Type")) {
+                target.headers.push(("Content-Type".to_string(), content_type.to_string()));
+            }
+            target.body = Some(body);
+        }
+    }
+    match request.find("auth").or(auth) {
+        Some(auth) => try!(authorize(&mut target, auth, variables)),
+        None => (),
+    }
+    Ok(target)
+}
+
+/// Builds the url of a request given as an object, from its ``raw`` form with any ``:name`` path
+/// variables filled in
+fn object_url(url: &Json) -> Result<String, String> {
+    let mut raw = try!(url.find("raw").and_then(|r| r.as_string()).ok_or("url: no raw url".to_string())).to_string();
+    if let Some(&Json::Array(ref path_variables)) = url.find("variable") {
+        for variable in path_variables.iter() {
+            if let (Some(key), Some(value)) = (variable.find("key").and_then(|k| k.as_string()),
+                                               variable.find("value").and_then(|v| v.as_string())) {
+                let segment = format!(":{}", key);
+                raw = {
+                    let (path, query) = match raw.find('?') {
+                        Some(i) => raw.split_at(i),
+                        None => (&raw[..], ""),
+                    };
+                    let path: Vec<&str> = path.split('/').map(|s| if s == segment { value } else { s }).collect();
+                    path.join("/") + query
+                };
+            }
+        }
+    }
+    Ok(raw)
+}
+
+/// Builds a GET target for a url with its variables resolved
+fn parse_url(url: &str) -> Result<Target, String> {
+    match canonical::parse(url) {
+        Ok(c) => {
+            let mut target = Target::get(c.url);
+            target.scheme_guessed = c.scheme_guessed;
+            Ok(target)
+        }
+        Err(err) => Err(format!("'{}' is not a valid http(s) url: {}", url, err)),
+    }
+}
+
+/// The content type and text of a request body, if it has one
+fn request_body(body: &Json, variables: &Variables) -> Result<Option<(&'static str, String)>, String> {
+    if body.find("disabled") == Some(&Json::Boolean(true)) {
+        return Ok(None);
+    }
+    match body.find("mode").and_then(|m| m.as_string()) {
+        Some("raw") => {
+            let raw = body.find("raw").and_then(|r| r.as_string()).unwrap_or("");
+            if raw.is_empty() {
+                return Ok(None);
+            }
+            let content_type = match body.find_path(&["options", "raw", "language"]).and_then(|l| l.as_string()) {
+                Some("json") => "application/json",
+                Some("xml") => "application/xml",
+                Some("html") => "text/html",
+                Some("javascript") => "application/javascript",
+                _ => "text/plain",
+            };
+            Ok(Some((content_type, try!(substitute(raw, variables)))))
+        }
+        Some("urlencoded") => {
+            let mut params = Vec::new();
+            if let Some(&Json::Array(ref fields)) = body.find("urlencoded") {
+                for field in fields.iter().filter(|f| enabled(f)) {
+                    let key = try!(substitute(field.find("key").and_then(|k| k.as_string()).unwrap_or(""), variables));
+                    let value = try!(substitute(field.find("value").and_then(|v| v.as_string()).unwrap_or(""), variables));
+                    params.push(format!("{}={}", canonical::percent_encode(&key), canonical::percent_encode(&value)));
+                }
+            }
+            Ok(Some(("application/x-www-form-urlencoded", params.join("&"))))
+        }
+        Some("graphql") => {
+            let query = body.find_path(&["graphql", "query"]).and_then(|q| q.as_string()).unwrap_or("");
+            let mut fields = BTreeMap::new();
+            fields.insert("query".to_string(), Json::String(try!(substitute(query, variables))));
+            if let Some(given) = body.find_path(&["graphql", "variables"]).and_then(|v| v.as_string()) {
+                let given = try!(substitute(given, variables));
+                if !given.trim().is_empty() {
+                    let parsed = try!(Json::from_str(&given).map_err(|e| format!("graphql variables: {}", e)));
+                    fields.insert("variables".to_string(), parsed);
+                }
+            }
+            Ok(Some(("application/json", Json::Object(fields).to_string())))
+        }
+        Some(mode) => Err(format!("'{}' bodies aren't supported", mode)),
+        None => Ok(None),
+    }
+}
+
+/// Adds the header or query parameter an authorization gives to ``target``
+fn authorize(target: &mut Target, auth: &Json, variables: &Variables) -> Result<(), String> {
+    let kind = auth.find("type").and_then(|t| t.as_string()).unwrap_or("noauth");
+    let param = |key: &str| -> Result<String, String> {
+        let value = match auth.find(kind) {
+            // v2.1 lists the parameters as key/value pairs, v2.0 as an object
+            Some(&Json::Array(ref params)) => {
+                params.iter().find(|p| p.find("key").and_then(|k| k.as_string()) == Some(key))
+                      .and_then(|p| p.find("value")).and_then(|v| v.as_string())
+            }
+            Some(params) => params.find(key).and_then(|v| v.as_string()),
+            None => None,
+        };
+        substitute(value.unwrap_or(""), variables)
+    };
+    match kind {
+        "noauth" => (),
+        "bearer" => target.headers.push(("Authorization".to_string(), format!("Bearer {}", try!(param("token"))))),
+        "basic" => {
+            let credentials = format!("{}:{}", try!(param("username")), try!(param("password")));
+            target.headers.push(("Authorization".to_string(), format!("Basic {}", credentials.as_bytes().to_base64(STANDARD))));
+        }
+        "apikey" => {
+            let (key, value) = (try!(param("key")), try!(param("value")));
+            if try!(param("in")) == "query" {
+                let separator = if target.url.contains('?') { '&' } else { '?' };
+                target.url = format!("{}{}{}={}", target.url, separator, canonical::percent_encode(&key),
+                                     canonical::percent_encode(&value));
+            } else {
+                target.headers.push((key, value));
+            }
+        }
+        _ => return Err(format!("'{}' authorization isn't supported", kind)),
+    }
+    Ok(())
+}
+
+/// True unless an item of a list (a header, variable or form field) is disabled
+fn enabled(item: &Json) -> bool {
+    item.find("disabled") != Some(&Json::Boolean(true)) && item.find("enabled") != Some(&Json::Boolean(false))
+}
+
+/// Parses a list of variables, leaving out disabled ones
+fn variables(values: &Json) -> Result<Variables, String> {
+    let values = match *values {
+        Json::Array(ref values) => values,
+        _ => return Err("expected an array".to_string()),
+    };
+    let mut variables = Variables::new();
+    for value in values.iter().filter(|v| enabled(v)) {
+        let key = try!(value.find("key").and_then(|k| k.as_string()).ok_or("variable without a key".to_string()));
+        let text = match value.find("value") {
+            Some(&Json::String(ref s)) => s.clone(),
+            Some(&Json::Null) | None => String::new(),
+            Some(other) => other.to_string(),
+        };
+        variables.insert(key.to_string(), text);
+    }
+    Ok(variables)
+}
+
+/// Replaces every ``{{name}}`` in ``text`` with the value of the variable, including variables
+/// used in the values of others
+///
+/// # Failures
+///
+/// Returns an Err if a variable has no value, or variables are nested too deeply.
+///
+/// # Examples
+///
+/// ```
+/// let mut variables = httplatency::postman::Variables::new();
+/// variables.insert("host".to_string(), "api.example.com".to_string());
+/// variables.insert("baseUrl".to_string(), "https://{{host}}/v2".to_string());
+/// assert_eq!(httplatency::postman::substitute("{{baseUrl}}/orders", &variables),
+///            Ok("https://api.example.com/v2/orders".to_string()));
+/// assert!(httplatency::postman::substitute("{{token}}", &variables).is_err());
+/// ```
+pub fn substitute(text: &str, variables: &Variables) -> Result<String, String> {
+    let mut text = text.to_string();
+    for _ in 0..MAX_NESTING {
+        if !text.contains("{{") {
+            return Ok(text);
+        }
+        let mut substituted = String::new();
+        let mut rest = &text[..];
+        while let Some(start) = rest.find("{{") {
+            let end = match rest[start + 2..].find("}}") {
+                Some(length) => start + 2 + length,
+                None => break,
+            };
+            let name = rest[start + 2..end].trim();
+            let value = match variables.get(name) {
+                Some(value) => value.clone(),
+                None => try!(dynamic(name).ok_or(format!("no value for the variable '{}'", name))),
+            };
+            substituted.push_str(&rest[..start]);
+            substituted.push_str(&value);
+            rest = &rest[end + 2..];
+        }
+        substituted.push_str(rest);
+        if substituted == text {
+            return Ok(text);
+        }
+        text = substituted;
+    }
+    Err(format!("variables are nested more than {} deep in '{}'", MAX_NESTING, text))
+}
+
+/// A new value for one of Postman's dynamic variables, so each place it is used gets its own
+fn dynamic(name: &str) -> Option<String> {
+    match name {
+        "$guid" => Some(envelope::new_uuid()),
+        "$timestamp" => Some(time::get_time().sec.to_string()),
+        "$randomInt" => Some(rand::thread_rng().gen_range(0, 1001).to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A collection with folders, variables and authorization
+    const COLLECTION: &'static str = r#"{
+        "info": {"name": "Shop", "schema": "https://schema.getpostman.com/json/collection/v2.1.0/collection.json"},
+        "auth": {"type": "bearer", "bearer": [{"key": "token", "value": "{{token}}", "type": "string"}]},
+        "variable": [
+            {"key": "baseUrl", "value": "https://shop.example.com"},
+            {"key": "token", "value": "collection-token"},
+            {"key": "pageSize", "value": 20}
+        ],
+        "item": [
+            {"name": "Health", "request": "{{baseUrl}}/health"},
+            {"name": "Orders", "item": [
+                {"name": "List", "request": {
+                    "method": "GET",
+                    "header": [{"key": "Accept", "value": "application/json"},
+                               {"key": "X-Debug", "value": "1", "disabled": true}],
+                    "url": {"raw": "{{baseUrl}}/orders?size={{pageSize}}", "host": ["{{baseUrl}}"], "path": ["orders"]}
+                }},
+                {"name": "Admin", "auth": {"type": "basic", "basic": [
+                    {"key": "username", "value": "admin"}, {"key": "password", "value": "s3cret"}
+                ]}, "item": [
+                    {"name": "Cancel", "request": {
+                        "method": "POST",
+                        "url": {"raw": "{{baseUrl}}/orders/:id/cancel?notify=1", "variable": [{"key": "id", "value": "42"}]},
+                        "body": {"mode": "raw", "raw": "{\"reason\": \"{{reason}}\"}", "options": {"raw": {"language": "json"}}}
+                    }},
+                    {"name": "Refund", "request": {
+                        "method": "POST",
+                        "auth": {"type": "noauth"},
+                        "url": "{{baseUrl}}/refunds",
+                        "body": {"mode": "urlencoded", "urlencoded": [
+                            {"key": "order", "value": "42"}, {"key": "note", "value": "a b&c"}
+                        ]}
+                    }},
+                    {"name": "Upload", "request": {
+                        "method": "POST", "url": "{{baseUrl}}/uploads",
+                        "body": {"mode": "formdata", "formdata": [{"key": "file", "type": "file", "src": "a.png"}]}
+                    }}
+                ]}
+            ]}
+        ]
+    }"#;
+
+    /// The environment used with ``COLLECTION``
+    fn environment() -> Variables {
+        let mut environment = Variables::new();
+        environment.insert("token".to_string(), "env-token".to_string());
+        environment.insert("reason".to_string(), "late".to_string());
+        environment
+    }
+
+    #[test]
+    /// Requests should be read with their folders as groups and variables resolved
+    fn imports_requests() {
+        let entries = entries(COLLECTION, &environment()).unwrap();
+        let texts: Vec<&str> = entries.iter().map(|e| &e.text[..]).collect();
+        assert_eq!(texts, vec!["Health", "Orders / List", "Orders / Admin / Cancel", "Orders / Admin / Refund",
+                               "Orders / Admin / Upload"]);
+
+        let health = entries[0].target.as_ref().unwrap();
+        assert_eq!(health.url, "https://shop.example.com/health");
+        assert_eq!(health.group, None);
+        assert_eq!(health.headers, vec![("Authorization".to_string(), "Bearer env-token".to_string())]);
+
+        let list = entries[1].target.as_ref().unwrap();
+        assert_eq!(list.url, "https://shop.example.com/orders?size=20");
+        assert_eq!(list.group, Some("Orders".to_string()));
+        assert_eq!(list.headers.len(), 2);
+
+        let cancel = entries[2].target.as_ref().unwrap();
+        assert_eq!(cancel.url, "https://shop.example.com/orders/42/cancel?notify=1");
+        assert_eq!(cancel.group, Some("Orders / Admin".to_string()));
+        assert_eq!(cancel.body, Some(r#"{"reason": "late"}"#.to_string()));
+        assert_eq!(cancel.headers, vec![("Content-Type".to_string(), "application/json".to_string()),
+                                        ("Authorization".to_string(), "Basic YWRtaW46czNjcmV0".to_string())]);
+
+        let refund = entries[3].target.as_ref().unwrap();
+        assert_eq!(refund.body, Some("order=42&note=a%20b%26c".to_string()));
+        assert_eq!(refund.headers, vec![("Content-Type".to_string(),
+                                         "application/x-www-form-urlencoded".to_string())]);
+
+        assert_eq!(entries[4].target.as_ref().unwrap_err(), "'formdata' bodies aren't supported");
+    }
+
+    #[test]
+    /// Variables without values should be reported, and cycles caught
+    fn unresolved_variables() {
+        let entries = entries(COLLECTION, &Variables::new()).unwrap();
+        assert_eq!(entries[2].target.as_ref().unwrap_err(), "no value for the variable 'reason'");
+
+        let mut cycle = Variables::new();
+        cycle.insert("a".to_string(), "{{b}}".to_string());
+        cycle.insert("b".to_string(), "{{a}}".to_string());
+        assert!(substitute("{{a}}", &cycle).unwrap_err().contains("nested"));
+        assert_eq!(substitute("{{$guid}}", &cycle).unwrap().len(), 36);
+        assert_eq!(substitute("{{ unclosed", &cycle), Ok("{{ unclosed".to_string()));
+    }
+
+    #[test]
+    /// Files which aren't v2 collections should be rejected
+    fn rejects_other_files() {
+        assert!(entries("[]", &Variables::new()).unwrap_err().contains("Postman collection"));
+        assert!(entries(r#"{"info": {"schema": "https://schema.getpostman.com/json/collection/v1.0.0/"}}"#,
+                        &Variables::new()).unwrap_err().contains("v2.1"));
+        assert!(parse_environment(r#"{"name": "x"}"#).is_err());
+    }
+}
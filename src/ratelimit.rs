@@ -0,0 +1,233 @@
+//! Per-host rate limits, so many urls on one server aren't requested all at once.
+//!
+//! Each host gets its own token bucket and a minimum delay between the start of consecutive
+//! requests. Limits are handed out as reservations: asking to send a request returns the time it
+//! may be sent, and the slot is taken immediately so concurrent callers are spaced out correctly.
+//!
+//! Both limits are measured from when each request starts, not from when its response arrives, so
+//! a slow response may still be in flight when the next request to its host starts. Nothing caps
+//! how many requests to one host are in flight at once other than the number of workers.
+
+use std::cmp;
+use std::collections::HashMap;
+
+const NS_PER_SEC: f64 = 1_000_000_000.0;
+const NS_PER_MS: u64 = 1_000_000;
+
+/// A classic token bucket: holds up to ``capacity`` tokens, refilled at ``rate`` tokens per second,
+/// with each request taking one token
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated_ns: u64,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket at time ``now_ns``
+    pub fn new(rate: f64, capacity: u32, now_ns: u64) -> TokenBucket {
+        let capacity = cmp::max(capacity, 1) as f64;
+        TokenBucket { rate: rate, capacity: capacity, tokens: capacity, updated_ns: now_ns }
+    }
+
+    /// The number of tokens in the bucket at ``at_ns``, which must not be before the last update
+    fn tokens_at(&self, at_ns: u64) -> f64 {
+        let elapsed = at_ns.saturating_sub(self.updated_ns) as f64 / NS_PER_SEC;
+        let tokens = self.tokens + elapsed * self.rate;
+        if tokens > self.capacity { self.capacity } else { tokens }
+    }
+
+    /// The earliest time, no sooner than ``now_ns``, at which a token will be available
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::ratelimit::TokenBucket;
+    /// let mut bucket = TokenBucket::new(10.0, 1, 0);
+    /// assert_eq!(bucket.available_at(0), 0);
+    /// bucket.take_at(0);
+    /// assert_eq!(bucket.available_at(0), 100000000);
+    /// ```
+    pub fn available_at(&self, now_ns: u64) -> u64 {
+        let from = cmp::max(now_ns, self.updated_ns);
+        let tokens = self.tokens_at(from);
+        if tokens >= 1.0 {
+            from
+        } else {
+            from + ((1.0 - tokens) / self.rate * NS_PER_SEC).ceil() as u64
+        }
+    }
+
+    /// Takes a token at ``at_ns``, which should be no sooner than ``available_at``
+    pub fn take_at(&mut self, at_ns: u64) {
+        let at = cmp::max(at_ns, self.updated_ns);
+        self.tokens = self.tokens_at(at) - 1.0;
+        self.updated_ns = at;
+    }
+}
+
+/// The limits applied to requests to every host
+#[derive(Debug, Clone)]
+pub struct HostPolicy {
+    /// The most requests per second to send to any one host, if limited
+    pub rate: Option<f64>,
+
+    /// How many requests to a host may be sent back-to-back before ``rate`` applies
+    pub burst: u32,
+
+    /// The least time in milliseconds between the start of two requests to the same host, however
+    /// long the first takes to respond
+    pub min_delay_ms: u64,
+}
+
+impl HostPolicy {
+    /// A policy which places no limits on requests
+    pub fn unlimited() -> HostPolicy {
+        HostPolicy { rate: None, burst: 1, min_delay_ms: 0 }
+    }
+}
+
+#[derive(Debug)]
+struct HostState {
+    bucket: Option<TokenBucket>,
+    last_start_ns: Option<u64>,
+}
+
+/// Tracks when each host may next be sent a request
+#[derive(Debug)]
+pub struct HostLimiter {
+    policy: HostPolicy,
+    hosts: HashMap<String, HostState>,
+    /// Longer minimum delays asked for by particular hosts
+    delays: HashMap<String, u64>,
+}
+
+impl HostLimiter {
+    /// Creates a limiter applying ``policy`` to each host separately
+    pub fn new(policy: HostPolicy) -> HostLimiter {
+        HostLimiter { policy: policy, hosts: HashMap::new(), delays: HashMap::new() }
+    }
+
+    /// Spaces the start of requests to ``host`` by at least ``min_delay_ms``, e.g. because its
+    /// robots.txt gives a ``Crawl-delay``. A delay shorter than the policy's has no effect.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::ratelimit::{HostLimiter, HostPolicy};
+    /// let mut limiter = HostLimiter::new(HostPolicy { rate: None, burst: 1, min_delay_ms: 500 });
+    /// limiter.slow_down("example.com", 2000);
+    /// limiter.slow_down("example.org", 100);
+    /// assert_eq!(limiter.reserve("example.com", 0), 0);
+    /// assert_eq!(limiter.reserve("example.com", 0), 2000000000);
+    /// assert_eq!(limiter.reserve("example.org", 0), 0);
+    /// assert_eq!(limiter.reserve("example.org", 0), 500000000);
+    /// ```
+    pub fn slow_down(&mut self, host: &str, min_delay_ms: u64) {
+        let delay = self.delays.entry(host.to_string()).or_insert(0);
+        *delay = cmp::max(*delay, min_delay_ms);
+    }
+
+    /// The earliest time, no sooner than ``now_ns``, a request to ``host`` may be sent
+    pub fn available_at(&self, host: &str, now_ns: u64) -> u64 {
+        let state = match self.hosts.get(host) {
+            Some(s) => s,
+            None => return now_ns,
+        };
+        let bucket_at = state.bucket.as_ref().map(|b| b.available_at(now_ns)).unwrap_or(now_ns);
+        let min_delay_ms = cmp::max(self.policy.min_delay_ms, self.delays.get(host).cloned().unwrap_or(0));
+        let delay_at = state.last_start_ns.map(|t| t.saturating_add(min_delay_ms.saturating_mul(NS_PER_MS))).unwrap_or(now_ns);
+        cmp::max(now_ns, cmp::max(bucket_at, delay_at))
+    }
+
+    /// Reserves the next slot for a request to ``host``, returning the time it may be sent
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::ratelimit::{HostLimiter, HostPolicy};
+    /// let mut limiter = HostLimiter::new(HostPolicy { rate: None, burst: 1, min_delay_ms: 500 });
+    /// assert_eq!(limiter.reserve("example.com", 0), 0);
+    /// assert_eq!(limiter.reserve("example.com", 0), 500000000);
+    /// assert_eq!(limiter.reserve("example.org", 0), 0);
+    /// ```
+    pub fn reserve(&mut self, host: &str, now_ns: u64) -> u64 {
+        let at = self.available_at(host, now_ns);
+        let policy = &self.policy;
+        let state = self.hosts.entry(host.to_string()).or_insert_with(|| HostState {
+            bucket: policy.rate.map(|r| TokenBucket::new(r, policy.burst, now_ns)),
+            last_start_ns: None,
+        });
+        if let Some(ref mut bucket) = state.bucket {
+            bucket.take_at(at);
+        }
+        state.last_start_ns = Some(at);
+        at
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    #[test]
+    /// A full bucket should allow ``capacity`` requests at once, then one per interval
+    fn bucket_burst_then_rate() {
+        let mut bucket = TokenBucket::new(10.0, 3, 0);
+        for _ in 0..3 {
+            assert_eq!(bucket.available_at(0), 0);
+            bucket.take_at(0);
+        }
+        assert_eq!(bucket.available_at(0), 100 * MS);
+    }
+
+    #[test]
+    /// The bucket should refill over time but never beyond its capacity
+    fn bucket_refills_to_capacity() {
+        let mut bucket = TokenBucket::new(10.0, 2, 0);
+        bucket.take_at(0);
+        bucket.take_at(0);
+        assert_eq!(bucket.available_at(10000 * MS), 10000 * MS);
+        bucket.take_at(10000 * MS);
+        bucket.take_at(10000 * MS);
+        assert_eq!(bucket.available_at(10000 * MS), 10100 * MS);
+    }
+
+    #[test]
+    /// Reservations for the same host should be spaced by the rate
+    fn limiter_rate_per_host() {
+        let mut limiter = HostLimiter::new(HostPolicy { rate: Some(4.0), burst: 1, min_delay_ms: 0 });
+        let times: Vec<u64> = (0..4).map(|_| limiter.reserve("a.example.com", 0)).collect();
+        assert_eq!(times, vec![0, 250 * MS, 500 * MS, 750 * MS]);
+    }
+
+    #[test]
+    /// The stricter of the rate and minimum delay should win
+    fn limiter_min_delay_and_rate() {
+        let mut limiter = HostLimiter::new(HostPolicy { rate: Some(100.0), burst: 5, min_delay_ms: 200 });
+        assert_eq!(limiter.reserve("a.example.com", 0), 0);
+        assert_eq!(limiter.reserve("a.example.com", 0), 200 * MS);
+        assert_eq!(limiter.reserve("a.example.com", 1000 * MS), 1000 * MS);
+    }
+
+    #[test]
+    /// Hosts shouldn't affect each other's limits
+    fn limiter_hosts_independent() {
+        let mut limiter = HostLimiter::new(HostPolicy { rate: Some(1.0), burst: 1, min_delay_ms: 0 });
+        assert_eq!(limiter.reserve("a.example.com", 0), 0);
+        assert_eq!(limiter.reserve("b.example.com", 0), 0);
+        assert_eq!(limiter.available_at("a.example.com", 0), 1000 * MS);
+    }
+
+    #[test]
+    /// An unlimited policy should never delay a request
+    fn limiter_unlimited() {
+        let mut limiter = HostLimiter::new(HostPolicy::unlimited());
+        for _ in 0..10 {
+            assert_eq!(limiter.reserve("a.example.com", 5), 5);
+        }
+    }
+}
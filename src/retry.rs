@@ -0,0 +1,291 @@
+//! Retrying requests that fail for transient reasons.
+//!
+//! A ``RetryPolicy`` decides whether an attempt should be repeated and how long to wait before
+//! doing so. Waits grow exponentially from ``backoff_base_ms`` up to ``backoff_cap_ms`` and, when
+//! jitter is enabled, a random wait between zero and that value is used instead ("full jitter")
+//! so that many probes failing at once don't all retry in lockstep.
+
+use std::thread;
+use std::time::Duration;
+
+use rand::{self, Rng};
+
+use page::Phases;
+use throughput::Curve;
+use upload::Upload;
+
+/// The broad category of a failed request, used to decide whether it is worth retrying
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    /// The connection was refused, reset or aborted
+    Connect,
+    /// The request timed out
+    Timeout,
+    /// Any other I/O failure, including failed DNS lookups
+    Io,
+    /// The TLS handshake failed
+    Tls,
+    /// The url could not be requested at all
+    InvalidUrl,
+    /// The server sent something that isn't valid HTTP
+    Protocol,
+    /// The request body couldn't be made, e.g. an upload file which can no longer be read
+    Body,
+}
+
+impl ErrorKind {
+    /// Parses the lower case name of an error kind, as used on the command line
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::retry::ErrorKind;
+    /// assert_eq!(ErrorKind::from_name("timeout"), Some(ErrorKind::Timeout));
+    /// assert_eq!(ErrorKind::from_name("bogus"), None);
+    /// ```
+    pub fn from_name(s: &str) -> Option<ErrorKind> {
+        match s {
+            "connect" => Some(ErrorKind::Connect),
+            "timeout" => Some(ErrorKind::Timeout),
+            "io" => Some(ErrorKind::Io),
+            "tls" => Some(ErrorKind::Tls),
+            "invalid-url" => Some(ErrorKind::InvalidUrl),
+            "protocol" => Some(ErrorKind::Protocol),
+            "body" => Some(ErrorKind::Body),
+            _ => None,
+        }
+    }
+}
+
+/// The outcome of a single request for a url
+///
+/// The default is an instant response with no status, to be filled in with what was measured.
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, Default)]
+pub struct Attempt {
+    /// The time in milliseconds this attempt took, whether or not it succeeded
+    pub latency_ms: i64,
+
+    /// The HTTP status code returned, if a response was received
+    pub status: Option<u16>,
+
+    /// Why the attempt failed, if no response was received
+    pub error: Option<String>,
+
+    /// The category of ``error``
+    pub error_kind: Option<ErrorKind>,
+
+    /// The size of the response body in bytes, if it was read in full (see ``Measure::FullBody``)
+    pub bytes: Option<u64>,
+
+    /// The ``Content-Encoding`` of the response, if it had one
+    pub content_encoding: Option<String>,
+
+    /// The size of the response body once decoded, if it was read in full and could be decoded
+    pub decompressed_bytes: Option<u64>,
+
+    /// How long decoding the response body took in milliseconds, if it was decoded
+    pub decompress_ms: Option<f64>,
+
+    /// How the response body downloaded over time, if it was read in full and sampled
+    pub throughput_curve: Option<Curve>,
+
+    /// How long each part of the upload took, if the request was an upload
+    pub upload: Option<Upload>,
+
+    /// How long each phase of the request took, if a response was received
+    pub phases: Option<Phases>,
+}
+
+impl Attempt {
+    /// An attempt which failed to get a response after ``latency_ms``
+    pub fn failure(latency_ms: i64, error: String, kind: ErrorKind) -> Attempt {
+        Attempt { latency_ms: latency_ms, error: Some(error), error_kind: Some(kind), ..Attempt::default() }
+    }
+
+    /// True if a response was received, regardless of its status code
+    pub fn is_response(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Controls how many times, and how often, a failing request is retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The total number of attempts to make, including the first
+    pub max_attempts: u32,
+
+    /// The wait in milliseconds before the first retry; doubled for every retry after that
+    pub backoff_base_ms: u64,
+
+    /// The longest wait in milliseconds between any two attempts
+    pub backoff_cap_ms: u64,
+
+    /// Wait a random time between zero and the backoff rather than the backoff itself
+    pub jitter: bool,
+
+    /// Failed requests of these kinds are retried
+    pub retryable_errors: Vec<ErrorKind>,
+
+    /// Responses with these status codes are retried
+    pub retryable_statuses: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    /// A policy which retries connection failures, timeouts and overloaded servers up to twice
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff_base_ms: 200,
+            backoff_cap_ms: 5000,
+            jitter: true,
+            retryable_errors: vec![ErrorKind::Connect, ErrorKind::Timeout, ErrorKind::Io],
+            retryable_statuses: vec![429, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy which never retries
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let policy = httplatency::retry::RetryPolicy::none();
+    /// assert_eq!(policy.max_attempts, 1);
+    /// ```
+    pub fn none() -> RetryPolicy {
+        RetryPolicy { max_attempts: 1, ..RetryPolicy::default() }
+    }
+
+    /// True if ``attempt`` failed in a way this policy considers transient
+    pub fn is_retryable(&self, attempt: &Attempt) -> bool {
+        match (attempt.error_kind, attempt.status) {
+            (Some(kind), _) => self.retryable_errors.contains(&kind),
+            (None, Some(status)) => self.retryable_statuses.contains(&status),
+            (None, None) => false,
+        }
+    }
+
+    /// The longest time in milliseconds to wait after the ``attempt``th attempt (counting from 1)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut policy = httplatency::retry::RetryPolicy::default();
+    /// policy.backoff_base_ms = 100;
+    /// policy.backoff_cap_ms = 300;
+    /// assert_eq!(policy.max_delay_ms(1), 100);
+    /// assert_eq!(policy.max_delay_ms(2), 200);
+    /// assert_eq!(policy.max_delay_ms(3), 300);
+    /// ```
+    pub fn max_delay_ms(&self, attempt: u32) -> u64 {
+        let shift = attempt.saturating_sub(1);
+        let delay = if shift >= 63 {
+            u64::max_value()
+        } else {
+            self.backoff_base_ms.saturating_mul(1 << shift)
+        };
+        if delay > self.backoff_cap_ms { self.backoff_cap_ms } else { delay }
+    }
+
+    /// The time in milliseconds to actually wait after the ``attempt``th attempt, with jitter applied
+    pub fn delay_ms(&self, attempt: u32) -> u64 {
+        let max = self.max_delay_ms(attempt);
+        if self.jitter && max > 0 {
+            rand::thread_rng().gen_range(0, max + 1)
+        } else {
+            max
+        }
+    }
+
+    /// Calls ``f`` until it produces an attempt which shouldn't be retried or ``max_attempts`` is
+    /// reached, sleeping between attempts. Returns every attempt made, in order.
+    pub fn run<F>(&self, mut f: F) -> Vec<Attempt> where F: FnMut() -> Attempt {
+        let mut attempts = Vec::new();
+        loop {
+            let attempt = f();
+            let retry = self.is_retryable(&attempt);
+            attempts.push(attempt);
+            let made = attempts.len() as u32;
+            if !retry || made >= self.max_attempts {
+                return attempts;
+            }
+            let delay = self.delay_ms(made);
+            debug!("Attempt {} failed, retrying in {}ms", made, delay);
+            thread::sleep(Duration::from_millis(delay));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn no_wait() -> RetryPolicy {
+        RetryPolicy { backoff_base_ms: 0, ..RetryPolicy::default() }
+    }
+
+    fn failed(kind: ErrorKind) -> Attempt {
+        Attempt::failure(1, "failed".to_string(), kind)
+    }
+
+    fn response(status: u16) -> Attempt {
+        Attempt { latency_ms: 1, status: Some(status), ..Attempt::default() }
+    }
+
+    #[test]
+    /// Backoff should double each attempt until it reaches the cap
+    fn backoff_doubles_until_capped() {
+        let policy = RetryPolicy { backoff_base_ms: 50, backoff_cap_ms: 1000, ..RetryPolicy::default() };
+        let delays: Vec<u64> = (1..7).map(|n| policy.max_delay_ms(n)).collect();
+        assert_eq!(delays, vec![50, 100, 200, 400, 800, 1000]);
+    }
+
+    #[test]
+    /// Very large attempt counts shouldn't overflow
+    fn backoff_does_not_overflow() {
+        let policy = RetryPolicy { backoff_base_ms: 50, backoff_cap_ms: 1000, ..RetryPolicy::default() };
+        assert_eq!(policy.max_delay_ms(200), 1000);
+    }
+
+    #[test]
+    /// Jittered delays should never exceed the un-jittered backoff
+    fn jitter_within_backoff() {
+        let policy = RetryPolicy { backoff_base_ms: 10, backoff_cap_ms: 40, jitter: true, ..RetryPolicy::default() };
+        for n in 1..10 {
+            assert!(policy.delay_ms(n) <= policy.max_delay_ms(n));
+        }
+    }
+
+    #[test]
+    /// Should keep retrying transient failures and stop on the first success
+    fn retries_until_success() {
+        let mut outcomes = vec![response(200), failed(ErrorKind::Connect), response(503)];
+        let attempts = no_wait().run(|| outcomes.pop().unwrap());
+        assert_eq!(attempts.len(), 3);
+        assert_eq!(attempts[2].status, Some(200));
+    }
+
+    #[test]
+    /// Should give up after max_attempts
+    fn stops_at_max_attempts() {
+        let policy = RetryPolicy { max_attempts: 4, ..no_wait() };
+        let attempts = policy.run(|| failed(ErrorKind::Timeout));
+        assert_eq!(attempts.len(), 4);
+    }
+
+    #[test]
+    /// Shouldn't retry errors or statuses that aren't listed as retryable
+    fn no_retry_when_not_retryable() {
+        let policy = no_wait();
+        assert_eq!(policy.run(|| failed(ErrorKind::InvalidUrl)).len(), 1);
+        assert_eq!(policy.run(|| response(404)).len(), 1);
+        assert_eq!(policy.run(|| response(200)).len(), 1);
+    }
+
+    #[test]
+    /// A policy of none should only ever make one attempt
+    fn none_makes_one_attempt() {
+        assert_eq!(RetryPolicy::none().run(|| failed(ErrorKind::Connect)).len(), 1);
+    }
+}
@@ -0,0 +1,168 @@
+//! Runs probes for many urls concurrently while respecting per-host rate limits.
+//!
+//! A fixed pool of workers takes targets from a shared queue. Each worker picks the queued target
+//! whose host may be sent a request soonest, so a throttled host doesn't hold up targets on other
+//! hosts, and reserves that host's next slot before sleeping until it.
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use time;
+
+use {http_host, Latency};
+use ratelimit::HostLimiter;
+use target::Target;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+struct Pending {
+    index: usize,
+    target: Target,
+    host: String,
+}
+
+struct Queue {
+    pending: Vec<Pending>,
+    limiter: HostLimiter,
+}
+
+impl Queue {
+    /// Removes the target that can be sent soonest, returning it with the time it may be sent
+    fn next(&mut self) -> Option<(Pending, u64)> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let now = time::precise_time_ns();
+        let mut best = 0;
+        let mut best_at = u64::max_value();
+        for (i, p) in self.pending.iter().enumerate() {
+            let at = self.limiter.available_at(&p.host, now);
+            if at < best_at {
+                best = i;
+                best_at = at;
+                if at <= now {
+                    break;
+                }
+            }
+        }
+        let next = self.pending.remove(best);
+        let at = self.limiter.reserve(&next.host, now);
+        Some((next, at))
+    }
+}
+
+/// Probes every target using ``concurrency`` workers, returning the results in the same order as ``targets``
+///
+/// Requests to each host are limited by ``limiter`` however many workers there are.
+pub fn probe_all<F>(targets: Vec<Target>, concurrency: usize, limiter: HostLimiter, probe: F) -> Vec<Option<Latency>>
+    where F: Fn(&Target) -> Option<Latency> + Send + Sync + 'static
+{
+    let total = targets.len();
+    let pending = targets.into_iter().enumerate().map(|(i, target)| {
+        let host = http_host(&target.url).unwrap_or(target.url.clone());
+        Pending { index: i, target: target, host: host }
+    }).collect();
+    let queue = Arc::new(Mutex::new(Queue { pending: pending, limiter: limiter }));
+    let probe = Arc::new(probe);
+    let (tx, rx) = channel();
+
+    let workers = if concurrency == 0 { 1 } else { concurrency };
+    for _ in 0..workers {
+        let queue = queue.clone();
+        let probe = probe.clone();
+        let tx = tx.clone();
+        thread::spawn(move || {
+            loop {
+                // The queue is still usable if another worker panicked while holding the lock
+                let next = queue.lock().unwrap_or_else(|e| e.into_inner()).next();
+                let (p, at) = match next {
+                    Some(n) => n,
+                    None => break,
+                };
+                let now = time::precise_time_ns();
+                if at > now {
+                    debug!("Waiting {}ms to request {}", (at - now) / 1_000_000, p.target.url);
+                    let wait = at - now;
+                    thread::sleep(Duration::new(wait / NS_PER_SEC, (wait % NS_PER_SEC) as u32));
+                }
+                tx.send((p.index, (*probe)(&p.target))).unwrap();
+            }
+        });
+    }
+    drop(tx);
+
+    let mut results: Vec<Option<Latency>> = (0..total).map(|_| None).collect();
+    for (index, result) in rx.iter() {
+        results[index] = result;
+    }
+    results
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use time;
+    use ratelimit::{HostLimiter, HostPolicy};
+    use target::Target;
+    use Latency;
+
+    fn ok(target: &Target) -> Option<Latency> {
+        Some(Latency {
+            url: target.url.clone(),
+            method: target.method.clone(),
+            started_at: "2016-01-01T00:00:00.000Z".to_string(),
+            latency_ms: 1,
+            status: Some(200),
+            ..Latency::default()
+        })
+    }
+
+    fn targets(urls: &[String]) -> Vec<Target> {
+        urls.iter().map(|u| Target::get(u.clone())).collect()
+    }
+
+    #[test]
+    /// Results should come back in the order the urls were given
+    fn preserves_order() {
+        let urls: Vec<String> = (0..20).map(|i| format!("http://host{}.example.com/", i % 3)).collect();
+        let results = probe_all(targets(&urls), 4, HostLimiter::new(HostPolicy::unlimited()), ok);
+        let got: Vec<String> = results.into_iter().map(|r| r.unwrap().url).collect();
+        assert_eq!(got, urls);
+    }
+
+    #[test]
+    /// Failed probes should stay in place as None
+    fn keeps_failures() {
+        let urls = vec!["http://a.example.com/".to_string(), "http://b.example.com/".to_string()];
+        let results = probe_all(targets(&urls), 2, HostLimiter::new(HostPolicy::unlimited()), |t: &Target| {
+            if t.url.contains("a.") { None } else { ok(t) }
+        });
+        assert!(results[0].is_none());
+        assert!(results[1].is_some());
+    }
+
+    #[test]
+    /// Requests to the same host should be spaced out even with spare workers
+    fn limits_each_host() {
+        let urls: Vec<String> = (0..6).map(|i| format!("http://host{}.example.com/{}", i % 2, i)).collect();
+        let starts = Arc::new(Mutex::new(Vec::new()));
+        let recorded = starts.clone();
+        let policy = HostPolicy { rate: None, burst: 1, min_delay_ms: 30 };
+        probe_all(targets(&urls), 6, HostLimiter::new(policy), move |t: &Target| {
+            recorded.lock().unwrap().push((t.url.contains("host0"), time::precise_time_ns()));
+            ok(t)
+        });
+        let starts = starts.lock().unwrap();
+        for host in vec![true, false] {
+            let mut times: Vec<u64> = starts.iter().filter(|s| s.0 == host).map(|s| s.1).collect();
+            times.sort();
+            assert_eq!(times.len(), 3);
+            for pair in times.windows(2) {
+                assert!(pair[1] - pair[0] >= 29000000, "requests {}ns apart", pair[1] - pair[0]);
+            }
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! Reading probe targets from a sitemap (see http://www.sitemaps.org/protocol.html).
+//!
+//! A sitemap lists the pages of a site in ``<loc>`` elements:
+//!
+//! ```xml
+//! <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+//!   <url><loc>http://www.example.com/</loc></url>
+//!   <url><loc>http://www.example.com/about</loc></url>
+//! </urlset>
+//! ```
+//!
+//! A sitemap index lists further sitemaps in the same way, inside ``<sitemapindex>`` and
+//! ``<sitemap>`` elements. Either may be gzipped. Every sitemap an index lists is read, and the
+//! pages of all of them are returned together.
+
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+use rand::{self, Rng};
+use xml::{Event, Parser};
+
+use canonical;
+use target::Target;
+
+/// How many sitemap indexes deep ``load`` will follow. The protocol doesn't allow an index to list
+/// other indexes, but some sites do.
+pub const MAX_DEPTH: usize = 3;
+
+/// The largest sitemap, once uncompressed, which will be read. The protocol limits sitemaps to 50MB.
+pub const MAX_BYTES: u64 = 50 * 1024 * 1024;
+
+/// The urls listed by a single sitemap file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sitemap {
+    /// A ``<urlset>``, listing pages
+    Urls(Vec<String>),
+
+    /// A ``<sitemapindex>``, listing further sitemaps
+    Index(Vec<String>),
+}
+
+/// Which of the urls in a sitemap to probe
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampling {
+    /// Every url
+    All,
+
+    /// A random choice of up to this many urls
+    Random(usize),
+
+    /// The first url and every nth after it
+    Every(usize),
+}
+
+impl Sampling {
+    /// Parses a sampling given as ``random:N`` or ``every:N``
+    ///
+    /// # Failures
+    ///
+    /// Returns an Err if ``spec`` isn't in either form or N isn't a number greater than zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::sitemap::Sampling;
+    ///
+    /// assert_eq!(Sampling::from_spec("random:100").unwrap(), Sampling::Random(100));
+    /// assert_eq!(Sampling::from_spec("every:10").unwrap(), Sampling::Every(10));
+    /// assert!(Sampling::from_spec("every:0").is_err());
+    /// ```
+    pub fn from_spec(spec: &str) -> Result<Sampling, String> {
+        let mut parts = spec.splitn(2, ':');
+        match (parts.next(), parts.next().map(|n| n.parse::<usize>())) {
+            (Some("random"), Some(Ok(n))) if n > 0 => Ok(Sampling::Random(n)),
+            (Some("every"), Some(Ok(n))) if n > 0 => Ok(Sampling::Every(n)),
+            _ => Err(format!("expected random:N or every:N with N greater than zero, found '{}'", spec)),
+        }
+    }
+
+    /// Chooses items according to this sampling, keeping them in their original order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::sitemap::Sampling;
+    ///
+    /// assert_eq!(Sampling::Every(2).apply(vec![1, 2, 3, 4, 5]), vec![1, 3, 5]);
+    /// assert_eq!(Sampling::Random(3).apply(vec![1, 2, 3, 4, 5]).len(), 3);
+    /// ```
+    pub fn apply<T>(&self, items: Vec<T>) -> Vec<T> {
+        self.apply_with(items, &mut rand::thread_rng())
+    }
+
+    /// Chooses items as ``apply`` does, using ``rng`` for random sampling
+    pub fn apply_with<T, R: Rng>(&self, items: Vec<T>, rng: &mut R) -> Vec<T> {
+        match *self {
+            Sampling::All => items,
+            Sampling::Every(n) => items.into_iter().enumerate().filter(|&(i, _)| i % n == 0).map(|(_, t)| t).collect(),
+            Sampling::Random(n) if n >= items.len() => items,
+            Sampling::Random(n) => {
+                let mut chosen: Vec<usize> = (0..items.len()).collect();
+                rng.shuffle(&mut chosen);
+                chosen.truncate(n);
+                chosen.sort();
+                items.into_iter()
+                     .enumerate()
+                     .filter(|&(i, _)| chosen.binary_search(&i).is_ok())
+                     .map(|(_, t)| t)
+                     .collect()
+            }
+        }
+    }
+}
+
+/// True if ``input`` should be read as a sitemap: an http(s) url, or a file ending in ``.xml``
+/// or ``.xml.gz``
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::sitemap::is_sitemap;
+///
+/// assert!(is_sitemap("https://www.example.com/sitemap_index.xml"));
+/// assert!(is_sitemap("sitemap.xml.gz"));
+/// assert!(!is_sitemap("urls.txt"));
+/// ```
+pub fn is_sitemap(input: &str) -> bool {
+    let lower = input.to_lowercase();
+    is_remote(&lower) || lower.ends_with(".xml") || lower.ends_with(".xml.gz")
+}
+
+/// True if ``location`` is an http(s) url rather than a file name
+pub fn is_remote(location: &str) -> bool {
+    let lower = location.to_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Parses a sitemap or sitemap index, which may be gzipped, returning the urls it lists
+///
+/// # Failures
+///
+/// Returns an Err if the contents can't be decompressed, aren't valid XML, are larger than
+/// ``MAX_BYTES`` or aren't a ``<urlset>`` or ``<sitemapindex>``.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::sitemap::{parse, Sitemap};
+///
+/// let sitemap = parse(b"<urlset><url><loc>http://www.example.com/?a=1&amp;b=2</loc></url></urlset>");
+/// assert_eq!(sitemap.unwrap(), Sitemap::Urls(vec!["http://www.example.com/?a=1&b=2".to_string()]));
+///
+/// let index = parse(b"<sitemapindex><sitemap><loc>http://www.example.com/s1.xml</loc></sitemap></sitemapindex>");
+/// assert_eq!(index.unwrap(), Sitemap::Index(vec!["http://www.example.com/s1.xml".to_string()]));
+/// ```
+pub fn parse(contents: &[u8]) -> Result<Sitemap, String> {
+    let contents = try!(decompress(contents));
+    let text = try!(String::from_utf8(contents).map_err(|e| format!("not valid UTF-8 ({})", e.utf8_error())));
+    let mut parser = Parser::new();
+    parser.feed_str(text.trim_left_matches('\u{feff}'));
+
+    let mut root = None;
+    let mut locs = Vec::new();
+    let mut open: Vec<(Option<String>, String)> = Vec::new();
+    let mut loc: Option<String> = None;
+    for event in parser {
+        match try!(event.map_err(|e| format!("line {}, column {}: {}", e.line, e.col, e.msg))) {
+            Event::ElementStart(tag) => {
+                if open.is_empty() {
+                    if root.is_some() {
+                        return Err(format!("unexpected <{}> after the end of the document", tag.name));
+                    }
+                    root = Some(tag.name.clone());
+                } else if open.len() == 2 && tag.name == "loc" {
+                    // Only the <loc> of a <url> or <sitemap> counts, not e.g. <image:loc>
+                    loc = Some(String::new());
+                }
+                open.push((tag.prefix, tag.name));
+            }
+            Event::ElementEnd(tag) => {
+                if open.pop() != Some((tag.prefix.clone(), tag.name.clone())) {
+                    return Err(format!("unexpected </{}>", tag.name));
+                }
+                if open.len() == 2 {
+                    if let Some(l) = loc.take() {
+                        if !l.trim().is_empty() {
+                            locs.push(l.trim().to_string());
+                        }
+                    }
+                }
+            }
+            Event::Characters(ref s) | Event::CDATA(ref s) => {
+                if let Some(ref mut l) = loc {
+                    l.push_str(s);
+                }
+            }
+            _ => (),
+        }
+    }
+    if let Some(&(_, ref name)) = open.last() {
+        return Err(format!("<{}> isn't closed", name));
+    }
+    match root.as_ref().map(|r| &r[..]) {
+        Some("urlset") => Ok(Sitemap::Urls(locs)),
+        Some("sitemapindex") => Ok(Sitemap::Index(locs)),
+        Some(other) => Err(format!("expected <urlset> or <sitemapindex>, found <{}>", other)),
+        None => Err("no elements found, expected <urlset> or <sitemapindex>".to_string()),
+    }
+}
+
+/// Reads every page listed by the sitemap at ``location``, whose ``contents`` have already been
+/// read, following sitemap indexes using ``fetch`` to read each sitemap they list
+///
+/// Returns the urls of the pages along with a warning for each sitemap listed by an index which
+/// couldn't be read.
+///
+/// # Failures
+///
+/// Returns an Err if ``contents`` can't be parsed (see ``parse``).
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::sitemap::load;
+///
+/// let index = b"<sitemapindex><sitemap><loc>http://www.example.com/pages.xml</loc></sitemap>\
+///               <sitemap><loc>http://www.example.com/missing.xml</loc></sitemap></sitemapindex>";
+/// let (urls, warnings) = load("sitemap.xml", index, |location| match location {
+///     "http://www.example.com/pages.xml" =>
+///         Ok(b"<urlset><url><loc>http://www.example.com/</loc></url></urlset>".to_vec()),
+///     _ => Err("404 Not Found".to_string()),
+/// }).unwrap();
+/// assert_eq!(urls, vec!["http://www.example.com/"]);
+/// assert_eq!(warnings, vec!["http://www.example.com/missing.xml: 404 Not Found"]);
+/// ```
+pub fn load<F>(location: &str, contents: &[u8], fetch: F) -> Result<(Vec<String>, Vec<String>), String>
+    where F: Fn(&str) -> Result<Vec<u8>, String>
+{
+    let mut urls = Vec::new();
+    let mut warnings = Vec::new();
+    match try!(parse(contents)) {
+        Sitemap::Urls(locs) => urls = locs,
+        Sitemap::Index(sitemaps) => {
+            let mut seen = vec![location.to_string()];
+            follow(sitemaps, 1, &fetch, &mut seen, &mut urls, &mut warnings);
+        }
+    }
+    Ok((urls, warnings))
+}
+
+/// Reads each of the sitemaps listed by an index ``depth`` indexes deep, adding their pages to ``urls``
+fn follow<F>(sitemaps: Vec<String>, depth: usize, fetch: &F, seen: &mut Vec<String>, urls: &mut Vec<String>,
+             warnings: &mut Vec<String>)
+    where F: Fn(&str) -> Result<Vec<u8>, String>
+{
+    for sitemap in sitemaps {
+        if seen.contains(&sitemap) {
+            warnings.push(format!("{}: already read, skipping", sitemap));
+            continue;
+        }
+        seen.push(sitemap.clone());
+        debug!("Reading sitemap {}", sitemap);
+        match fetch(&sitemap).and_then(|contents| parse(&contents)) {
+            Ok(Sitemap::Urls(locs)) => urls.extend(locs),
+            Ok(Sitemap::Index(_)) if depth >= MAX_DEPTH => {
+                warnings.push(format!("{}: sitemap indexes nested more than {} deep, skipping", sitemap, MAX_DEPTH));
+            }
+            Ok(Sitemap::Index(more)) => follow(more, depth + 1, fetch, seen, urls, warnings),
+            Err(err) => warnings.push(format!("{}: {}", sitemap, err)),
+        }
+    }
+}
+
+/// Builds a target for a url listed in a sitemap
+///
+/// # Failures
+///
+/// Returns an Err if the url isn't a valid http(s) url.
+pub fn target(url: &str) -> Result<Target, String> {
+    match canonical::parse(url) {
+        Ok(c) => {
+            let mut target = Target::get(c.url);
+            target.scheme_guessed = c.scheme_guessed;
+            Ok(target)
+        }
+        Err(err) => Err(format!("'{}' is not a valid http(s) url: {}", url, err)),
+    }
+}
+
+/// Decompresses ``contents`` if they are gzipped, checking they aren't larger than ``MAX_BYTES``
+fn decompress(contents: &[u8]) -> Result<Vec<u8>, String> {
+    if !contents.starts_with(&[0x1f, 0x8b]) {
+        if contents.len() as u64 > MAX_BYTES {
+            return Err(format!("larger than {} bytes", MAX_BYTES));
+        }
+        return Ok(contents.to_vec());
+    }
+    let decoder = try!(GzDecoder::new(contents).map_err(|e| format!("not valid gzip ({})", e)));
+    let mut uncompressed = Vec::new();
+    try!(decoder.take(MAX_BYTES + 1).read_to_end(&mut uncompressed).map_err(|e| format!("not valid gzip ({})", e)));
+    if uncompressed.len() as u64 > MAX_BYTES {
+        return Err(format!("larger than {} bytes once uncompressed", MAX_BYTES));
+    }
+    Ok(uncompressed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use rand::{SeedableRng, XorShiftRng};
+
+    const URLSET: &'static str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9"
+        xmlns:image="http://www.google.com/schemas/sitemap-image/1.1">
+  <!-- the home page -->
+  <url>
+    <loc>
+      http://www.example.com/
+    </loc>
+    <lastmod>2016-01-01</lastmod>
+    <image:image><image:loc>http://www.example.com/logo.png</image:loc></image:image>
+  </url>
+  <url><loc><![CDATA[http://www.example.com/a?b=1&c=2]]></loc></url>
+  <url><loc></loc></url>
+</urlset>
+"#;
+
+    #[test]
+    /// Only the <loc> of each <url> should be taken, trimmed, with CDATA and comments handled
+    fn parses_urlset() {
+        let sitemap = parse(URLSET.as_bytes()).unwrap();
+        assert_eq!(sitemap, Sitemap::Urls(vec!["http://www.example.com/".to_string(),
+                                               "http://www.example.com/a?b=1&c=2".to_string()]));
+    }
+
+    #[test]
+    /// Gzipped sitemaps should be read the same as plain ones
+    fn parses_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(URLSET.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        assert_eq!(parse(&gzipped).unwrap(), parse(URLSET.as_bytes()).unwrap());
+        assert!(parse(&gzipped[..20]).unwrap_err().contains("gzip"));
+    }
+
+    #[test]
+    /// Documents which aren't sitemaps should be rejected
+    fn rejects_other_documents() {
+        assert!(parse(b"<html><body></body></html>").unwrap_err().contains("found <html>"));
+        assert!(parse(b"").is_err());
+        assert_eq!(parse(b"<urlset><url><loc>http://a/</url></urlset>").unwrap_err(), "unexpected </url>");
+        assert_eq!(parse(b"<urlset><url><loc>http://a/</loc></url>").unwrap_err(), "<urlset> isn't closed");
+        assert!(parse(b"<urlset><url><loc>http://a/&bogus;</loc></url></urlset>").unwrap_err().starts_with("line 1"));
+    }
+
+    #[test]
+    /// Indexes listing themselves, or nested too deeply, shouldn't be followed forever
+    fn index_loops() {
+        let index = b"<sitemapindex><sitemap><loc>http://www.example.com/index.xml</loc></sitemap></sitemapindex>";
+        let (urls, warnings) = load("http://www.example.com/index.xml", index, |_| Ok(index.to_vec())).unwrap();
+        assert!(urls.is_empty());
+        assert_eq!(warnings, vec!["http://www.example.com/index.xml: already read, skipping"]);
+
+        let nested = |location: &str| {
+            let next = format!("{}x", location);
+            Ok(format!("<sitemapindex><sitemap><loc>{}</loc></sitemap></sitemapindex>", next).into_bytes())
+        };
+        let (_, warnings) = load("index.xml", &nested("http://a/").unwrap(), nested).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("nested more than"), "{:?}", warnings);
+    }
+
+    #[test]
+    /// Random samples should be the requested size, keep the original order and vary with the rng
+    fn random_sampling() {
+        let items: Vec<usize> = (0..100).collect();
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let first = Sampling::Random(10).apply_with(items.clone(), &mut rng);
+        let second = Sampling::Random(10).apply_with(items.clone(), &mut rng);
+        assert_eq!(first.len(), 10);
+        assert!(first.windows(2).all(|w| w[0] < w[1]));
+        assert!(first != second);
+        assert_eq!(Sampling::Random(200).apply(items.clone()), items);
+        assert_eq!(Sampling::All.apply(items.clone()), items);
+    }
+}
@@ -0,0 +1,313 @@
+//! Summary statistics over latency samples, and tests for whether two sets of samples differ.
+
+use std::cmp::Ordering;
+
+use rand::Rng;
+
+/// Returns the ``p``th percentile (0 to 100) of an already sorted slice using the nearest-rank method
+///
+/// # Panics
+///
+/// Panics if ``sorted`` is empty.
+///
+/// # Examples
+///
+/// ```
+/// let samples = vec![10, 20, 30, 40, 50, 60, 70, 80, 90, 100];
+/// assert_eq!(httplatency::stats::percentile(&samples, 50.0), 50);
+/// assert_eq!(httplatency::stats::percentile(&samples, 90.0), 90);
+/// assert_eq!(httplatency::stats::percentile(&samples, 100.0), 100);
+/// ```
+pub fn percentile(sorted: &[i64], p: f64) -> i64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    let index = if rank == 0 { 0 } else { rank - 1 };
+    sorted[if index >= sorted.len() { sorted.len() - 1 } else { index }]
+}
+
+/// The median of ``values`` by the nearest-rank method, as ``percentile`` finds it, or None
+/// if there are none
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(httplatency::stats::median(vec![30, 10, 20, 40]), Some(20));
+/// assert_eq!(httplatency::stats::median(vec![0.5, 0.25, 2.0]), Some(0.5));
+/// assert_eq!(httplatency::stats::median::<i64>(vec![]), None);
+/// ```
+pub fn median<T: PartialOrd + Copy>(mut values: Vec<T>) -> Option<T> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    Some(values[(values.len() + 1) / 2 - 1])
+}
+
+/// The distribution of a set of latency samples, in milliseconds
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialEq)]
+pub struct Summary {
+    /// The number of samples
+    pub count: usize,
+    pub min: i64,
+    pub mean: f64,
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+    pub p999: i64,
+    pub max: i64,
+}
+
+impl Summary {
+    /// Summarises the given samples, which needn't be sorted. Returns None if there are none.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let summary = httplatency::stats::Summary::from_samples(&[3, 1, 2]).unwrap();
+    /// assert_eq!(summary.min, 1);
+    /// assert_eq!(summary.p50, 2);
+    /// assert_eq!(summary.max, 3);
+    /// ```
+    pub fn from_samples(samples: &[i64]) -> Option<Summary> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort();
+        let total: i64 = sorted.iter().fold(0, |acc, s| acc + s);
+        Some(Summary {
+            count: sorted.len(),
+            min: sorted[0],
+            mean: total as f64 / sorted.len() as f64,
+            p50: percentile(&sorted, 50.0),
+            p90: percentile(&sorted, 90.0),
+            p99: percentile(&sorted, 99.0),
+            p999: percentile(&sorted, 99.9),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// The result of a two-sided Mann-Whitney U test
+#[derive(RustcEncodable, Debug, Clone, PartialEq)]
+pub struct MannWhitney {
+    /// The smaller of the two U statistics
+    pub u: f64,
+
+    /// The standardised test statistic, positive when the second sample tends to be larger
+    pub z: f64,
+
+    /// The probability of a difference at least this large if both samples came from the same
+    /// distribution, from the normal approximation with tie and continuity corrections
+    pub p_value: f64,
+}
+
+/// Tests whether two samples come from the same distribution using the Mann-Whitney U test
+///
+/// The test compares ranks rather than values so, unlike comparing means, it isn't thrown off by
+/// a few very slow outliers. Returns None if either sample is empty.
+///
+/// # Examples
+///
+/// ```
+/// let before = vec![101, 99, 103, 98, 102, 100, 97, 104];
+/// let after = vec![121, 119, 125, 118, 122, 120, 117, 124];
+/// let test = httplatency::stats::mann_whitney(&before, &after).unwrap();
+/// assert!(test.p_value < 0.01);
+/// ```
+pub fn mann_whitney(a: &[i64], b: &[i64]) -> Option<MannWhitney> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let n1 = a.len() as f64;
+    let n2 = b.len() as f64;
+    let mut all: Vec<(i64, bool)> = a.iter().map(|&x| (x, true))
+                                     .chain(b.iter().map(|&x| (x, false)))
+                                     .collect();
+    all.sort_by(|x, y| x.0.cmp(&y.0));
+
+    // Rank the combined samples, giving tied values the average of the ranks they span
+    let mut rank_sum_a = 0.0;
+    let mut ties = 0.0;
+    let mut i = 0;
+    while i < all.len() {
+        let mut j = i;
+        while j + 1 < all.len() && all[j + 1].0 == all[i].0 {
+            j += 1;
+        }
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        let t = (j - i + 1) as f64;
+        ties += t * t * t - t;
+        rank_sum_a += rank * all[i..j + 1].iter().filter(|x| x.1).count() as f64;
+        i = j + 1;
+    }
+
+    let u1 = rank_sum_a - n1 * (n1 + 1.0) / 2.0;
+    let u2 = n1 * n2 - u1;
+    let n = n1 + n2;
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+    let (z, p_value) = if variance <= 0.0 {
+        // Every value is the same
+        (0.0, 1.0)
+    } else {
+        let diff = u2 - n1 * n2 / 2.0;
+        let corrected = if diff.abs() > 0.5 { diff.abs() - 0.5 } else { 0.0 };
+        let z = corrected / variance.sqrt() * diff.signum();
+        (z, erfc(z.abs() / 2f64.sqrt()))
+    };
+    Some(MannWhitney { u: if u1 < u2 { u1 } else { u2 }, z: z, p_value: if p_value > 1.0 { 1.0 } else { p_value } })
+}
+
+/// The complementary error function, accurate to about 1.2e-7 (Numerical Recipes' ``erfcc``)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t * (-z * z - 1.26551223 + t * (1.00002368 + t * (0.37409196 + t * (0.09678418 +
+            t * (-0.18628806 + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587 +
+            t * (-0.82215223 + t * 0.17087277))))))))).exp();
+    if x >= 0.0 { r } else { 2.0 - r }
+}
+
+/// A confidence interval
+#[derive(RustcEncodable, Debug, Clone, PartialEq)]
+pub struct Interval {
+    pub low: f64,
+    pub high: f64,
+}
+
+impl Interval {
+    /// True if the interval doesn't include zero
+    pub fn excludes_zero(&self) -> bool {
+        self.low > 0.0 || self.high < 0.0
+    }
+}
+
+/// Estimates a confidence interval for the change in a statistic from sample ``a`` to sample ``b``
+///
+/// Both samples are repeatedly resampled with replacement and ``statistic`` (given a sorted
+/// sample) is compared between them; the central ``confidence`` fraction of the ``iterations``
+/// differences found is returned. Returns None if either sample is empty.
+///
+/// # Examples
+///
+/// ```
+/// extern crate rand;
+/// extern crate httplatency;
+/// # fn main() {
+/// use httplatency::stats::{bootstrap_ci, percentile};
+///
+/// let mut rng = rand::thread_rng();
+/// let median = |s: &[i64]| percentile(s, 50.0) as f64;
+/// let ci = bootstrap_ci(&[10, 10, 10], &[15, 15, 15], &median, 100, 0.95, &mut rng).unwrap();
+/// assert_eq!((ci.low, ci.high), (5.0, 5.0));
+/// # }
+/// ```
+pub fn bootstrap_ci<F, R>(a: &[i64], b: &[i64], statistic: &F, iterations: usize, confidence: f64,
+                          rng: &mut R) -> Option<Interval>
+    where F: Fn(&[i64]) -> f64, R: Rng
+{
+    if a.is_empty() || b.is_empty() || iterations == 0 {
+        return None;
+    }
+    let mut diffs: Vec<f64> = (0..iterations).map(|_| {
+        let before = statistic(&resample(a, rng));
+        statistic(&resample(b, rng)) - before
+    }).collect();
+    diffs.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    let tail = (1.0 - confidence) / 2.0;
+    let index = |q: f64| {
+        let i = (q * iterations as f64).floor() as usize;
+        if i >= iterations { iterations - 1 } else { i }
+    };
+    Some(Interval { low: diffs[index(tail)], high: diffs[index(1.0 - tail)] })
+}
+
+/// Draws a sorted sample of the same size as ``sample``, with replacement
+fn resample<R: Rng>(sample: &[i64], rng: &mut R) -> Vec<i64> {
+    let mut drawn: Vec<i64> = (0..sample.len()).map(|_| sample[rng.gen_range(0, sample.len())]).collect();
+    drawn.sort();
+    drawn
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    /// A single sample is every percentile
+    fn percentile_single_sample() {
+        assert_eq!(percentile(&[7], 0.0), 7);
+        assert_eq!(percentile(&[7], 50.0), 7);
+        assert_eq!(percentile(&[7], 99.9), 7);
+    }
+
+    #[test]
+    /// High percentiles of small sample sets should be the maximum
+    fn percentile_tail_of_small_set() {
+        let samples: Vec<i64> = (1..101).collect();
+        assert_eq!(percentile(&samples, 99.0), 99);
+        assert_eq!(percentile(&samples, 99.9), 100);
+    }
+
+    #[test]
+    /// No samples means no summary
+    fn summary_of_nothing() {
+        assert!(Summary::from_samples(&[]).is_none());
+    }
+
+    #[test]
+    /// Mean shouldn't be truncated to whole milliseconds
+    fn summary_mean() {
+        let summary = Summary::from_samples(&[1, 2]).unwrap();
+        assert_eq!(summary.mean, 1.5);
+        assert_eq!(summary.count, 2);
+    }
+
+    #[test]
+    /// Samples drawn from the same values shouldn't be reported as different
+    fn mann_whitney_same() {
+        let a = vec![10, 20, 30, 40, 50];
+        let test = mann_whitney(&a, &a).unwrap();
+        assert_eq!(test.z, 0.0);
+        assert!(test.p_value > 0.99);
+    }
+
+    #[test]
+    /// Completely separated samples should give U of zero and the direction of change in z
+    fn mann_whitney_separated() {
+        let a: Vec<i64> = (1..11).collect();
+        let b: Vec<i64> = (11..21).collect();
+        let up = mann_whitney(&a, &b).unwrap();
+        assert_eq!(up.u, 0.0);
+        assert!(up.z > 3.0);
+        assert!(up.p_value < 0.001);
+        assert!(mann_whitney(&b, &a).unwrap().z < -3.0);
+    }
+
+    #[test]
+    /// Matches the normal approximation for a small worked example
+    fn mann_whitney_worked_example() {
+        let test = mann_whitney(&[19, 22, 16, 29, 24], &[20, 11, 17, 12]).unwrap();
+        assert_eq!(test.u, 3.0);
+        assert!((test.p_value - 0.111).abs() < 0.001, "p was {}", test.p_value);
+    }
+
+    #[test]
+    /// Identical values everywhere can't be told apart
+    fn mann_whitney_all_tied() {
+        assert_eq!(mann_whitney(&[5, 5], &[5, 5, 5]).unwrap().p_value, 1.0);
+        assert!(mann_whitney(&[], &[5]).is_none());
+    }
+
+    #[test]
+    /// A consistent shift should give an interval around that shift
+    fn bootstrap_shift() {
+        use rand::{SeedableRng, XorShiftRng};
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let a: Vec<i64> = (100..200).collect();
+        let b: Vec<i64> = (150..250).collect();
+        let median = |s: &[i64]| percentile(s, 50.0) as f64;
+        let ci = bootstrap_ci(&a, &b, &median, 500, 0.95, &mut rng).unwrap();
+        assert!(ci.low > 20.0 && ci.high < 80.0, "interval was {:?}", ci);
+        assert!(ci.excludes_zero());
+    }
+}
@@ -0,0 +1,335 @@
+//! The description of a single url to probe and how to request it.
+
+use throughput::SamplingPolicy;
+use upload::UploadPolicy;
+
+/// The HTTP methods a target may use
+pub const METHODS: [&'static str; 7] = ["GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "PATCH"];
+
+/// A url to probe along with how to request it and what to expect back
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    /// The fully qualified url to request
+    pub url: String,
+
+    /// The HTTP method to use, in upper case
+    pub method: String,
+
+    /// Extra headers to send, which replace any default header of the same name
+    pub headers: Vec<(String, String)>,
+
+    /// The body to send with the request, if any
+    pub body: Option<String>,
+
+    /// How long in milliseconds to wait for the server to respond, if limited
+    pub timeout_ms: Option<u64>,
+
+    /// The status codes the response is expected to have. Any status is accepted if empty.
+    pub expect_status: Vec<u16>,
+
+    /// The longest the request is expected to take, in milliseconds
+    pub max_latency_ms: Option<u64>,
+
+    /// The name of the group this target belongs to, if any
+    pub group: Option<String>,
+
+    /// True if ``url`` was given without a scheme, so ``http`` was assumed (see ``SchemePolicy``)
+    pub scheme_guessed: bool,
+
+    /// A url to request instead if ``url`` can't be reached at all
+    pub fallback_url: Option<String>,
+
+    /// The template ``url`` was expanded from, if it was given as one (see ``template``)
+    pub template: Option<String>,
+
+    /// Addresses to connect to instead of looking up host names, as (host, port, address)
+    pub resolve: Vec<(String, u16, String)>,
+
+    /// True if the server's TLS certificate shouldn't be verified
+    pub insecure: bool,
+
+    /// The page which linked to ``url``, if it was found by crawling (see ``crawl``)
+    pub linked_from: Option<String>,
+
+    /// How much of the response to wait for, if not the default of ``Measure::Headers``
+    pub measure: Option<Measure>,
+
+    /// How to sample the download of the body, if it is read in full and its throughput over
+    /// time is wanted (see ``throughput``)
+    pub throughput: Option<SamplingPolicy>,
+
+    /// The body to upload and how, if the target measures an upload (see ``upload``). Takes the
+    /// place of ``body``.
+    pub upload: Option<UploadPolicy>,
+}
+
+impl Target {
+    /// A plain GET request for ``url`` with no expectations
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let target = httplatency::target::Target::get("http://www.google.com/".to_string());
+    /// assert_eq!(target.method, "GET");
+    /// ```
+    pub fn get(url: String) -> Target {
+        Target {
+            url: url,
+            method: "GET".to_string(),
+            headers: Vec::new(),
+            body: None,
+            timeout_ms: None,
+            expect_status: Vec::new(),
+            max_latency_ms: None,
+            group: None,
+            scheme_guessed: false,
+            fallback_url: None,
+            template: None,
+            resolve: Vec::new(),
+            insecure: false,
+            linked_from: None,
+            measure: None,
+            throughput: None,
+            upload: None,
+        }
+    }
+
+    /// Lists the ways a response with ``status`` taking ``latency_ms`` fails this target's
+    /// expectations. Returns an empty list if every expectation is met.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut target = httplatency::target::Target::get("http://www.google.com/".to_string());
+    /// target.expect_status = vec![200];
+    /// assert!(target.check(100, Some(200)).is_empty());
+    /// assert_eq!(target.check(100, Some(404)), vec!["expected status 200 but got 404".to_string()]);
+    /// ```
+    pub fn check(&self, latency_ms: i64, status: Option<u16>) -> Vec<String> {
+        let mut failures = Vec::new();
+        if !self.expect_status.is_empty() {
+            match status {
+                Some(s) if self.expect_status.contains(&s) => (),
+                Some(s) => failures.push(format!("expected status {} but got {}", join(&self.expect_status), s)),
+                None => failures.push(format!("expected status {} but got no response", join(&self.expect_status))),
+            }
+        }
+        if let Some(max) = self.max_latency_ms {
+            if latency_ms > max as i64 {
+                failures.push(format!("expected a response within {}ms but took {}ms", max, latency_ms));
+            }
+        }
+        failures
+    }
+}
+
+/// How much of a response to wait for before a request is complete, and so what its latency measures
+#[derive(RustcEncodable, Debug, Clone, Copy, PartialEq)]
+pub enum Measure {
+    /// Until the response headers have been received, so the body isn't read at all
+    Headers,
+
+    /// Until the first byte of the body has been received, or the body is found to be empty
+    FirstByte,
+
+    /// Until the whole body has been read, counting its bytes
+    FullBody,
+}
+
+impl Measure {
+    /// Looks up a measure by the name used on the command line and in config files
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::target::Measure;
+    ///
+    /// assert_eq!(Measure::from_name("full-body"), Some(Measure::FullBody));
+    /// assert_eq!(Measure::from_name("body"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<Measure> {
+        match name {
+            "headers" => Some(Measure::Headers),
+            "first-byte" => Some(Measure::FirstByte),
+            "full-body" => Some(Measure::FullBody),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Measure {
+    fn default() -> Measure {
+        Measure::Headers
+    }
+}
+
+/// How to request a target whose url was given without a scheme
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SchemePolicy {
+    /// Request it over http
+    Http,
+
+    /// Request it over https
+    Https,
+
+    /// Request it over https, falling back to http if https can't be reached
+    HttpsThenHttp,
+
+    /// Request it over both https and http, reporting each
+    Both,
+}
+
+impl SchemePolicy {
+    /// Looks up a policy by the name used on the command line
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::target::SchemePolicy;
+    ///
+    /// assert_eq!(SchemePolicy::from_name("https-then-http"), Some(SchemePolicy::HttpsThenHttp));
+    /// assert_eq!(SchemePolicy::from_name("gopher"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<SchemePolicy> {
+        match name {
+            "http" => Some(SchemePolicy::Http),
+            "https" => Some(SchemePolicy::Https),
+            "https-then-http" => Some(SchemePolicy::HttpsThenHttp),
+            "both" => Some(SchemePolicy::Both),
+            _ => None,
+        }
+    }
+
+    /// Returns the targets to request for ``target``. Targets whose scheme wasn't guessed are
+    /// returned unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use httplatency::target::{SchemePolicy, Target};
+    ///
+    /// let mut target = Target::get("http://www.example.com/".to_string());
+    /// target.scheme_guessed = true;
+    /// let targets = SchemePolicy::Both.apply(target);
+    /// assert_eq!(targets[0].url, "https://www.example.com/");
+    /// assert_eq!(targets[1].url, "http://www.example.com/");
+    /// ```
+    pub fn apply(&self, target: Target) -> Vec<Target> {
+        if !target.scheme_guessed || !target.url.starts_with("http://") {
+            return vec![target];
+        }
+        let mut https = target.clone();
+        https.url = format!("https://{}", &target.url["http://".len()..]);
+        match *self {
+            SchemePolicy::Http => vec![target],
+            SchemePolicy::Https => vec![https],
+            SchemePolicy::HttpsThenHttp => {
+                https.fallback_url = Some(target.url);
+                vec![https]
+            }
+            SchemePolicy::Both => vec![https, target],
+        }
+    }
+}
+
+fn join(statuses: &[u16]) -> String {
+    let parts: Vec<String> = statuses.iter().map(|s| s.to_string()).collect();
+    parts.join(" or ")
+}
+
+/// Checks ``method`` is one of the supported HTTP methods, returning it in upper case
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(httplatency::target::parse_method("head"), Ok("HEAD".to_string()));
+/// assert!(httplatency::target::parse_method("FETCH").is_err());
+/// ```
+pub fn parse_method(method: &str) -> Result<String, String> {
+    let upper = method.to_uppercase();
+    if METHODS.contains(&&upper[..]) {
+        Ok(upper)
+    } else {
+        Err(format!("unknown method '{}', expected one of {}", method, METHODS.join(", ")))
+    }
+}
+
+/// Parses a duration such as ``500ms``, ``2s`` or ``1m`` into milliseconds
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::target::parse_duration_ms;
+/// assert_eq!(parse_duration_ms("500ms"), Ok(500));
+/// assert_eq!(parse_duration_ms("2s"), Ok(2000));
+/// assert_eq!(parse_duration_ms("1.5s"), Ok(1500));
+/// assert!(parse_duration_ms("2").is_err());
+/// ```
+pub fn parse_duration_ms(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, scale) = if s.ends_with("ms") {
+        (&s[..s.len() - 2], 1.0)
+    } else if s.ends_with('s') {
+        (&s[..s.len() - 1], 1000.0)
+    } else if s.ends_with('m') {
+        (&s[..s.len() - 1], 60000.0)
+    } else {
+        return Err(format!("'{}' needs a unit, e.g. \"500ms\", \"2s\" or \"1m\"", s));
+    };
+    match number.trim().parse::<f64>() {
+        Ok(n) if n >= 0.0 => Ok((n * scale).round() as u64),
+        _ => Err(format!("'{}' is not a valid duration, e.g. \"500ms\", \"2s\" or \"1m\"", s)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    /// Every unmet expectation should be reported
+    fn check_reports_all_failures() {
+        let mut target = Target::get("http://www.google.com/".to_string());
+        target.expect_status = vec![200, 204];
+        target.max_latency_ms = Some(50);
+        let failures = target.check(80, None);
+        assert_eq!(failures.len(), 2);
+        assert!(failures[0].contains("200 or 204"));
+    }
+
+    #[test]
+    /// A target with no expectations accepts anything
+    fn check_no_expectations() {
+        let target = Target::get("http://www.google.com/".to_string());
+        assert!(target.check(100000, Some(500)).is_empty());
+    }
+
+    #[test]
+    /// Urls given with a scheme should be left alone whatever the policy
+    fn scheme_policy_keeps_given_schemes() {
+        let target = Target::get("http://www.example.com/".to_string());
+        for policy in vec![SchemePolicy::Https, SchemePolicy::HttpsThenHttp, SchemePolicy::Both] {
+            assert_eq!(policy.apply(target.clone()), vec![target.clone()]);
+        }
+    }
+
+    #[test]
+    /// Falling back should keep the http url to try
+    fn scheme_policy_fallback() {
+        let mut target = Target::get("http://www.example.com:8080/a".to_string());
+        target.scheme_guessed = true;
+        let targets = SchemePolicy::HttpsThenHttp.apply(target);
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].url, "https://www.example.com:8080/a");
+        assert_eq!(targets[0].fallback_url, Some("http://www.example.com:8080/a".to_string()));
+    }
+
+    #[test]
+    /// Durations shouldn't accept negative numbers or garbage
+    fn bad_durations() {
+        assert!(parse_duration_ms("-1s").is_err());
+        assert!(parse_duration_ms("fast").is_err());
+        assert!(parse_duration_ms("ms").is_err());
+        assert_eq!(parse_duration_ms(" 3m "), Ok(180000));
+    }
+}
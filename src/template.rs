@@ -0,0 +1,197 @@
+//! Expansion of url templates, so that many similar urls can be written as one.
+//!
+//! A template may contain:
+//!
+//!  * ranges, e.g. ``shard{1..16}.example.com``. A leading zero pads every number to the width of
+//!    the first, so ``{01..16}`` gives ``01``, ``02`` .. ``16``. Ranges may count down.
+//!  * lists, e.g. ``{eu,us,ap}.api.example.com``
+//!  * environment variables, e.g. ``${API_HOST}/health``
+//!
+//! A template with several ranges or lists expands to every combination of them, in order.
+
+use std::env;
+
+/// The most urls a single template may expand to
+pub const MAX_EXPANSIONS: usize = 10000;
+
+/// True if ``s`` uses any template syntax, so would be changed by ``expand``
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::template::is_template;
+///
+/// assert!(is_template("shard{1..4}.example.com"));
+/// assert!(!is_template("www.example.com"));
+/// ```
+pub fn is_template(s: &str) -> bool {
+    s.contains('{') || s.contains('}')
+}
+
+/// Expands a template into every url it describes, taking variables from the environment
+///
+/// # Failures
+///
+/// Returns an Err if the template is malformed, uses an environment variable which isn't set, or
+/// would expand to more than ``MAX_EXPANSIONS`` urls.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::template::expand;
+///
+/// assert_eq!(expand("{eu,us}.example.com/v{1..2}").unwrap(),
+///            vec!["eu.example.com/v1", "eu.example.com/v2", "us.example.com/v1", "us.example.com/v2"]);
+/// assert_eq!(expand("www.example.com").unwrap(), vec!["www.example.com"]);
+/// assert!(expand("shard{1..}.example.com").is_err());
+/// ```
+pub fn expand(template: &str) -> Result<Vec<String>, String> {
+    expand_with(template, |name| env::var(name).ok())
+}
+
+/// Expands a template as ``expand`` does, looking up variables with ``lookup``
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::template::expand_with;
+///
+/// let urls = expand_with("${HOST}/health", |name| match name {
+///     "HOST" => Some("api.example.com".to_string()),
+///     _ => None,
+/// });
+/// assert_eq!(urls.unwrap(), vec!["api.example.com/health"]);
+/// ```
+pub fn expand_with<F>(template: &str, lookup: F) -> Result<Vec<String>, String>
+    where F: Fn(&str) -> Option<String>
+{
+    let mut expanded = vec![String::new()];
+    let mut rest = template;
+    while !rest.is_empty() {
+        if rest.starts_with("${") {
+            let end = try!(rest.find('}').ok_or("unclosed '${', expected ${NAME}".to_string()));
+            let name = &rest[2..end];
+            if !is_variable_name(name) {
+                return Err(format!("'{}' is not a valid environment variable name", name));
+            }
+            let value = try!(lookup(name).ok_or(format!("environment variable {} is not set", name)));
+            for url in expanded.iter_mut() {
+                url.push_str(&value);
+            }
+            rest = &rest[end + 1..];
+        } else if rest.starts_with('{') {
+            let end = try!(rest.find('}').ok_or("unclosed '{'".to_string()));
+            let choices = try!(choices(&rest[1..end]));
+            if expanded.len() * choices.len() > MAX_EXPANSIONS {
+                return Err(format!("expands to more than {} urls", MAX_EXPANSIONS));
+            }
+            expanded = expanded.iter()
+                               .flat_map(|url| choices.iter().map(move |c| format!("{}{}", url, c)))
+                               .collect();
+            rest = &rest[end + 1..];
+        } else if rest.starts_with('}') {
+            return Err("'}' without a matching '{'".to_string());
+        } else {
+            let end = rest.find(|c: char| c == '{' || c == '}' || c == '$').unwrap_or(rest.len());
+            // A '$' not followed by '{' is an ordinary character
+            let end = if end == 0 { 1 } else { end };
+            for url in expanded.iter_mut() {
+                url.push_str(&rest[..end]);
+            }
+            rest = &rest[end..];
+        }
+    }
+    Ok(expanded)
+}
+
+/// The values of a ``{..}`` section, given the text between the braces
+fn choices(body: &str) -> Result<Vec<String>, String> {
+    if body.contains(',') {
+        return Ok(body.split(',').map(|s| s.to_string()).collect());
+    }
+    let mut ends = body.splitn(2, "..");
+    match (ends.next(), ends.next()) {
+        (Some(first), Some(last)) => match (first.parse::<u64>(), last.parse::<u64>()) {
+            (Ok(from), Ok(to)) => {
+                let span = if from <= to { to - from } else { from - to };
+                if span >= MAX_EXPANSIONS as u64 {
+                    return Err(format!("range {{{}}} has more than {} values", body, MAX_EXPANSIONS));
+                }
+                let width = if first.len() > 1 && first.starts_with('0') { first.len() } else { 0 };
+                // Counting by offsets from ``from`` can't overflow, even for a range ending at u64::MAX
+                let numbers: Vec<u64> = if from <= to {
+                    (0..span + 1).map(|i| from + i).collect()
+                } else {
+                    (0..span + 1).map(|i| from - i).collect()
+                };
+                Ok(numbers.into_iter().map(|n| format!("{:01$}", n, width)).collect())
+            }
+            _ => Err(format!("range {{{}}} should be two whole numbers, e.g. {{1..16}}", body)),
+        },
+        _ => Err(format!("{{{}}} should be a range like {{1..16}} or a list like {{eu,us}}", body)),
+    }
+}
+
+/// True if ``name`` is usable as an environment variable name: letters, digits and underscores,
+/// not starting with a digit
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with(|c: char| c.is_digit(10)) &&
+    name.chars().all(|c| (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || (c >= '0' && c <= '9') || c == '_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Looks up the variables used by the tests
+    fn vars(name: &str) -> Option<String> {
+        match name {
+            "HOST" => Some("api.example.com".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    /// Ranges should count up or down and keep zero padding
+    fn ranges() {
+        assert_eq!(expand("a{1..3}").unwrap(), vec!["a1", "a2", "a3"]);
+        assert_eq!(expand("a{3..1}").unwrap(), vec!["a3", "a2", "a1"]);
+        assert_eq!(expand("a{08..10}").unwrap(), vec!["a08", "a09", "a10"]);
+        assert_eq!(expand("a{0..1}").unwrap(), vec!["a0", "a1"]);
+        assert_eq!(expand("a{5..5}").unwrap(), vec!["a5"]);
+        assert_eq!(expand("a{18446744073709551614..18446744073709551615}").unwrap(),
+                   vec!["a18446744073709551614", "a18446744073709551615"]);
+        assert_eq!(expand("a{18446744073709551615..18446744073709551614}").unwrap(),
+                   vec!["a18446744073709551615", "a18446744073709551614"]);
+    }
+
+    #[test]
+    /// Variables should be substituted, and ``$`` on its own left alone
+    fn variables() {
+        assert_eq!(expand_with("http://${HOST}/a${EMPTY}", vars).unwrap(), vec!["http://api.example.com/a"]);
+        assert_eq!(expand_with("http://a.example.com/$x?y=$", vars).unwrap(), vec!["http://a.example.com/$x?y=$"]);
+        assert_eq!(expand_with("${HOST}{1,2}", vars).unwrap(), vec!["api.example.com1", "api.example.com2"]);
+        assert_eq!(expand_with("${MISSING}", vars).unwrap_err(), "environment variable MISSING is not set");
+        assert!(expand_with("${1X}", vars).is_err());
+        assert!(expand_with("${HOST", vars).is_err());
+    }
+
+    #[test]
+    /// Malformed templates should be rejected rather than requested literally
+    fn malformed() {
+        assert!(expand("a{1..x}").is_err());
+        assert!(expand("a{x}").is_err());
+        assert!(expand("a{1,2").is_err());
+        assert!(expand("a}").is_err());
+        assert!(expand("a{1..100000}").is_err());
+        assert!(expand("{1..100}{1..100}{1..2}").is_err());
+    }
+
+    #[test]
+    /// Every combination should be produced, the first section varying slowest
+    fn combinations() {
+        let urls = expand("{a,b}{1..2}{x,}").unwrap();
+        assert_eq!(urls, vec!["a1x", "a1", "a2x", "a2", "b1x", "b1", "b2x", "b2"]);
+    }
+}
@@ -0,0 +1,176 @@
+//! Sampling how quickly a response body downloads over time.
+//!
+//! When a body is read in full, the time and size of every read from the connection is recorded.
+//! Those reads are turned into a ``Curve``: the bytes received by the end of each fixed interval
+//! and the throughput during it, how long the first ``first_bytes`` took to arrive, and every
+//! stall where no bytes arrived for longer than ``stall_ms``. A curve that starts slowly and
+//! speeds up shows TCP slow start; stalls partway through show buffering or a struggling server.
+
+/// How to sample the download of a response body
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplingPolicy {
+    /// The length of each sample, in milliseconds
+    pub interval_ms: u64,
+
+    /// How many bytes the time to first bytes is measured for
+    pub first_bytes: u64,
+
+    /// The longest gap between reads, in milliseconds, which isn't counted as a stall
+    pub stall_ms: u64,
+}
+
+impl Default for SamplingPolicy {
+    fn default() -> SamplingPolicy {
+        SamplingPolicy { interval_ms: 100, first_bytes: 100 * 1024, stall_ms: 500 }
+    }
+}
+
+/// The bytes received during one interval of a download
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// The end of the interval, in milliseconds since the request started
+    pub at_ms: i64,
+
+    /// The bytes of the body received by the end of the interval
+    pub bytes: u64,
+
+    /// The rate bytes were received at during the interval, in bytes per second
+    pub bps: f64,
+}
+
+/// A period when no bytes of the body were received
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialEq)]
+pub struct Stall {
+    /// When the last bytes before the stall arrived, in milliseconds since the request started
+    pub start_ms: i64,
+
+    /// How long the stall lasted, in milliseconds
+    pub duration_ms: i64,
+}
+
+/// How a response body downloaded over time
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialEq)]
+pub struct Curve {
+    /// The length of each sample, in milliseconds
+    pub interval_ms: u64,
+
+    /// The bytes received in each interval since the request started, the last of which may be shorter
+    pub samples: Vec<Sample>,
+
+    /// How many bytes ``time_to_first_bytes_ms`` is measured for
+    pub first_bytes: u64,
+
+    /// When ``first_bytes`` of the body had been received, in milliseconds since the request
+    /// started, if the body was that large
+    pub time_to_first_bytes_ms: Option<i64>,
+
+    /// Every gap of more than ``stall_ms`` between the response headers and the end of the body
+    /// when no bytes were received
+    pub stalls: Vec<Stall>,
+}
+
+/// Builds the curve of a download from the reads made
+///
+/// ``start_ns`` is when the request started and ``body_start_ns`` when the headers had been
+/// received, both from ``time::precise_time_ns``. Each read is the time it completed and how many
+/// bytes it returned, with the final empty read marking the end of the body.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::throughput::{analyse, SamplingPolicy};
+///
+/// let policy = SamplingPolicy { interval_ms: 100, first_bytes: 1000, stall_ms: 500 };
+/// let ms = 1_000_000;
+/// let curve = analyse(&policy, 0, 20 * ms, &[(50 * ms, 600), (150 * ms, 600), (800 * ms, 200), (800 * ms, 0)]);
+/// assert_eq!(curve.samples.len(), 8);
+/// assert_eq!(curve.samples[0].bytes, 600);
+/// assert_eq!(curve.samples[0].bps, 6000.0);
+/// assert_eq!(curve.time_to_first_bytes_ms, Some(150));
+/// assert_eq!(curve.stalls.len(), 1);
+/// assert_eq!((curve.stalls[0].start_ms, curve.stalls[0].duration_ms), (150, 650));
+/// ```
+pub fn analyse(policy: &SamplingPolicy, start_ns: u64, body_start_ns: u64, reads: &[(u64, usize)]) -> Curve {
+    let since_start = |ns: u64| (ns.saturating_sub(start_ns) / 1_000_000) as i64;
+    let interval = policy.interval_ms.max(1) as i64;
+    let end_ms = reads.last().map(|r| since_start(r.0)).unwrap_or(since_start(body_start_ns));
+
+    let mut samples = Vec::new();
+    let mut received = 0;
+    let mut next_read = reads.iter().peekable();
+    let mut at_ms = 0;
+    while at_ms < end_ms {
+        let previous = (at_ms, received);
+        at_ms = (at_ms + interval).min(end_ms);
+        while let Some(&&(ns, bytes)) = next_read.peek() {
+            if since_start(ns) > at_ms {
+                break;
+            }
+            received += bytes as u64;
+            next_read.next();
+        }
+        samples.push(Sample {
+            at_ms: at_ms,
+            bytes: received,
+            bps: (received - previous.1) as f64 * 1000.0 / (at_ms - previous.0) as f64,
+        });
+    }
+
+    let mut time_to_first_bytes_ms = None;
+    let mut stalls = Vec::new();
+    let mut received = 0;
+    let mut last_ms = since_start(body_start_ns);
+    for &(ns, bytes) in reads {
+        let read_ms = since_start(ns);
+        if read_ms - last_ms > policy.stall_ms as i64 {
+            stalls.push(Stall { start_ms: last_ms, duration_ms: read_ms - last_ms });
+        }
+        received += bytes as u64;
+        if time_to_first_bytes_ms.is_none() && received >= policy.first_bytes {
+            time_to_first_bytes_ms = Some(read_ms);
+        }
+        last_ms = read_ms;
+    }
+
+    Curve {
+        interval_ms: policy.interval_ms,
+        samples: samples,
+        first_bytes: policy.first_bytes,
+        time_to_first_bytes_ms: time_to_first_bytes_ms,
+        stalls: stalls,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    #[test]
+    /// An empty body should give no samples, and reads should land in the interval they end in
+    fn samples_intervals() {
+        let policy = SamplingPolicy { interval_ms: 50, first_bytes: 10, stall_ms: 1000 };
+        let empty = analyse(&policy, 5 * MS, 5 * MS, &[(5 * MS, 0)]);
+        assert!(empty.samples.is_empty());
+        assert_eq!(empty.time_to_first_bytes_ms, None);
+
+        let curve = analyse(&policy, 0, 10 * MS, &[(50 * MS, 5), (51 * MS, 5), (120 * MS, 10), (120 * MS, 0)]);
+        let points: Vec<(i64, u64)> = curve.samples.iter().map(|s| (s.at_ms, s.bytes)).collect();
+        assert_eq!(points, vec![(50, 5), (100, 10), (120, 20)]);
+        // The last interval is only 20ms long
+        assert_eq!(curve.samples[2].bps, 500.0);
+        assert_eq!(curve.time_to_first_bytes_ms, Some(51));
+        assert!(curve.stalls.is_empty());
+    }
+
+    #[test]
+    /// Waiting for the first byte of the body and for the connection to close both count as stalls
+    fn finds_stalls() {
+        let policy = SamplingPolicy { interval_ms: 100, first_bytes: 1, stall_ms: 100 };
+        let curve = analyse(&policy, 0, 0, &[(300 * MS, 1), (350 * MS, 1), (900 * MS, 0)]);
+        let stalls: Vec<(i64, i64)> = curve.stalls.iter().map(|s| (s.start_ms, s.duration_ms)).collect();
+        assert_eq!(stalls, vec![(0, 300), (350, 550)]);
+        assert_eq!(curve.samples.len(), 9);
+    }
+}
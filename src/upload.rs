@@ -0,0 +1,461 @@
+//! Measuring uploads: sending a request body of a chosen size and timing each part of the exchange.
+//!
+//! hyper sends a request's headers and body in one go and can't wait for ``100 Continue``, so
+//! uploads are written directly to the connection. The request headers are sent first. If the
+//! target asks for ``Expect: 100-continue``, the server then has ``CONTINUE_TIMEOUT_MS`` to say
+//! it will accept the body, as curl allows, before the body is sent anyway. The body is written
+//! either with a ``Content-Length`` or in chunks, and then the server's response is read. Each
+//! step is timed, so the time to ``100 Continue``, the upload itself and the server's response can
+//! be told apart.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use rand::{self, Rng};
+use time;
+
+use elapsed_ms;
+
+/// How long to wait for ``100 Continue`` before sending the body anyway, in milliseconds
+pub const CONTINUE_TIMEOUT_MS: u64 = 1000;
+
+/// The largest body which may be uploaded, in bytes. Bodies are held in memory while they're sent.
+pub const MAX_UPLOAD_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// The size of each chunk of a chunked body, in bytes
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// The longest response head which will be read, in bytes
+const MAX_HEAD: usize = 64 * 1024;
+
+/// Request headers which are set by the upload itself, so can't be given by a target
+const MANAGED_HEADERS: [&'static str; 5] = ["Host", "Connection", "Content-Length", "Transfer-Encoding", "Expect"];
+
+/// Where the body of an upload comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Payload {
+    /// This many random bytes, generated afresh for each request
+    Random(u64),
+
+    /// The contents of the file with this name
+    File(String),
+}
+
+/// What to upload and how
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadPolicy {
+    /// The request body to send
+    pub payload: Payload,
+
+    /// True to send the body with ``Transfer-Encoding: chunked`` rather than a ``Content-Length``
+    pub chunked: bool,
+
+    /// True to send ``Expect: 100-continue`` and wait for the server's go-ahead before the body
+    pub expect_continue: bool,
+}
+
+/// How long each part of an upload took
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialEq)]
+pub struct Upload {
+    /// The size of the request body, in bytes
+    pub bytes: u64,
+
+    /// How many bytes of the body were sent, which is none if the server refused it in response
+    /// to ``Expect: 100-continue``
+    pub bytes_sent: u64,
+
+    /// True if the body was sent in chunks
+    pub chunked: bool,
+
+    /// True if the server was asked to ``Expect: 100-continue``
+    pub expect_continue: bool,
+
+    /// The time in milliseconds from sending the request headers until ``100 Continue`` arrived,
+    /// if it was asked for and arrived before the body was sent
+    pub continue_ms: Option<i64>,
+
+    /// The time in milliseconds it took to send the body
+    pub upload_ms: i64,
+
+    /// The rate the body was sent at in bytes per second, if any time passed
+    pub upload_bps: Option<f64>,
+
+    /// The time in milliseconds from the body being sent until the response headers arrived
+    pub response_ms: i64,
+}
+
+/// A connection an upload can be sent over
+pub trait Connection: Read + Write {
+    /// Limits how long a read may wait, as ``TcpStream::set_read_timeout`` does
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+/// Parses a size in bytes such as ``512``, ``64KB``, ``10MB`` or ``1GB``, where each unit is 1024
+/// times the last
+///
+/// # Failures
+///
+/// Returns an Err if ``size`` isn't a size, or is larger than ``MAX_UPLOAD_BYTES``.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::upload::parse_size;
+///
+/// assert_eq!(parse_size("512"), Ok(512));
+/// assert_eq!(parse_size("64kb"), Ok(64 * 1024));
+/// assert_eq!(parse_size("10MB"), Ok(10 * 1024 * 1024));
+/// assert!(parse_size("ten").is_err());
+/// assert!(parse_size("2GB").is_err());
+/// assert!(parse_size("18014398509481984K").is_err());
+/// ```
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let digits = size.find(|c: char| !c.is_digit(10)).unwrap_or(size.len());
+    let multiplier = match &size[digits..].trim().to_uppercase()[..] {
+        "" | "B" => 1,
+        "KB" | "K" => 1024,
+        "MB" | "M" => 1024 * 1024,
+        "GB" | "G" => 1024 * 1024 * 1024,
+        _ => return Err(format!("'{}' is not a size, expected a number of bytes such as 512, 64KB or 10MB", size)),
+    };
+    match size[..digits].parse::<u64>() {
+        // A size too large to count in bytes is certainly too large to upload
+        Ok(n) => check_size(n.checked_mul(multiplier).unwrap_or(u64::max_value())),
+        Err(_) => Err(format!("'{}' is not a size, expected a number of bytes such as 512, 64KB or 10MB", size)),
+    }
+}
+
+/// Checks an upload of ``bytes`` isn't too large to send, returning its size
+///
+/// # Failures
+///
+/// Returns an Err if ``bytes`` is larger than ``MAX_UPLOAD_BYTES``.
+pub fn check_size(bytes: u64) -> Result<u64, String> {
+    if bytes > MAX_UPLOAD_BYTES {
+        Err(format!("uploads can be at most {} bytes (1GB)", MAX_UPLOAD_BYTES))
+    } else {
+        Ok(bytes)
+    }
+}
+
+/// Checks that a body can be made for ``payload`` without reading it
+///
+/// A file is checked once before any request is made, so a missing or oversized file is reported
+/// straight away rather than failing every request.
+///
+/// # Failures
+///
+/// Returns an Err if the payload's file doesn't exist or isn't a file, or the body would be larger
+/// than ``MAX_UPLOAD_BYTES``.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::upload::{check, Payload, MAX_UPLOAD_BYTES};
+///
+/// assert!(check(&Payload::Random(1000)).is_ok());
+/// assert!(check(&Payload::Random(MAX_UPLOAD_BYTES + 1)).is_err());
+/// assert!(check(&Payload::File("/no/such/file".to_string())).is_err());
+/// ```
+pub fn check(payload: &Payload) -> Result<(), String> {
+    match *payload {
+        Payload::Random(size) => check_size(size).map(|_| ()),
+        Payload::File(ref path) => {
+            let metadata = try!(fs::metadata(path).map_err(|e| format!("{}: {}", path, e)));
+            if !metadata.is_file() {
+                return Err(format!("{}: not a file", path));
+            }
+            check_size(metadata.len()).map(|_| ()).map_err(|e| format!("{}: {}", path, e))
+        }
+    }
+}
+
+/// The request body for ``payload``
+///
+/// # Failures
+///
+/// Returns an Err if the payload's file can't be read, or the body would be larger than
+/// ``MAX_UPLOAD_BYTES``.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::upload::{body, Payload, MAX_UPLOAD_BYTES};
+///
+/// assert_eq!(body(&Payload::Random(1000)).unwrap().len(), 1000);
+/// assert!(body(&Payload::Random(MAX_UPLOAD_BYTES + 1)).is_err());
+/// assert!(body(&Payload::File("/no/such/file".to_string())).is_err());
+/// ```
+pub fn body(payload: &Payload) -> Result<Vec<u8>, String> {
+    match *payload {
+        Payload::Random(size) => {
+            let mut body = vec![0; try!(check_size(size)) as usize];
+            rand::thread_rng().fill_bytes(&mut body);
+            Ok(body)
+        }
+        Payload::File(ref path) => {
+            let mut body = Vec::new();
+            let file = try!(File::open(path).map_err(|e| format!("{}: {}", path, e)));
+            // Reading one byte more than allowed tells a file which is too large from one which just fits
+            try!(file.take(MAX_UPLOAD_BYTES + 1).read_to_end(&mut body).map_err(|e| format!("{}: {}", path, e)));
+            try!(check_size(body.len() as u64).map_err(|e| format!("{}: {}", path, e)));
+            Ok(body)
+        }
+    }
+}
+
+/// The head of an upload request with a body of ``length`` bytes
+///
+/// ``host`` is the value of the ``Host`` header and ``path`` includes any query. A target's
+/// ``headers`` replace the defaults, except for those the upload sets itself such as
+/// ``Content-Length``.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::upload::{request_head, Payload, UploadPolicy};
+///
+/// let policy = UploadPolicy { payload: Payload::Random(5), chunked: true, expect_continue: true };
+/// let head = request_head("PUT", "www.example.com", "/files/a?v=1", &[], 5, &policy);
+/// assert!(head.starts_with("PUT /files/a?v=1 HTTP/1.1\r\nHost: www.example.com\r\n"));
+/// assert!(head.contains("\r\nTransfer-Encoding: chunked\r\nExpect: 100-continue\r\n"));
+/// assert!(head.ends_with("\r\n\r\n"));
+/// ```
+pub fn request_head(method: &str, host: &str, path: &str, headers: &[(String, String)], length: u64,
+                    policy: &UploadPolicy) -> String {
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", method, path, host);
+    if policy.chunked {
+        head.push_str("Transfer-Encoding: chunked\r\n");
+    } else {
+        head.push_str(&format!("Content-Length: {}\r\n", length));
+    }
+    if policy.expect_continue {
+        head.push_str("Expect: 100-continue\r\n");
+    }
+    if !headers.iter().any(|h| h.0.eq_ignore_ascii_case("Content-Type")) {
+        head.push_str("Content-Type: application/octet-stream\r\n");
+    }
+    for &(ref name, ref value) in headers.iter() {
+        if !MANAGED_HEADERS.iter().any(|m| m.eq_ignore_ascii_case(name)) {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    head.push_str("\r\n");
+    head
+}
+
+/// Sends an upload over ``connection`` and reads the response, returning its status and how long
+/// each part of the exchange took
+///
+/// ``head`` is the request head (see ``request_head``) and ``timeout`` how long reads may wait
+/// once the body has been sent. The response body is read to the end and discarded.
+///
+/// # Failures
+///
+/// Returns an Err if the connection fails or the response isn't valid HTTP.
+pub fn exchange<C: Connection>(connection: &mut C, head: &str, body: &[u8], policy: &UploadPolicy,
+                               timeout: Option<Duration>) -> io::Result<(u16, Upload)> {
+    let mut upload = Upload {
+        bytes: body.len() as u64,
+        bytes_sent: 0,
+        chunked: policy.chunked,
+        expect_continue: policy.expect_continue,
+        continue_ms: None,
+        upload_ms: 0,
+        upload_bps: None,
+        response_ms: 0,
+    };
+    try!(connection.write_all(head.as_bytes()));
+    try!(connection.flush());
+    let head_sent = time::precise_time_ns();
+
+    let mut refused = None;
+    if policy.expect_continue {
+        let wait = Duration::from_millis(CONTINUE_TIMEOUT_MS);
+        try!(connection.set_read_timeout(Some(timeout.map(|t| t.min(wait)).unwrap_or(wait))));
+        match read_head(connection) {
+            Ok(100) => upload.continue_ms = Some(elapsed_ms(head_sent, time::precise_time_ns())),
+            // The server answered without waiting for the body, so it won't be sent
+            Ok(status) if status >= 200 => refused = Some(status),
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => {
+                debug!("No 100 Continue within {}ms, sending the body anyway", CONTINUE_TIMEOUT_MS);
+            }
+            Err(e) => return Err(e),
+        }
+        try!(connection.set_read_timeout(timeout));
+    }
+    if let Some(status) = refused {
+        upload.response_ms = elapsed_ms(head_sent, time::precise_time_ns());
+        try!(io::copy(connection, &mut io::sink()));
+        return Ok((status, upload));
+    }
+
+    let body_start = time::precise_time_ns();
+    try!(write_body(connection, body, policy.chunked));
+    let body_sent = time::precise_time_ns();
+    upload.bytes_sent = body.len() as u64;
+    upload.upload_ms = elapsed_ms(body_start, body_sent);
+    let upload_ns = body_sent - body_start;
+    if upload_ns > 0 {
+        upload.upload_bps = Some(body.len() as f64 * 1_000_000_000.0 / upload_ns as f64);
+    }
+
+    // A late ``100 Continue``, or any other informational response, comes before the real one
+    let mut status = try!(read_head(connection));
+    while status < 200 {
+        status = try!(read_head(connection));
+    }
+    upload.response_ms = elapsed_ms(body_sent, time::precise_time_ns());
+    try!(io::copy(connection, &mut io::sink()));
+    Ok((status, upload))
+}
+
+/// Writes ``body``, in chunks if ``chunked``
+fn write_body<W: Write>(writer: &mut W, body: &[u8], chunked: bool) -> io::Result<()> {
+    if chunked {
+        for chunk in body.chunks(CHUNK_SIZE) {
+            try!(write!(writer, "{:x}\r\n", chunk.len()));
+            try!(writer.write_all(chunk));
+            try!(writer.write_all(b"\r\n"));
+        }
+        try!(writer.write_all(b"0\r\n\r\n"));
+    } else {
+        try!(writer.write_all(body));
+    }
+    writer.flush()
+}
+
+/// Reads a response head, returning its status code
+///
+/// The head is read a byte at a time so nothing after it is consumed.
+fn read_head<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut head = Vec::new();
+    let mut byte = [0; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if try!(reader.read(&mut byte)) == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before a complete response"));
+        }
+        head.push(byte[0]);
+        if head.len() > MAX_HEAD {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "response head is too long"));
+        }
+    }
+    let text = String::from_utf8_lossy(&head);
+    let status_line = text.lines().next().unwrap_or("");
+    let mut parts = status_line.split_whitespace();
+    match (parts.next(), parts.next().and_then(|s| s.parse::<u16>().ok())) {
+        (Some(version), Some(status)) if version.starts_with("HTTP/") => Ok(status),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid status line '{}'", status_line))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{self, Read, Write};
+    use std::time::Duration;
+
+    /// A server which replies with ``response``, after making the first ``silent_reads`` reads time out
+    struct Server {
+        response: io::Cursor<Vec<u8>>,
+        received: Vec<u8>,
+        silent_reads: usize,
+        timeouts: Vec<Option<Duration>>,
+    }
+
+    impl Server {
+        fn new(response: &str, silent_reads: usize) -> Server {
+            Server {
+                response: io::Cursor::new(response.as_bytes().to_vec()),
+                received: Vec::new(),
+                silent_reads: silent_reads,
+                timeouts: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Server {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.silent_reads > 0 {
+                self.silent_reads -= 1;
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "timed out"));
+            }
+            self.response.read(buf)
+        }
+    }
+
+    impl Write for Server {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.received.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Server {
+        fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+            self.timeouts.push(timeout);
+            Ok(())
+        }
+    }
+
+    fn policy(chunked: bool, expect_continue: bool) -> UploadPolicy {
+        UploadPolicy { payload: Payload::Random(0), chunked: chunked, expect_continue: expect_continue }
+    }
+
+    #[test]
+    /// The body should follow ``100 Continue``, with the final response read after it
+    fn waits_for_continue() {
+        let mut server = Server::new("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok", 0);
+        let (status, upload) = exchange(&mut server, "HEAD\r\n\r\n", b"abc", &policy(false, true),
+                                        Some(Duration::from_millis(5000))).unwrap();
+        assert_eq!(status, 201);
+        assert!(upload.continue_ms.is_some());
+        assert_eq!(upload.bytes_sent, 3);
+        assert_eq!(server.received, b"HEAD\r\n\r\nabc".to_vec());
+        assert_eq!(server.timeouts, vec![Some(Duration::from_millis(CONTINUE_TIMEOUT_MS)), Some(Duration::from_millis(5000))]);
+    }
+
+    #[test]
+    /// A server which doesn't send ``100 Continue`` in time should be sent the body anyway
+    fn sends_without_continue() {
+        let mut server = Server::new("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n\r\n", 1);
+        let (status, upload) = exchange(&mut server, "HEAD\r\n\r\n", &[7; 20000], &policy(true, true), None).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(upload.continue_ms, None);
+        let mut expected = b"HEAD\r\n\r\n4000\r\n".to_vec();
+        expected.extend_from_slice(&[7; 16384]);
+        expected.extend_from_slice(b"\r\ne20\r\n");
+        expected.extend_from_slice(&[7; 3616]);
+        expected.extend_from_slice(b"\r\n0\r\n\r\n");
+        assert_eq!(server.received, expected);
+    }
+
+    #[test]
+    /// A server which answers before the body is sent has refused it, so it shouldn't be sent
+    fn stops_when_refused() {
+        let mut server = Server::new("HTTP/1.1 413 Payload Too Large\r\n\r\n", 0);
+        let (status, upload) = exchange(&mut server, "HEAD\r\n\r\n", b"abc", &policy(false, true), None).unwrap();
+        assert_eq!(status, 413);
+        assert_eq!(upload.bytes_sent, 0);
+        assert_eq!(server.received, b"HEAD\r\n\r\n".to_vec());
+
+        let mut garbage = Server::new("SSH-2.0-OpenSSH\r\n\r\n", 0);
+        assert!(exchange(&mut garbage, "HEAD\r\n\r\n", b"abc", &policy(false, false), None).is_err());
+    }
+
+    #[test]
+    /// Managed headers can't be replaced, but others can
+    fn builds_head() {
+        let headers = vec![("Content-Length".to_string(), "1".to_string()),
+                           ("content-type".to_string(), "text/csv".to_string())];
+        let head = request_head("POST", "example.com:8080", "/", &headers, 3, &policy(false, false));
+        assert_eq!(head, "POST / HTTP/1.1\r\nHost: example.com:8080\r\nConnection: close\r\nContent-Length: 3\r\n\
+                          content-type: text/csv\r\n\r\n");
+    }
+}
@@ -0,0 +1,259 @@
+//! Reading probe targets from a plain text list of urls.
+//!
+//! Each line holds a url, optionally followed by settings for that url:
+//!
+//! ```text
+//! # The home page
+//! http://www.example.com/
+//!
+//! http://api.example.com/health method=HEAD timeout=2s expect=200,204 tag=api
+//! ```
+//!
+//! Blank lines and lines starting with ``#`` are ignored, as is anything after a ``#`` which
+//! follows the url. Lines that can't be used are skipped with a warning giving the line number.
+//!
+//! The url may be a template (see ``template``), such as ``shard{1..16}.example.com``, in which
+//! case the line gives a target for every url it expands to, each with the same settings.
+
+use canonical;
+use target::{parse_duration_ms, parse_method, Target};
+use template;
+
+/// The settings which may follow a url
+const OPTIONS: [&'static str; 6] = ["method", "timeout", "expect", "max_latency", "header", "tag"];
+
+/// A target read from a url list, or a line which couldn't be used
+///
+/// A line whose url is a template gives an entry for every url it expands to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The line number, starting from 1
+    pub line: usize,
+
+    /// The text of the line, with any invalid UTF-8 replaced
+    pub text: String,
+
+    /// The target described by the line, or why the line couldn't be used
+    pub target: Result<Target, String>,
+}
+
+/// Parses the targets from the contents of a url list
+///
+/// Returns the targets along with a warning for each line which was skipped.
+///
+/// # Examples
+///
+/// ```
+/// let (targets, warnings) = httplatency::urllist::parse(b"# urls\nwww.google.com method=HEAD\n\nftp://x\n");
+/// assert_eq!(targets.len(), 1);
+/// assert_eq!(targets[0].method, "HEAD");
+/// assert_eq!(warnings.len(), 1);
+/// assert!(warnings[0].starts_with("line 4: 'ftp://x' is not a valid http(s) url: unsupported scheme"));
+/// ```
+pub fn parse(contents: &[u8]) -> (Vec<Target>, Vec<String>) {
+    let mut targets = Vec::new();
+    let mut warnings = Vec::new();
+    for entry in entries(contents) {
+        match entry.target {
+            Ok(target) => targets.push(target),
+            Err(err) => warnings.push(format!("line {}: {}", entry.line, err)),
+        }
+    }
+    (targets, warnings)
+}
+
+/// Parses every line of a url list which isn't blank or a comment, keeping lines which can't be used
+///
+/// # Examples
+///
+/// ```
+/// let entries = httplatency::urllist::entries(b"# urls\n\nwww.google.com\nwww{1..2}.example.com\n");
+/// assert_eq!(entries.len(), 3);
+/// assert_eq!(entries[0].line, 3);
+/// assert_eq!(entries[0].text, "www.google.com");
+/// assert_eq!(entries[0].target.as_ref().unwrap().url, "http://www.google.com/");
+/// assert_eq!(entries[2].line, 4);
+/// assert_eq!(entries[2].target.as_ref().unwrap().url, "http://www2.example.com/");
+/// ```
+pub fn entries(contents: &[u8]) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (i, bytes) in contents.split(|&b| b == b'\n').enumerate() {
+        let targets = match String::from_utf8(bytes.to_vec()) {
+            Ok(line) => match parse_line(&line) {
+                Ok(targets) => targets.into_iter().map(Ok).collect(),
+                Err(err) => vec![Err(err)],
+            },
+            Err(err) => vec![Err(format!("not valid UTF-8 ({})", err.utf8_error()))],
+        };
+        let text = String::from_utf8_lossy(bytes).trim().to_string();
+        for target in targets {
+            entries.push(Entry { line: i + 1, text: text.clone(), target: target });
+        }
+    }
+    entries
+}
+
+/// Parses a single line of a url list, returning a target for each url the line's url expands to,
+/// or none for blank and comment lines
+///
+/// # Failures
+///
+/// Returns an Err if the url is an invalid template, any url isn't a valid http(s) url, or a
+/// setting is invalid.
+///
+/// # Examples
+///
+/// ```
+/// use httplatency::urllist::parse_line;
+///
+/// let targets = parse_line("www.google.com timeout=2s expect=200 tag=search").unwrap();
+/// assert_eq!(targets[0].url, "http://www.google.com/");
+/// assert_eq!(targets[0].timeout_ms, Some(2000));
+/// assert_eq!(targets[0].expect_status, vec![200]);
+/// assert_eq!(targets[0].group, Some("search".to_string()));
+///
+/// let targets = parse_line("{eu,us}.api.example.com method=HEAD").unwrap();
+/// assert_eq!(targets.len(), 2);
+/// assert_eq!(targets[1].url, "http://us.api.example.com/");
+/// assert_eq!(targets[1].template, Some("{eu,us}.api.example.com".to_string()));
+///
+/// assert!(parse_line("  # a comment").unwrap().is_empty());
+/// assert!(parse_line("www.google.com retries=2").is_err());
+/// ```
+pub fn parse_line(line: &str) -> Result<Vec<Target>, String> {
+    let mut words = line.split_whitespace().take_while(|w| !w.starts_with('#'));
+    let url = match words.next() {
+        Some(u) => u.to_string(),
+        None => return Ok(Vec::new()),
+    };
+    let mut target = Target::get(String::new());
+    let urls = if template::is_template(&url) {
+        target.template = Some(url.clone());
+        try!(template::expand(&url).map_err(|e| format!("'{}' is not a valid template: {}", url, e)))
+    } else {
+        vec![url]
+    };
+    let mut canonical_urls = Vec::new();
+    for u in urls {
+        match canonical::parse(&u) {
+            Ok(c) => canonical_urls.push(c),
+            Err(err) => return Err(format!("'{}' is not a valid http(s) url: {}", u, err)),
+        }
+    }
+    for word in words {
+        let mut parts = word.splitn(2, '=');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(n), Some(v)) => (n, v),
+            _ => return Err(format!("expected name=value after the url, found '{}'", word)),
+        };
+        let result = match name {
+            "method" => parse_method(value).map(|m| target.method = m),
+            "timeout" => parse_duration_ms(value).map(|d| target.timeout_ms = Some(d)),
+            "max_latency" => parse_duration_ms(value).map(|d| target.max_latency_ms = Some(d)),
+            "expect" => parse_statuses(value).map(|s| target.expect_status = s),
+            "header" => parse_header(value).map(|h| target.headers.push(h)),
+            "tag" => {
+                target.group = Some(value.to_string());
+                Ok(())
+            }
+            _ => Err(format!("unknown setting, expected one of {}", OPTIONS.join(", "))),
+        };
+        if let Err(err) = result {
+            return Err(format!("{}: {}", name, err));
+        }
+    }
+    Ok(canonical_urls.into_iter().map(|c| {
+        let mut t = target.clone();
+        t.url = c.url;
+        t.scheme_guessed = c.scheme_guessed;
+        t
+    }).collect())
+}
+
+/// Parses a comma separated list of status codes, e.g. ``200,204``
+fn parse_statuses(value: &str) -> Result<Vec<u16>, String> {
+    value.split(',').map(|s| match s.parse::<u16>() {
+        Ok(status) if status >= 100 && status <= 599 => Ok(status),
+        _ => Err(format!("expected status codes between 100 and 599, found '{}'", s)),
+    }).collect()
+}
+
+/// Parses a header given as ``Name:value``
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    let mut parts = value.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(v)) if !name.is_empty() => Ok((name.to_string(), v.to_string())),
+        _ => Err(format!("expected Name:value, found '{}'", value)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    /// Blank lines, comments and carriage returns shouldn't produce targets or warnings
+    fn ignores_blanks_and_comments() {
+        let (targets, warnings) = parse(b"\r\n# comment\nhttp://a.example.com/\r\n   \nhttp://b.example.com/ # note\n");
+        let urls: Vec<String> = targets.into_iter().map(|t| t.url).collect();
+        assert_eq!(urls, vec!["http://a.example.com/".to_string(), "http://b.example.com/".to_string()]);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    /// A line that isn't UTF-8 should be skipped without losing the lines around it
+    fn skips_invalid_utf8() {
+        let (targets, warnings) = parse(b"http://a.example.com/\nhttp://\xff\xfe.example.com/\nhttp://b.example.com/\n");
+        assert_eq!(targets.len(), 2);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("line 2: not valid UTF-8"), "{}", warnings[0]);
+    }
+
+    #[test]
+    /// Lines which can't be used should be kept with their line number, text and reason
+    fn entries_keep_rejected_lines() {
+        let entries = entries(b"http://a.example.com/\n\nftp://b.example.com/  \n");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].line, 3);
+        assert_eq!(entries[1].text, "ftp://b.example.com/");
+        assert!(entries[1].target.is_err());
+    }
+
+    #[test]
+    /// Every setting should be applied to the target
+    fn all_settings() {
+        let target = parse_line("https://api.example.com/ method=post timeout=1.5s expect=200,201 \
+                                 max_latency=300ms header=Accept:application/json tag=api")
+                         .unwrap().remove(0);
+        assert_eq!(target.method, "POST");
+        assert_eq!(target.timeout_ms, Some(1500));
+        assert_eq!(target.expect_status, vec![200, 201]);
+        assert_eq!(target.max_latency_ms, Some(300));
+        assert_eq!(target.headers, vec![("Accept".to_string(), "application/json".to_string())]);
+        assert_eq!(target.group, Some("api".to_string()));
+    }
+
+    #[test]
+    /// Bad settings should say which setting was wrong
+    fn bad_settings() {
+        assert!(parse_line("http://a.example.com/ expect=abc").unwrap_err().starts_with("expect:"));
+        assert!(parse_line("http://a.example.com/ timeout=2").unwrap_err().starts_with("timeout:"));
+        assert!(parse_line("http://a.example.com/ HEAD").unwrap_err().contains("name=value"));
+        assert!(parse_line("http://a.example.com/ header=Accept").unwrap_err().starts_with("header:"));
+    }
+
+    #[test]
+    /// Each url a template expands to should be an entry for the template's line
+    fn expands_templates() {
+        let entries = entries(b"shard{1..3}.example.com tag=shards\nhttp://a.example.com/\nx{1..}\n");
+        assert_eq!(entries.len(), 5);
+        let lines: Vec<usize> = entries.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![1, 1, 1, 2, 3]);
+        let target = entries[2].target.as_ref().unwrap();
+        assert_eq!(target.url, "http://shard3.example.com/");
+        assert_eq!(target.group, Some("shards".to_string()));
+        assert_eq!(target.template, Some("shard{1..3}.example.com".to_string()));
+        assert_eq!(entries[3].target.as_ref().unwrap().template, None);
+        assert!(entries[4].target.as_ref().unwrap_err().contains("not a valid template"));
+    }
+}